@@ -1,23 +1,246 @@
 pub mod compile;
+pub mod format;
+pub mod include;
+pub mod lint;
 pub mod parser;
+pub mod stdlib;
+pub mod typecheck;
 
+#[cfg(test)]
+mod format_tests;
+#[cfg(test)]
+mod include_tests;
+#[cfg(test)]
+mod lint_tests;
 #[cfg(test)]
 mod parser_tests;
+#[cfg(test)]
+mod stdlib_tests;
+#[cfg(test)]
+mod typecheck_tests;
 
+use std::collections::HashSet;
 use std::fmt;
+use std::sync::Arc;
+
+/// A position in a source file. `line` and `column` are 1-based; a zero line
+/// means the position is unknown (e.g. for synthesized nodes).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourcePos {
+    pub offset: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A half-open `[start, end)` range in a source file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub file: Option<Arc<str>>,
+    pub start: SourcePos,
+    pub end: SourcePos,
+}
+
+impl SourceSpan {
+    pub fn new(start: SourcePos, end: SourcePos) -> Self {
+        Self { file: None, start, end }
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.start.line == 0
+    }
+
+    /// The span covering both `self` and `other`, keeping `self`'s file.
+    pub fn to(&self, other: &SourceSpan) -> SourceSpan {
+        if self.is_unknown() {
+            return other.clone();
+        }
+        if other.is_unknown() {
+            return self.clone();
+        }
+        SourceSpan {
+            file: self.file.clone(),
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Module {
+    pub includes: Vec<Include>,
+    pub uses: Vec<Use>,
+    pub relations: Vec<RelationDecl>,
+    pub modes: Vec<ModeDecl>,
+    pub tables: Vec<TableDecl>,
+    pub costs: Vec<CostDecl>,
     pub state_vars: Vec<String>,
     pub facts: Vec<Term>,
+    pub initial_state: Option<InitialState>,
     pub global_stage: Stage,
     pub stages: Vec<Stage>,
+    pub span: SourceSpan,
+}
+
+/// An `Include "path"` (or `Import "path"`) directive. The path is relative
+/// to the including file.
+#[derive(Debug, Clone)]
+pub struct Include {
+    pub path: String,
+    pub span: SourceSpan,
+}
+
+/// A `Use lists` directive, bringing the rules of a standard library
+/// module into scope.
+#[derive(Debug, Clone)]
+pub struct Use {
+    pub module: String,
+    pub span: SourceSpan,
+}
+
+/// A `Relation name(Type, ...)` declaration, fixing a relation's arity and
+/// the type of each argument.
+#[derive(Debug, Clone)]
+pub struct RelationDecl {
+    pub name: String,
+    pub params: Vec<Type>,
+    pub span: SourceSpan,
+}
+
+/// A `Table reach` declaration, asking the solver to memoize the answers
+/// of a relation so that left-recursive rules for it terminate.
+#[derive(Debug, Clone)]
+pub struct TableDecl {
+    pub name: String,
+    pub span: SourceSpan,
+}
+
+/// A `Cost move(X, water) = 3` declaration: resolving a call of the
+/// relation whose arguments match the pattern adds the cost to the search
+/// state, which best-first search explores cheapest first. The cost is a
+/// non-negative integer or a variable of the pattern.
+#[derive(Debug, Clone)]
+pub struct CostDecl {
+    pub head: Term,
+    pub cost: Term,
+    pub span: SourceSpan,
+}
+
+/// An argument type in a relation declaration. Any capitalized name other
+/// than `Int`, `Real` and `Any` names a sort of symbolic values such as
+/// atoms and compound terms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Real,
+    Any,
+    Named(String),
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Type {
+        match name {
+            "Int" => Type::Int,
+            "Real" => Type::Real,
+            "Any" => Type::Any,
+            _ => Type::Named(name.to_string()),
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Real)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Real => write!(f, "Real"),
+            Type::Any => write!(f, "Any"),
+            Type::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for RelationDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params: Vec<String> = self.params.iter().map(Type::to_string).collect();
+        write!(f, "Relation {}({})", self.name, params.join(", "))
+    }
+}
+
+/// A `Mode name(+, -)` declaration: one way a relation is meant to be
+/// called. A relation may have several.
+#[derive(Debug, Clone)]
+pub struct ModeDecl {
+    pub name: String,
+    pub params: Vec<Mode>,
+    pub span: SourceSpan,
+}
+
+/// How a relation treats one argument in a mode declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// `+`: ground when the relation is called.
+    In,
+    /// `-`: determined by the relation once the inputs are.
+    Out,
+    /// `?`: either.
+    Any,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::In => write!(f, "+"),
+            Mode::Out => write!(f, "-"),
+            Mode::Any => write!(f, "?"),
+        }
+    }
+}
+
+/// Writes `name(+, -)`, the form modes are shown in by declarations and
+/// diagnostics.
+pub fn format_modes(name: &str, modes: &[Mode]) -> String {
+    let modes: Vec<String> = modes.iter().map(Mode::to_string).collect();
+    format!("{}({})", name, modes.join(", "))
+}
+
+impl fmt::Display for ModeDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Mode {}", format_modes(&self.name, &self.params))
+    }
+}
+
+/// A `Begin Initial State:` block: state variable declarations and the
+/// constraints that give them their starting values.
+#[derive(Debug, Clone)]
+pub struct InitialState {
+    pub state_vars: Vec<StateVarDecl>,
+    pub constraints: Vec<Term>,
+    pub span: SourceSpan,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateVarDecl {
+    pub name: String,
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone)]
 pub struct DrawDirective {
     pub condition: Option<Term>,
     pub draws: Vec<Term>,
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +249,7 @@ pub struct Stage {
     pub rules: Vec<Rule>,
     pub state_constraints: Vec<Term>,
     pub draw_directives: Vec<DrawDirective>,
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone)]
@@ -33,11 +257,113 @@ pub struct Rule {
     pub name: String,
     pub premise: Term,
     pub conclusion: Term,
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone)]
 pub struct Term {
     pub contents: TermContents,
+    pub span: SourceSpan,
+}
+
+impl Term {
+    /// Visits this term and all of its subterms, outermost first.
+    pub fn walk_mut(&mut self, f: &mut impl FnMut(&mut Term)) {
+        f(self);
+        if let TermContents::App { args, .. } = &mut self.contents {
+            for arg in args {
+                arg.walk_mut(f);
+            }
+        }
+    }
+}
+
+impl Module {
+    /// Every declared state variable, from `StateVar` facts and then the
+    /// initial state block, without duplicates.
+    pub fn declared_state_vars(&self) -> Vec<String> {
+        let mut names = self.state_vars.clone();
+        let block = self.initial_state.iter().flat_map(|i| &i.state_vars);
+        for decl in block {
+            if !names.contains(&decl.name) {
+                names.push(decl.name.clone());
+            }
+        }
+        names
+    }
+
+    /// Relations the module gives a meaning of its own, through a fact, a
+    /// rule or a `Relation` declaration.
+    pub fn defined_relations(&self) -> HashSet<String> {
+        let rules = std::iter::once(&self.global_stage).chain(&self.stages).flat_map(|stage| &stage.rules);
+        let heads = self.facts.iter().chain(rules.map(|rule| &rule.conclusion));
+        let names = heads.filter_map(|term| match &term.contents {
+            TermContents::App { rel: Rel::UserRel { name }, .. } => Some(name.clone()),
+            _ => None,
+        });
+        names.chain(self.relations.iter().map(|decl| decl.name.clone())).collect()
+    }
+
+    /// Tags every span in the module with the file it was parsed from.
+    pub fn set_file(&mut self, file: &str) {
+        let file: Arc<str> = file.into();
+        let tag = |span: &mut SourceSpan| span.file = Some(file.clone());
+        let tag_term = |t: &mut Term| t.walk_mut(&mut |t| tag(&mut t.span));
+
+        for include in &mut self.includes {
+            include.span.file = Some(file.clone());
+        }
+        for use_ in &mut self.uses {
+            use_.span.file = Some(file.clone());
+        }
+        for relation in &mut self.relations {
+            relation.span.file = Some(file.clone());
+        }
+        for mode in &mut self.modes {
+            mode.span.file = Some(file.clone());
+        }
+        for table in &mut self.tables {
+            table.span.file = Some(file.clone());
+        }
+        for cost in &mut self.costs {
+            tag_term(&mut cost.head);
+            tag_term(&mut cost.cost);
+            cost.span.file = Some(file.clone());
+        }
+        for fact in &mut self.facts {
+            tag_term(fact);
+        }
+        if let Some(initial) = &mut self.initial_state {
+            for decl in &mut initial.state_vars {
+                decl.span.file = Some(file.clone());
+            }
+            for constraint in &mut initial.constraints {
+                tag_term(constraint);
+            }
+            initial.span.file = Some(file.clone());
+        }
+        for stage in std::iter::once(&mut self.global_stage).chain(self.stages.iter_mut()) {
+            for rule in &mut stage.rules {
+                tag_term(&mut rule.premise);
+                tag_term(&mut rule.conclusion);
+                rule.span.file = Some(file.clone());
+            }
+            for constraint in &mut stage.state_constraints {
+                tag_term(constraint);
+            }
+            for directive in &mut stage.draw_directives {
+                if let Some(cond) = &mut directive.condition {
+                    tag_term(cond);
+                }
+                for draw in &mut directive.draws {
+                    tag_term(draw);
+                }
+                directive.span.file = Some(file.clone());
+            }
+            stage.span.file = Some(file.clone());
+        }
+        self.span.file = Some(file);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -49,15 +375,32 @@ pub enum TermContents {
     Float { val: f32 },
 }
 
+/// The relation a term applies. Infix arithmetic (`+`, `-`, `*`, `/`) is
+/// kept as an `SMTRel` until the compiler picks its int or real relation.
 #[derive(Debug, Clone)]
 pub enum Rel {
     SMTRel { name: String },
     UserRel { name: String },
 }
 
+impl Rel {
+    pub fn name(&self) -> &str {
+        match self {
+            Rel::SMTRel { name } | Rel::UserRel { name } => name,
+        }
+    }
+}
+
+pub fn is_arith_op(name: &str) -> bool {
+    matches!(name, "+" | "-" | "*" | "/")
+}
+
 impl fmt::Display for Term {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.contents {
+            TermContents::App { rel: Rel::SMTRel { name }, args } if is_arith_op(name) && args.len() == 2 => {
+                write!(f, "({} {} {})", args[0], name, args[1])
+            }
             TermContents::App { rel, args } => {
                 let rel_name = match rel {
                     Rel::SMTRel { name } => name,
@@ -135,6 +478,30 @@ impl fmt::Display for Stage {
 
 impl fmt::Display for Module {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for include in &self.includes {
+            writeln!(f, "Include \"{}\"", include.path)?;
+        }
+        for use_ in &self.uses {
+            writeln!(f, "Use {}", use_.module)?;
+        }
+        if !self.includes.is_empty() || !self.uses.is_empty() {
+            writeln!(f)?;
+        }
+        for relation in &self.relations {
+            writeln!(f, "{}", relation)?;
+        }
+        for mode in &self.modes {
+            writeln!(f, "{}", mode)?;
+        }
+        for table in &self.tables {
+            writeln!(f, "Table {}", table.name)?;
+        }
+        for cost in &self.costs {
+            writeln!(f, "Cost {} = {}", cost.head, cost.cost)?;
+        }
+        if !self.relations.is_empty() || !self.modes.is_empty() || !self.tables.is_empty() || !self.costs.is_empty() {
+            writeln!(f)?;
+        }
         writeln!(f, "Begin Facts:")?;
         for state_var in &self.state_vars {
             writeln!(f, "    StateVar {}", state_var)?;
@@ -145,6 +512,19 @@ impl fmt::Display for Module {
         writeln!(f, "End Facts")?;
         writeln!(f)?;
 
+        if let Some(initial) = &self.initial_state {
+            writeln!(f, "Begin Initial State:")?;
+            if !initial.state_vars.is_empty() {
+                let names: Vec<&str> = initial.state_vars.iter().map(|d| d.name.as_str()).collect();
+                writeln!(f, "    StateVars {}", names.join(", "))?;
+            }
+            for constraint in &initial.constraints {
+                writeln!(f, "    {}", constraint)?;
+            }
+            writeln!(f, "End Initial State")?;
+            writeln!(f)?;
+        }
+
         writeln!(f, "Begin Global:")?;
         for rule in &self.global_stage.rules {
             write!(f, "{}", rule)?;
@@ -1,31 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-use crate::ast::{Module, Rel, Rule, Stage, Term, TermContents};
-use crate::ast::parser::{self, Span};
+use crate::ast::{is_arith_op, CostDecl, Module, Rel, Rule, SourceSpan, Stage, Term, TermContents, Type};
+use crate::ast::{lint, stdlib, typecheck};
+use crate::diagnostic::{has_errors, Diagnostic};
+use crate::solver::{analysis, modes};
 use crate::solver::ir::{
-    Clause, DrawDirective as IrDrawDirective, Program, Prop, PropId, RelId, RelInfo, RelKind,
+    Clause, CostRule, DrawDirective as IrDrawDirective, Program, Prop, PropId, RelId, RelInfo, RelKind, RelMode,
     Stage as IrStage, SymbolId, Term as IRTerm, TermId, Var,
 };
-use nom::Finish;
-use nom::Parser;
-use nom::multi::many0;
-use nom::character::complete::multispace0;
-
-const STDLIB: &str = include_str!("../stdlib.l");
-
-fn parse_stdlib_rules() -> Vec<Rule> {
-    let input: Span = STDLIB.into();
-    let result = many0(|s| {
-        let (s, _) = multispace0(s)?;
-        parser::parse_rule(s)
-    }).parse(input);
-    match result.finish() {
-        Ok((_, rules)) => rules,
-        Err(_) => panic!("Failed to parse stdlib"),
+
+/// The errors that stopped a module, query or fact from compiling.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.diagnostics.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", errors.join("\n"))
     }
 }
 
-const SMT_INT_RELATIONS: &[(&str, usize)] = &[
+pub(crate) const SMT_INT_RELATIONS: &[(&str, usize)] = &[
     ("int_eq", 2),
     ("int_neq", 2),
     ("int_lt", 2),
@@ -38,7 +36,7 @@ const SMT_INT_RELATIONS: &[(&str, usize)] = &[
     ("int_div", 3),
 ];
 
-const SMT_REAL_RELATIONS: &[(&str, usize)] = &[
+pub(crate) const SMT_REAL_RELATIONS: &[(&str, usize)] = &[
     ("real_eq", 2),
     ("real_neq", 2),
     ("real_lt", 2),
@@ -56,6 +54,14 @@ pub struct Compiler<'a> {
     rel_map: HashMap<String, RelId>,
     var_map: HashMap<String, TermId>,
     next_var_map: HashMap<String, TermId>,
+    /// Relations computing infix arithmetic in the term being lowered,
+    /// waiting to be conjoined with the proposition that uses them.
+    arith_props: Vec<PropId>,
+    /// The family arithmetic falls back to when its operands don't say.
+    arith_hint: Option<RelKind>,
+    /// The arithmetic family of each fact variable initialised to a number.
+    var_kinds: HashMap<String, RelKind>,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Compiler<'a> {
@@ -70,12 +76,17 @@ impl<'a> Compiler<'a> {
             rel_map,
             var_map: HashMap::new(),
             next_var_map: HashMap::new(),
+            arith_props: Vec::new(),
+            arith_hint: None,
+            var_kinds: HashMap::new(),
+            diagnostics: Vec::new(),
         };
         compiler.register_builtin_relations();
         compiler
     }
 
     pub fn with_var_map(program: &'a mut Program, var_map: HashMap<String, TermId>) -> Self {
+        let var_kinds = program_var_kinds(program, &var_map);
         let mut rel_map = HashMap::new();
         for (id, rel_info) in program.rels.iter() {
             rel_map.insert(rel_info.name.clone(), id);
@@ -84,8 +95,12 @@ impl<'a> Compiler<'a> {
         let mut compiler = Self {
             program,
             rel_map,
+            var_kinds,
             var_map,
             next_var_map: HashMap::new(),
+            arith_props: Vec::new(),
+            arith_hint: None,
+            diagnostics: Vec::new(),
         };
         compiler.register_builtin_relations();
         compiler
@@ -95,6 +110,14 @@ impl<'a> Compiler<'a> {
         self.var_map
     }
 
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
     fn register_builtin_relations(&mut self) {
         for &(name, arity) in SMT_INT_RELATIONS {
             self.get_or_create_rel(name, arity, RelKind::SMTInt);
@@ -118,6 +141,8 @@ impl<'a> Compiler<'a> {
         id
     }
 
+    /// The variable named `name` in the current scope. Every `_` is a fresh
+    /// variable, so don't-care positions never constrain each other.
     fn get_or_create_var(&mut self, name: &str) -> TermId {
         if let Some(&id) = self.var_map.get(name) {
             return id;
@@ -127,7 +152,9 @@ impl<'a> Compiler<'a> {
         };
         let var_id = self.program.vars.alloc(var);
         let term_id = self.program.terms.alloc(IRTerm::Var(var_id));
-        self.var_map.insert(name.to_string(), term_id);
+        if name != "_" {
+            self.var_map.insert(name.to_string(), term_id);
+        }
         term_id
     }
 
@@ -162,23 +189,26 @@ impl<'a> Compiler<'a> {
         term_id
     }
 
-    fn lower_simple_term(&mut self, term: &Term) -> TermId {
-        match &term.contents {
-            TermContents::Var { name } => self.get_or_create_var(name),
-            TermContents::Atom { text } => {
-                let sym_id = self.intern_symbol(text);
-                self.alloc_term(IRTerm::Atom(sym_id))
-            }
-            TermContents::Int { val } => self.alloc_term(IRTerm::Int(*val)),
-            TermContents::Float { val } => self.alloc_term(IRTerm::Float(*val)),
-            TermContents::App { .. } => {
-                panic!("lower_simple_term called on App - use lower_term_to_prop instead")
-            }
-        }
-    }
-
     fn lower_term_arg(&mut self, term: &Term) -> TermId {
         match &term.contents {
+            TermContents::App { rel: Rel::SMTRel { name }, args } if is_arith_op(name) && args.len() == 2 => {
+                let kind = match self.arith_kind(term).or(self.arith_hint.clone()) {
+                    Some(kind) => kind,
+                    None if has_int_literal(term) => RelKind::SMTInt,
+                    None => {
+                        let op = arith_op_name(name);
+                        self.diagnostics.push(Diagnostic::error(
+                            format!(
+                                "cannot tell whether `{}` is Int or Real arithmetic here; use `int_{}` or `real_{}`",
+                                term, op, op
+                            ),
+                            term.span.clone(),
+                        ));
+                        RelKind::SMTInt
+                    }
+                };
+                self.lower_arith(name, args, kind, term)
+            }
             TermContents::App { rel, args } => {
                 let rel_name = match rel {
                     Rel::SMTRel { name } | Rel::UserRel { name } => name.as_str(),
@@ -198,8 +228,101 @@ impl<'a> Compiler<'a> {
                 let sym = self.intern_symbol(rel_name);
                 self.alloc_term(IRTerm::App { sym, args: lowered_args })
             }
-            _ => self.lower_simple_term(term),
+            TermContents::Var { name } => self.get_or_create_var(name),
+            TermContents::Atom { text } => {
+                let sym_id = self.intern_symbol(text);
+                self.alloc_term(IRTerm::Atom(sym_id))
+            }
+            TermContents::Int { val } => self.alloc_term(IRTerm::Int(*val)),
+            TermContents::Float { val } => self.alloc_term(IRTerm::Float(*val)),
+        }
+    }
+
+    /// Lowers `lhs op rhs` to a relation such as `real_add(lhs, rhs, T)`
+    /// on a fresh variable `T`, which stands for the expression.
+    fn lower_arith(&mut self, op: &str, args: &[Term], kind: RelKind, term: &Term) -> TermId {
+        let operands: Vec<TermId> = args
+            .iter()
+            .map(|arg| match &arg.contents {
+                TermContents::App { rel: Rel::SMTRel { name }, args } if is_arith_op(name) && args.len() == 2 => {
+                    self.lower_arith(name, args, kind.clone(), arg)
+                }
+                _ => self.lower_term_arg(arg),
+            })
+            .collect();
+
+        let family = if kind == RelKind::SMTReal { "real" } else { "int" };
+        let rel = self.get_or_create_rel(&format!("{}_{}", family, arith_op_name(op)), 3, kind);
+
+        let var = self.program.vars.alloc(Var {
+            name: "_arith".to_string(),
+        });
+        let result = self.alloc_term(IRTerm::Var(var));
+        let prop = self.alloc_prop(Prop::App {
+            rel,
+            args: vec![operands[0], operands[1], result],
+        });
+        if !term.span.is_unknown() {
+            self.program.prop_spans.insert(prop, term.span.clone());
+        }
+        self.arith_props.push(prop);
+        result
+    }
+
+    /// The arithmetic family `term` belongs to, if a float literal or a
+    /// variable initialised to a number says so, real winning over int.
+    /// Integer literals are no evidence, since `X + 1` is as likely to be
+    /// real as `X + 1.0`.
+    fn arith_kind(&self, term: &Term) -> Option<RelKind> {
+        match &term.contents {
+            TermContents::Float { .. } => Some(RelKind::SMTReal),
+            TermContents::Var { name } => self.var_kinds.get(name).cloned(),
+            TermContents::App { rel: Rel::UserRel { name }, args } if name == "next" && args.len() == 1 => {
+                self.arith_kind(&args[0])
+            }
+            TermContents::App { rel: Rel::SMTRel { name }, args } if is_arith_op(name) => {
+                let kinds: Vec<RelKind> = args.iter().filter_map(|a| self.arith_kind(a)).collect();
+                kinds.iter().find(|k| **k == RelKind::SMTReal).or(kinds.first()).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    /// Lowers `preserve(X, Y, ...)` to `next(X) = X & next(Y) = Y & ...`.
+    fn lower_preserve(&mut self, term: &Term, args: &[Term]) -> PropId {
+        let mut props = Vec::new();
+        for arg in args {
+            match &arg.contents {
+                TermContents::Var { name } => {
+                    let next = self.get_or_create_next_var(name);
+                    let current = self.get_or_create_var(name);
+                    props.push(self.alloc_prop(Prop::Eq(next, current)));
+                }
+                _ => self.diagnostics.push(Diagnostic::error(
+                    format!("`preserve` expects state variables, found `{}`", arg),
+                    arg.span.clone(),
+                )),
+            }
+        }
+        if props.is_empty() && args.is_empty() {
+            self.diagnostics.push(Diagnostic::error(
+                "`preserve` expects at least one state variable",
+                term.span.clone(),
+            ));
         }
+        props
+            .into_iter()
+            .reduce(|lhs, rhs| self.alloc_prop(Prop::And(lhs, rhs)))
+            .unwrap_or_else(|| self.alloc_prop(Prop::True))
+    }
+
+    /// Conjoins the pending arithmetic relations in front of `prop`.
+    fn with_arith(&mut self, prop: PropId) -> PropId {
+        let pending = std::mem::take(&mut self.arith_props);
+        pending
+            .into_iter()
+            .rev()
+            .fold(prop, |rest, arith| self.alloc_prop(Prop::And(arith, rest)))
     }
 
     fn is_smt_relation(&self, name: &str) -> bool {
@@ -216,6 +339,14 @@ impl<'a> Compiler<'a> {
     }
 
     fn lower_term_to_prop(&mut self, term: &Term) -> PropId {
+        let prop = self.lower_term_to_prop_inner(term);
+        if !term.span.is_unknown() {
+            self.program.prop_spans.insert(prop, term.span.clone());
+        }
+        prop
+    }
+
+    fn lower_term_to_prop_inner(&mut self, term: &Term) -> PropId {
         match &term.contents {
             TermContents::App { rel, args } => {
                 let rel_name = match rel {
@@ -223,6 +354,20 @@ impl<'a> Compiler<'a> {
                 };
 
                 match rel_name {
+                    "and" | "or" | "cond" | "not" if args.len() != connective_arity(rel_name) => {
+                        let arity = connective_arity(rel_name);
+                        self.diagnostics.push(Diagnostic::error(
+                            format!(
+                                "`{}` takes {} argument{} but {} were given",
+                                rel_name,
+                                arity,
+                                if arity == 1 { "" } else { "s" },
+                                args.len()
+                            ),
+                            term.span.clone(),
+                        ));
+                        self.alloc_prop(Prop::False)
+                    }
                     "and" => {
                         let lhs_prop = self.lower_term_to_prop(&args[0]);
                         let rhs_prop = self.lower_term_to_prop(&args[1]);
@@ -247,32 +392,38 @@ impl<'a> Compiler<'a> {
                         let not_prop = Prop::Not(prop);
                         self.alloc_prop(not_prop)
                     }
+                    "preserve" => self.lower_preserve(term, args),
                     "eq" if args.len() == 2 => {
+                        self.arith_hint = self.arith_kind(&args[0]).or_else(|| self.arith_kind(&args[1]));
                         let t1 = self.lower_term_arg(&args[0]);
                         let t2 = self.lower_term_arg(&args[1]);
-                        let eq_prop = Prop::Eq(t1, t2);
-                        self.alloc_prop(eq_prop)
+                        self.arith_hint = None;
+                        let eq_prop = self.alloc_prop(Prop::Eq(t1, t2));
+                        self.with_arith(eq_prop)
                     }
                     _ => {
+                        let kind = if self.is_smt_relation(rel_name) {
+                            self.smt_kind(rel_name)
+                        } else {
+                            RelKind::User
+                        };
+
+                        self.arith_hint = (kind != RelKind::User).then(|| kind.clone());
                         let lowered_args: Vec<TermId> = args
                             .iter()
                             .map(|a| self.lower_term_arg(a))
                             .collect();
+                        self.arith_hint = None;
 
                         let arity = lowered_args.len();
-                        let kind = if self.is_smt_relation(rel_name) {
-                            self.smt_kind(rel_name)
-                        } else {
-                            RelKind::User
-                        };
 
                         let rel_id = self.get_or_create_rel(rel_name, arity, kind);
 
-                        let app_prop = Prop::App {
+                        let app_prop = self.alloc_prop(Prop::App {
                             rel: rel_id,
                             args: lowered_args,
-                        };
-                        self.alloc_prop(app_prop)
+                        });
+                        self.with_arith(app_prop)
                     }
                 }
             }
@@ -286,11 +437,36 @@ impl<'a> Compiler<'a> {
         self.lower_term_to_prop(term)
     }
 
-    pub fn compile_fact(&mut self, term: &Term) -> PropId {
-        self.lower_term_to_prop(term)
+    /// Fails with the errors reported since `mark`, taking them out of the
+    /// compiler's diagnostics.
+    fn errors_since(&mut self, mark: usize) -> Result<(), CompileError> {
+        if !has_errors(&self.diagnostics[mark..]) {
+            return Ok(());
+        }
+        Err(CompileError {
+            diagnostics: self.diagnostics.drain(mark..).collect(),
+        })
+    }
+
+    /// The numeric types of the fact variables, for resolving the generic
+    /// arithmetic of a query or fact.
+    fn var_types(&self) -> HashMap<String, Type> {
+        self.var_kinds
+            .iter()
+            .map(|(name, kind)| (name.clone(), if *kind == RelKind::SMTReal { Type::Real } else { Type::Int }))
+            .collect()
     }
 
-    fn lower_conclusion(&mut self, conclusion: &Term) -> (RelId, Vec<TermId>) {
+    pub fn compile_fact(&mut self, term: &Term) -> Result<PropId, CompileError> {
+        let mark = self.diagnostics.len();
+        let mut term = term.clone();
+        self.diagnostics.extend(typecheck::resolve_term(&mut term, &self.var_types()));
+        let prop = self.lower_term_to_prop(&term);
+        self.errors_since(mark)?;
+        Ok(prop)
+    }
+
+    fn lower_conclusion(&mut self, conclusion: &Term) -> Option<(RelId, Vec<TermId>)> {
         match &conclusion.contents {
             TermContents::App { rel, args } => {
                 let rel_name = match rel {
@@ -305,15 +481,19 @@ impl<'a> Compiler<'a> {
                 let arity = lowered_args.len();
                 let rel_id = self.get_or_create_rel(rel_name, arity, RelKind::User);
 
-                (rel_id, lowered_args)
+                Some((rel_id, lowered_args))
             }
             _ => {
-                panic!("Non-rel-app in conclusion")
+                self.diagnostics.push(Diagnostic::error(
+                    format!("rule conclusion must be a relation, found `{}`", conclusion),
+                    conclusion.span.clone(),
+                ));
+                None
             }
         }
     }
 
-    fn lower_rule(&mut self, rule: &Rule, fact_var_map: &HashMap<String, TermId>) -> Clause {
+    fn lower_rule(&mut self, rule: &Rule, fact_var_map: &HashMap<String, TermId>) -> Option<Clause> {
         self.clear_scope();
 
         // Restore fact variables so rules can reference state variables from facts
@@ -322,14 +502,66 @@ impl<'a> Compiler<'a> {
         }
 
         let body = self.lower_term_to_prop(&rule.premise);
-        let (head_rel, head_args) = self.lower_conclusion(&rule.conclusion);
+        let (head_rel, head_args) = self.lower_conclusion(&rule.conclusion)?;
+        // Arithmetic in the conclusion is computed after the premise holds.
+        let arith = std::mem::take(&mut self.arith_props);
+        let body = arith.into_iter().fold(body, |body, prop| self.alloc_prop(Prop::And(body, prop)));
 
-        Clause {
+        Some(Clause {
             name: rule.name.clone(),
             head_rel,
             head_args,
             body,
-        }
+            span: rule.span.clone(),
+        })
+    }
+
+    fn lower_cost(&mut self, decl: &CostDecl) {
+        let TermContents::App { rel, args } = &decl.head.contents else {
+            unreachable!("the parser only accepts calls as cost patterns");
+        };
+        let name = match rel {
+            Rel::SMTRel { name } | Rel::UserRel { name } => name.as_str(),
+        };
+        let rel = match self.rel_map.get(name) {
+            _ if self.is_smt_relation(name) => {
+                self.diagnostics.push(Diagnostic::warning(
+                    format!("`{}` is a built-in relation and can't have a cost", name),
+                    decl.span.clone(),
+                ));
+                return;
+            }
+            Some(&rel) if self.program.rels.get(rel).arity == args.len() => rel,
+            Some(&rel) => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("`{}` takes {} arguments, but its cost pattern has {}", name, self.program.rels.get(rel).arity, args.len()),
+                    decl.head.span.clone(),
+                ));
+                return;
+            }
+            None => {
+                self.diagnostics.push(Diagnostic::warning(
+                    format!("`{}` has a cost but is never defined", name),
+                    decl.span.clone(),
+                ));
+                return;
+            }
+        };
+
+        self.clear_scope();
+        let args = args.iter().map(|a| self.lower_term_arg(a)).collect();
+        let cost = match &decl.cost.contents {
+            TermContents::Int { val } if *val >= 0 => self.alloc_term(IRTerm::Int(*val)),
+            TermContents::Var { name } if name != "_" && self.var_map.contains_key(name) => self.var_map[name],
+            _ => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("the cost of `{}` must be a non-negative integer or a variable of its pattern, found `{}`", name, decl.cost),
+                    decl.cost.span.clone(),
+                ));
+                return;
+            }
+        };
+        self.program.costs.entry(rel).or_default().push(CostRule { args, cost });
     }
 
     fn lower_draw_directive(
@@ -349,12 +581,16 @@ impl<'a> Compiler<'a> {
             .iter()
             .map(|t| self.lower_term_arg(t))
             .collect();
+        let arith = std::mem::take(&mut self.arith_props);
+        let condition = arith
+            .into_iter()
+            .fold(condition, |condition, prop| self.alloc_prop(Prop::And(condition, prop)));
 
         IrDrawDirective { condition, draws }
     }
 
     fn lower_stage(&mut self, stage: &Stage, fact_var_map: &HashMap<String, TermId>) -> IrStage {
-        let rules = stage.rules.iter().map(|r| self.lower_rule(r, fact_var_map)).collect();
+        let rules = stage.rules.iter().filter_map(|r| self.lower_rule(r, fact_var_map)).collect();
 
         self.var_map = fact_var_map.clone();
         self.next_var_map.clear();
@@ -377,41 +613,90 @@ impl<'a> Compiler<'a> {
             state_constraints,
             next_var_map,
             draw_directives,
+            span: stage.span.clone(),
         }
     }
 
-    pub fn compile_query(&mut self, term: &Term) -> (PropId, Vec<(String, TermId)>) {
-        let prop_id = self.lower_term_to_prop(term);
+    pub fn compile_query(&mut self, term: &Term) -> Result<(PropId, Vec<(String, TermId)>), CompileError> {
+        let mark = self.diagnostics.len();
+        let mut term = term.clone();
+        self.diagnostics.extend(typecheck::resolve_term(&mut term, &self.var_types()));
+        let prop_id = self.lower_term_to_prop(&term);
+        self.errors_since(mark)?;
         let query_vars: Vec<(String, TermId)> = self.var_map.iter()
             .map(|(k, v)| (k.clone(), *v))
             .collect();
-        (prop_id, query_vars)
+        Ok((prop_id, query_vars))
     }
 
-    pub fn compile_module(&mut self, module: &Module) {
-        self.program.state_vars = module.state_vars.clone();
+    /// Compiles `module` into the program. Everything that lowers cleanly is
+    /// kept even when other parts fail, so the error carries only the
+    /// errors; warnings stay in [`Compiler::diagnostics`].
+    pub fn compile_module(&mut self, module: &Module) -> Result<(), CompileError> {
+        let mut module = module.clone();
+        self.diagnostics.extend(typecheck::resolve_arithmetic(&mut module));
+        let module = &module;
+        let state_vars = module.declared_state_vars();
+        let initial_constraints = module.initial_state.iter().flat_map(|i| &i.constraints);
+        let facts: Vec<&Term> = module.facts.iter().chain(initial_constraints).collect();
+
+        self.program.state_vars = state_vars.clone();
+        self.var_kinds = fact_var_kinds(&facts);
+        self.diagnostics.extend(check_initial_values(module, &facts));
+        self.diagnostics.extend(typecheck::check_module(module));
+        self.diagnostics.extend(lint::lint_module(module));
+
+        for decl in &module.relations {
+            if !self.is_smt_relation(&decl.name) {
+                let rel_id = self.get_or_create_rel(&decl.name, decl.params.len(), RelKind::User);
+                self.program.declared_rels.insert(rel_id);
+            }
+        }
+
+        for decl in &module.modes {
+            if self.is_smt_relation(&decl.name) {
+                self.diagnostics.push(Diagnostic::warning(
+                    format!("`{}` is a built-in relation and accepts any mode", decl.name),
+                    decl.span.clone(),
+                ));
+                continue;
+            }
+            let rel = self.get_or_create_rel(&decl.name, decl.params.len(), RelKind::User);
+            self.program.rel_modes.push(RelMode {
+                rel,
+                modes: decl.params.clone(),
+                span: decl.span.clone(),
+            });
+        }
 
-        for fact_term in &module.facts {
+        for fact_term in facts {
             let fact_prop = self.lower_fact(fact_term);
-            self.program.facts.push(fact_prop);
+            self.program.add_fact(fact_prop);
         }
 
         let fact_var_map = self.var_map.clone();
         
-        for state_var_name in &module.state_vars {
+        for state_var_name in &state_vars {
             if let Some(&term_id) = fact_var_map.get(state_var_name) {
                 self.program.state_var_term_ids.insert(state_var_name.clone(), term_id);
             }
         }
 
         for rule in &module.global_stage.rules {
-            let clause = self.lower_rule(rule, &fact_var_map);
-            self.program.global_rules.push(clause);
+            if let Some(clause) = self.lower_rule(rule, &fact_var_map) {
+                self.program.add_rule(clause);
+            }
         }
 
-        for rule in parse_stdlib_rules() {
-            let clause = self.lower_rule(&rule, &fact_var_map);
-            self.program.global_rules.push(clause);
+        // Library rules see none of the program's fact variables, and the
+        // program's own definitions shadow theirs.
+        let defined = module.defined_relations();
+        let (library_rules, diagnostics) = stdlib::used_rules(&module.uses, &defined);
+        self.diagnostics.extend(diagnostics);
+        for rule in &library_rules {
+            if let Some(clause) = self.lower_rule(rule, &HashMap::new()) {
+                self.program.add_rule(clause);
+            }
         }
 
         for stage in &module.stages {
@@ -419,13 +704,336 @@ impl<'a> Compiler<'a> {
             self.program.stages.push(ir_stage);
         }
 
+        for decl in &module.tables {
+            match self.rel_map.get(&decl.name) {
+                _ if self.is_smt_relation(&decl.name) => self.diagnostics.push(Diagnostic::warning(
+                    format!("`{}` is a built-in relation and can't be tabled", decl.name),
+                    decl.span.clone(),
+                )),
+                Some(&rel) => match smt_relation_reached(self.program, rel) {
+                    // A table holds resolved arguments, not the constraints
+                    // left on them.
+                    Some(smt) => self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}` can't be tabled, since its rules reach the constraint `{}`",
+                            decl.name,
+                            self.program.rels.get(smt).name
+                        ),
+                        decl.span.clone(),
+                    )),
+                    None => {
+                        self.program.tabled_rels.insert(rel);
+                    }
+                },
+                None => self.diagnostics.push(Diagnostic::warning(
+                    format!("`{}` is tabled but never defined", decl.name),
+                    decl.span.clone(),
+                )),
+            }
+        }
+
+        for decl in &module.costs {
+            self.lower_cost(decl);
+        }
+        self.diagnostics.extend(check_literal_costs(module));
+
+        self.diagnostics.extend(analysis::check_program(self.program));
+        self.diagnostics.extend(modes::check_modes(self.program));
         self.var_map = fact_var_map;
+
+        if has_errors(&self.diagnostics) {
+            let diagnostics = self.diagnostics.iter().filter(|d| d.is_error()).cloned().collect();
+            return Err(CompileError { diagnostics });
+        }
+        Ok(())
+    }
+}
+
+fn connective_arity(name: &str) -> usize {
+    match name {
+        "not" => 1,
+        "cond" => 3,
+        _ => 2,
+    }
+}
+
+/// A built-in arithmetic relation that some chain of `rel`'s rules calls.
+fn smt_relation_reached(program: &Program, rel: RelId) -> Option<RelId> {
+    let clauses: Vec<&Clause> = program.global_rules.iter().chain(program.stages.iter().flat_map(|s| &s.rules)).collect();
+    let mut seen = HashSet::from([rel]);
+    let mut stack = vec![rel];
+    while let Some(rel) = stack.pop() {
+        let mut called = Vec::new();
+        for clause in clauses.iter().filter(|c| c.head_rel == rel) {
+            calls_in(program, clause.body, &mut called);
+        }
+        for next in called {
+            if program.rels.get(next).kind != RelKind::User {
+                return Some(next);
+            }
+            if seen.insert(next) {
+                stack.push(next);
+            }
+        }
     }
+    None
 }
 
+fn calls_in(program: &Program, prop: PropId, out: &mut Vec<RelId>) {
+    match program.props.get(prop) {
+        Prop::True | Prop::False | Prop::Eq(..) => {}
+        Prop::And(a, b) | Prop::Or(a, b) => {
+            calls_in(program, *a, out);
+            calls_in(program, *b, out);
+        }
+        Prop::Not(p) => calls_in(program, *p, out),
+        Prop::Cond(c, a, b) => {
+            calls_in(program, *c, out);
+            calls_in(program, *a, out);
+            calls_in(program, *b, out);
+        }
+        Prop::App { rel, .. } => out.push(*rel),
+    }
+}
+
+/// Variables that a fact such as `X = 0.0` initialises to a float.
+fn fact_var_kinds(facts: &[&Term]) -> HashMap<String, RelKind> {
+    let mut vars = HashMap::new();
+    for fact in facts {
+        if let TermContents::App { rel: Rel::UserRel { name }, args } = &fact.contents
+            && (name == "eq" || name == "real_eq" || name == "int_eq")
+            && args.len() == 2
+        {
+            match (&args[0].contents, &args[1].contents) {
+                (TermContents::Var { name }, TermContents::Float { .. })
+                | (TermContents::Float { .. }, TermContents::Var { name }) => {
+                    vars.insert(name.clone(), RelKind::SMTReal);
+                }
+                (TermContents::Var { name }, TermContents::Int { .. })
+                | (TermContents::Int { .. }, TermContents::Var { name }) => {
+                    vars.entry(name.clone()).or_insert(RelKind::SMTInt);
+                }
+                _ => {}
+            }
+        }
+    }
+    vars
+}
+
+/// The arithmetic family of each variable in `var_map` that one of the
+/// program's facts, such as `X = 0.0`, sets to a number.
+fn program_var_kinds(program: &Program, var_map: &HashMap<String, TermId>) -> HashMap<String, RelKind> {
+    let names: HashMap<TermId, &String> = var_map.iter().map(|(name, &id)| (id, name)).collect();
+    let mut vars = HashMap::new();
+    for &fact in &program.facts {
+        let (lhs, rhs) = match program.props.get(fact) {
+            Prop::Eq(lhs, rhs) => (*lhs, *rhs),
+            Prop::App { rel, args } if args.len() == 2 && program.rels.get(*rel).name.ends_with("_eq") => {
+                (args[0], args[1])
+            }
+            _ => continue,
+        };
+        for (var, value) in [(lhs, rhs), (rhs, lhs)] {
+            let Some(&name) = names.get(&var) else {
+                continue;
+            };
+            match program.terms.get(value) {
+                IRTerm::Float(_) => {
+                    vars.insert(name.clone(), RelKind::SMTReal);
+                }
+                IRTerm::Int(_) => {
+                    vars.entry(name.clone()).or_insert(RelKind::SMTInt);
+                }
+                _ => {}
+            }
+        }
+    }
+    vars
+}
+
+/// Whether `term` has an integer literal among its operands, which decides
+/// arithmetic that nothing else does.
+fn has_int_literal(term: &Term) -> bool {
+    match &term.contents {
+        TermContents::Int { .. } => true,
+        TermContents::App { rel: Rel::UserRel { name }, args } if name == "next" && args.len() == 1 => {
+            has_int_literal(&args[0])
+        }
+        TermContents::App { rel: Rel::SMTRel { name }, args } if is_arith_op(name) => args.iter().any(has_int_literal),
+        _ => false,
+    }
+}
+
+fn arith_op_name(op: &str) -> &'static str {
+    match op {
+        "+" => "add",
+        "-" => "sub",
+        "*" => "mul",
+        _ => "div",
+    }
+}
+
+/// Collects the equations `lhs = rhs` among the conjuncts of `term`.
+fn collect_equations<'t>(term: &'t Term, out: &mut Vec<(&'t Term, &'t Term, &'t Term)>) {
+    if let TermContents::App { rel: Rel::UserRel { name }, args } = &term.contents {
+        match name.as_str() {
+            "and" if args.len() == 2 => {
+                collect_equations(&args[0], out);
+                collect_equations(&args[1], out);
+            }
+            "eq" | "int_eq" | "real_eq" if args.len() == 2 => out.push((&args[0], &args[1], term)),
+            _ => {}
+        }
+    }
+}
+
+fn term_vars<'t>(term: &'t Term, out: &mut Vec<&'t str>) {
+    match &term.contents {
+        TermContents::Var { name } => out.push(name),
+        TermContents::App { args, .. } => args.iter().for_each(|a| term_vars(a, out)),
+        _ => {}
+    }
+}
+
+/// Checks that every state variable gets exactly one initial value from an
+/// equation with a ground side, or one built only from state variables that
+/// already have values.
+/// Errors for the facts and rule conclusions whose cost, by the first
+/// `Cost` declaration they match, is a literal other than a non-negative
+/// integer, such as `road(a, b, 2.5)` under `Cost road(_, _, D) = D`.
+fn check_literal_costs(module: &Module) -> Vec<Diagnostic> {
+    let stages = std::iter::once(&module.global_stage).chain(&module.stages);
+    let conclusions = stages.flat_map(|stage| &stage.rules).map(|rule| &rule.conclusion);
+    let mut diagnostics = Vec::new();
+    for head in module.facts.iter().chain(conclusions) {
+        let TermContents::App { rel: Rel::UserRel { name }, args } = &head.contents else {
+            continue;
+        };
+        let matched = module.costs.iter().find_map(|decl| {
+            let TermContents::App { rel, args: pattern } = &decl.head.contents else {
+                return None;
+            };
+            let mut bound = HashMap::new();
+            let matches = rel.name() == name
+                && pattern.len() == args.len()
+                && pattern.iter().zip(args).all(|(p, a)| match_cost_pattern(p, a, &mut bound));
+            matches.then_some((decl, bound))
+        });
+        let Some((decl, bound)) = matched else {
+            continue;
+        };
+        let TermContents::Var { name: cost_var } = &decl.cost.contents else {
+            continue;
+        };
+        let Some(cost) = bound.get(cost_var.as_str()) else {
+            continue;
+        };
+        match &cost.contents {
+            TermContents::Int { val } if *val >= 0 => {}
+            // Variables and arithmetic get their values as the search runs.
+            TermContents::Var { .. } | TermContents::App { rel: Rel::SMTRel { .. }, .. } => {}
+            _ => diagnostics.push(Diagnostic::error(
+                format!("the cost of `{}` must be a non-negative integer, found `{}`", head, cost),
+                cost.span.clone(),
+            )),
+        }
+    }
+    diagnostics
+}
+
+/// Whether `term` matches the cost pattern `pattern`, binding the pattern's
+/// variables in `bound`. A variable of `term` matches only a variable.
+fn match_cost_pattern<'a>(pattern: &'a Term, term: &'a Term, bound: &mut HashMap<&'a str, &'a Term>) -> bool {
+    match (&pattern.contents, &term.contents) {
+        (TermContents::Var { name }, _) if name == "_" => true,
+        (TermContents::Var { name }, _) => match bound.get(name.as_str()) {
+            Some(first) => first.to_string() == term.to_string(),
+            None => {
+                bound.insert(name, term);
+                true
+            }
+        },
+        (TermContents::App { rel, args }, TermContents::App { rel: term_rel, args: term_args }) => {
+            rel.name() == term_rel.name()
+                && args.len() == term_args.len()
+                && args.iter().zip(term_args).all(|(p, a)| match_cost_pattern(p, a, bound))
+        }
+        (TermContents::App { .. }, _) | (_, TermContents::Var { .. }) => false,
+        _ => pattern.to_string() == term.to_string(),
+    }
+}
+
+fn check_initial_values(module: &Module, facts: &[&Term]) -> Vec<Diagnostic> {
+    // `StateVar` facts carry no span of their own.
+    let mut module_start = module.span.clone();
+    module_start.end = module_start.start;
+    let mut declared: Vec<(&str, SourceSpan)> = module
+        .state_vars
+        .iter()
+        .map(|name| (name.as_str(), module_start.clone()))
+        .collect();
+    for decl in module.initial_state.iter().flat_map(|i| &i.state_vars) {
+        if !declared.iter().any(|(name, _)| *name == decl.name) {
+            declared.push((&decl.name, decl.span.clone()));
+        }
+    }
+
+    let mut equations = Vec::new();
+    for fact in facts {
+        collect_equations(fact, &mut equations);
+    }
+
+    let mut diagnostics = Vec::new();
+    let mut values: HashMap<&str, SourceSpan> = HashMap::new();
+    loop {
+        let before = equations.len();
+        equations.retain(|&(lhs, rhs, equation)| {
+            for (side, other) in [(lhs, rhs), (rhs, lhs)] {
+                let TermContents::Var { name } = &side.contents else {
+                    continue;
+                };
+                if !declared.iter().any(|(d, _)| d == name) {
+                    continue;
+                }
+                let mut vars = Vec::new();
+                term_vars(other, &mut vars);
+                if !vars.iter().all(|v| v != name && values.contains_key(v)) {
+                    continue;
+                }
+                match values.get(name.as_str()) {
+                    Some(first) => diagnostics.push(Diagnostic::error(
+                        format!("state variable `{}` has more than one initial value; the first is at {}", name, first),
+                        equation.span.clone(),
+                    )),
+                    None => {
+                        values.insert(name, equation.span.clone());
+                    }
+                }
+                return false;
+            }
+            true
+        });
+        if equations.len() == before {
+            break;
+        }
+    }
+
+    for (name, span) in declared {
+        if !values.contains_key(name) {
+            diagnostics.push(Diagnostic::error(
+                format!("state variable `{}` has no initial value", name),
+                span,
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Compiles `module` on its own, keeping whatever lowered cleanly even if
+/// it has errors.
 pub fn compile(module: &Module) -> Program {
     let mut program = Program::default();
-    Compiler::new(&mut program).compile_module(module);
+    let _ = Compiler::new(&mut program).compile_module(module);
     program
 }
 
@@ -479,6 +1087,27 @@ End Global
         assert_eq!(program.rels.get(clause.head_rel).name, "position");
     }
 
+    #[test]
+    fn test_each_underscore_is_a_fresh_var() {
+        let input = r#"Begin Facts:
+End Facts
+
+Begin Global:
+Rule Pair:
+    item(_, X) & item(_, X)
+    -----------------------
+    pair(X, _)
+End Global
+"#;
+        let program = parse_and_compile(input);
+        let clause = program.global_rules.iter().find(|c| c.name == "Pair").unwrap();
+        let underscores = program.vars.iter().filter(|(_, v)| v.name == "_").count();
+        assert_eq!(underscores, 3);
+        let xs = program.vars.iter().filter(|(_, v)| v.name == "X").count();
+        assert_eq!(xs, 1);
+        assert_eq!(clause.head_args.len(), 2);
+    }
+
     #[test]
     fn test_smt_relation_in_rule() {
         let input = r#"Begin Facts:
@@ -527,4 +1156,128 @@ End Stage Movement
         assert_eq!(program.stages[0].rules.len(), 1);
     }
 
+    #[test]
+    fn test_non_relation_conclusion_is_diagnosed() {
+        let input = r#"Begin Facts:
+End Facts
+
+Begin Global:
+Rule Bad:
+    a(X)
+    ----
+    X
+End Global
+"#;
+        let (_, module) = parser::parse_module(input.into()).finish().unwrap();
+        let mut program = Program::default();
+        let mut compiler = Compiler::new(&mut program);
+        assert!(compiler.compile_module(&module).is_err());
+
+        let diagnostics = compiler.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(diagnostics[0].span.start.line, 8);
+        assert!(!program.global_rules.iter().any(|c| c.name == "Bad"));
+    }
+
+    #[test]
+    fn test_clause_and_prop_spans() {
+        let input = r#"Begin Facts:
+    position(player, 0, 0)
+End Facts
+
+Begin Global:
+Rule MoveRight:
+    position(player, X, Y)
+    ----------------------
+    moved(player)
+End Global
+"#;
+        let program = parse_and_compile(input);
+        let clause = program.global_rules.iter().find(|c| c.name == "MoveRight").unwrap();
+        assert_eq!(clause.span.start.line, 6);
+        assert_eq!(clause.span.end.line, 9);
+
+        let span = program.prop_spans.get(&program.facts[0]).unwrap();
+        assert_eq!(span.start.line, 2);
+        assert_eq!(span.start.column, 5);
+    }
+
+    #[test]
+    fn test_infix_arithmetic_lowers_to_smt_relations() {
+        let input = r#"Begin Facts:
+    StateVar Y
+    StateVar N
+    Y = 0.0
+    N = 0
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Step:
+Begin State Constraints:
+    next(Y) = Y + V * 2
+    next(N) = N + 1
+End State Constraints
+End Stage Step
+"#;
+        let program = parse_and_compile(input);
+        let mut rels: Vec<&str> = program
+            .props
+            .iter()
+            .filter_map(|(_, prop)| match prop {
+                Prop::App { rel, .. } => Some(program.rels.get(*rel).name.as_str()),
+                _ => None,
+            })
+            .collect();
+        rels.sort();
+        assert_eq!(rels, vec!["int_add", "real_add", "real_mul"]);
+    }
+
+    #[test]
+    fn test_undecided_infix_arithmetic_is_an_error() {
+        let input = r#"Begin Facts:
+    StateVar N
+    N = 0
+End Facts
+
+Begin Global:
+    Rule Sum:
+    val(X) & val(Y) & Z = X + Y
+    ---------------------------
+    sum(Z)
+
+    Rule Count:
+    val(X) & Z = N + X
+    ------------------
+    count(Z)
+End Global
+"#;
+        let (_, module) = parser::parse_module(input.into()).finish().unwrap();
+        let mut program = Program::default();
+        let mut compiler = Compiler::new(&mut program);
+        assert!(compiler.compile_module(&module).is_err());
+
+        let errors: Vec<String> = compiler
+            .take_diagnostics()
+            .into_iter()
+            .filter(|d| d.is_error())
+            .map(|d| format!("{}: {}", d.span, d.message))
+            .collect();
+        assert_eq!(errors, vec![
+            "8:27: cannot tell whether `(X + Y)` is Int or Real arithmetic here; use `int_add` or `real_add`",
+        ]);
+        let count = program.global_rules.iter().find(|c| c.name == "Count").unwrap();
+        let mut rels = Vec::new();
+        let mut props = vec![count.body];
+        while let Some(prop) = props.pop() {
+            match program.props.get(prop) {
+                Prop::And(a, b) => props.extend([*a, *b]),
+                Prop::App { rel, .. } => rels.push(program.rels.get(*rel).name.as_str()),
+                _ => {}
+            }
+        }
+        assert!(rels.contains(&"int_add"), "{:?}", rels);
+    }
 }
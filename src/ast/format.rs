@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use nom::Input;
+
+use crate::ast::parser::{self, ParseError, Span};
+use crate::ast::{Module, Term};
+
+const INDENT: usize = 4;
+
+/// Formats a `.l` source file into canonical layout while keeping its
+/// comments and the operator sugar the author wrote.
+///
+/// Sections are separated by one blank line, as are the rules and draw
+/// directives inside them; blank lines that group facts, constraints or
+/// draws are kept, collapsed to one. Terms are copied from the source and
+/// only re-indented, and each rule's divider is resized to the wider of its
+/// premise and conclusion. Formatting formatted source changes nothing.
+pub fn format_source(source: &str) -> Result<String, ParseError> {
+    let module = parser::parse_source(source)?;
+    let mut formatter = Formatter::new(source, &module);
+    formatter.run()?;
+    Ok(formatter.finish())
+}
+
+#[derive(Clone, Copy)]
+enum Role {
+    /// A fact, constraint or draw item.
+    Item,
+    /// A rule premise, with the index of its rule.
+    Premise(usize),
+    Conclusion,
+    Condition,
+}
+
+/// How a line relates to its neighbours, which decides the blank lines
+/// around it.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// `Begin Facts:`, `Begin Initial State:`, `Begin Global:` or
+    /// `Begin Stage`.
+    Section,
+    /// The first line of a rule, constraint block or draw directive.
+    Block,
+    /// An include, fact, state variable, constraint or draw.
+    Item,
+    /// Any other line inside a rule or draw directive.
+    Continuation,
+    /// An `End ...` line.
+    Close,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    /// Top-level terms keyed by start offset, with their end offset.
+    terms: HashMap<usize, (usize, Role)>,
+    divider_widths: Vec<usize>,
+    current_rule: Option<usize>,
+    /// Whether list items are constraints or draws rather than facts.
+    nested: bool,
+    /// Whether a `With` condition is waiting for its `Draw`.
+    awaiting_draw: bool,
+    /// Whether the innermost open list or block already has lines.
+    block_has_items: bool,
+    last_level: usize,
+    pending_comments: Vec<&'a str>,
+    pending_blank: bool,
+    lines: Vec<String>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, module: &Module) -> Self {
+        let mut terms = HashMap::new();
+        let mut divider_widths = Vec::new();
+        let mut add = |term: &Term, role: Role| {
+            terms.insert(term.span.start.offset, (term.span.end.offset, role));
+        };
+
+        let initial_constraints = module.initial_state.iter().flat_map(|i| &i.constraints);
+        for fact in module.facts.iter().chain(initial_constraints) {
+            add(fact, Role::Item);
+        }
+        for stage in std::iter::once(&module.global_stage).chain(&module.stages) {
+            for rule in &stage.rules {
+                add(&rule.premise, Role::Premise(divider_widths.len()));
+                add(&rule.conclusion, Role::Conclusion);
+                let width = [&rule.premise, &rule.conclusion]
+                    .iter()
+                    .flat_map(|t| reindent(source, t.span.start.offset, t.span.end.offset, 0))
+                    .map(|line| line.chars().count())
+                    .max()
+                    .unwrap_or(1);
+                divider_widths.push(width);
+            }
+            for constraint in &stage.state_constraints {
+                add(constraint, Role::Item);
+            }
+            for directive in &stage.draw_directives {
+                if let Some(condition) = &directive.condition {
+                    add(condition, Role::Condition);
+                }
+                for draw in &directive.draws {
+                    add(draw, Role::Item);
+                }
+            }
+        }
+
+        Self {
+            source,
+            terms,
+            divider_widths,
+            current_rule: None,
+            nested: false,
+            awaiting_draw: false,
+            block_has_items: false,
+            last_level: 0,
+            pending_comments: Vec::new(),
+            pending_blank: false,
+            lines: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), ParseError> {
+        let source = self.source;
+        let mut offset = 0;
+        while offset < source.len() {
+            let line_end = line_end(source, offset);
+            let line = &source[offset..line_end];
+            let code_start = offset + (line.len() - line.trim_start().len());
+
+            match self.terms.get(&code_start).copied() {
+                Some((end, role)) => offset = self.term(code_start, end, role)? + 1,
+                None => {
+                    self.line(code_start, line.trim())?;
+                    offset = line_end + 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> String {
+        if !self.pending_comments.is_empty() {
+            if !self.lines.is_empty() {
+                self.lines.push(String::new());
+            }
+            let comments = std::mem::take(&mut self.pending_comments);
+            self.lines.extend(comments.into_iter().map(str::to_string));
+        }
+        let mut out = self.lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    /// Emits the term spanning `start..end` and returns the end of its last
+    /// line.
+    fn term(&mut self, start: usize, end: usize, role: Role) -> Result<usize, ParseError> {
+        let (level, kind) = match role {
+            Role::Item if self.nested => (2, Kind::Item),
+            Role::Item => (1, Kind::Item),
+            Role::Premise(rule) => {
+                self.current_rule = Some(rule);
+                (1, Kind::Continuation)
+            }
+            Role::Conclusion => (1, Kind::Continuation),
+            Role::Condition => (2, Kind::Continuation),
+        };
+
+        let line_end = line_end(self.source, end);
+        let comment = self.trailing_comment(end, line_end)?;
+
+        let mut lines = reindent(self.source, start, end, level * INDENT);
+        let first = lines.remove(0);
+        self.emit(level, kind, first.trim_start(), false);
+        self.lines.extend(lines);
+        self.push_comment(comment);
+        Ok(line_end)
+    }
+
+    /// Emits a line that does not start a term: a keyword, divider or
+    /// comment.
+    fn line(&mut self, offset: usize, line: &'a str) -> Result<(), ParseError> {
+        if line.is_empty() {
+            self.pending_blank = true;
+            return Ok(());
+        }
+        if line.starts_with('#') {
+            self.pending_comments.push(line);
+            return Ok(());
+        }
+
+        // An include path is kept verbatim, and may itself contain `#`.
+        let is_include = line.starts_with("Include") || line.starts_with("Import");
+        let code_end = if is_include {
+            line.match_indices('"').nth(1).map_or(line.len(), |(i, _)| i + 1)
+        } else {
+            line.find('#').unwrap_or(line.len())
+        };
+        let comment = self.trailing_comment(offset + code_end, offset + line.len())?;
+        let code = match line[..code_end].split_once(char::is_whitespace) {
+            Some((keyword, path)) if is_include => format!("{} {}", keyword, path.trim_start()),
+            Some(("StateVars", names)) => {
+                let names: Vec<&str> = names.split(',').map(str::trim).collect();
+                format!("StateVars {}", names.join(", "))
+            }
+            _ => line[..code_end].split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        let words = code.split(' ').count();
+
+        let (level, kind, opens) = match code.as_str() {
+            "Begin Facts:" | "Begin Initial State:" | "Begin Global:" => (0, Kind::Section, true),
+            "End Facts" | "End Initial State" | "End Global" => (0, Kind::Close, false),
+            "Begin State Constraints:" => (1, Kind::Block, true),
+            "End State Constraints" => (1, Kind::Close, false),
+            "With" => (1, Kind::Block, false),
+            "Draw" if self.awaiting_draw => (1, Kind::Continuation, true),
+            "Draw" => (1, Kind::Block, true),
+            _ if code.starts_with("Begin Stage ") && words == 3 => (0, Kind::Section, true),
+            _ if code.starts_with("End Stage ") && words == 3 => (0, Kind::Close, false),
+            _ if code.starts_with("Rule ") && words == 2 => (1, Kind::Block, false),
+            _ if code.starts_with("StateVar ") && words == 2 => (1, Kind::Item, false),
+            _ if code.starts_with("StateVars ") => (1, Kind::Item, false),
+            _ if code.starts_with("Include ") || code.starts_with("Import ") => (0, Kind::Item, false),
+            _ if code.starts_with("Use ") && words == 2 => (0, Kind::Item, false),
+            _ if code.starts_with("Table ") && words == 2 => (0, Kind::Item, false),
+            _ if code.starts_with("Cost ") && code.contains('=') => (0, Kind::Item, false),
+            _ if code.starts_with("Relation ") || code.starts_with("Mode ") => (0, Kind::Item, false),
+            _ if code.chars().all(|c| c == '-') => {
+                let width = self.current_rule.map_or(code.len(), |rule| self.divider_widths[rule]);
+                self.emit(1, Kind::Continuation, &"-".repeat(width), false);
+                self.push_comment(comment);
+                return Ok(());
+            }
+            _ => return Err(one_item_per_line(self.source, offset)),
+        };
+
+        match code.as_str() {
+            "Begin State Constraints:" | "With" => self.nested = true,
+            "End State Constraints" => self.nested = false,
+            _ if kind == Kind::Section || kind == Kind::Close || code.starts_with("Rule ") => {
+                self.nested = false;
+            }
+            _ => {}
+        }
+        self.awaiting_draw = code == "With";
+
+        self.emit(level, kind, &code, opens);
+        self.push_comment(comment);
+        Ok(())
+    }
+
+    /// The comment, if any, between `start` and `end`, which must hold
+    /// nothing else but whitespace.
+    fn trailing_comment(&self, start: usize, end: usize) -> Result<Option<&'a str>, ParseError> {
+        let rest = self.source[start..end].trim();
+        if rest.is_empty() {
+            Ok(None)
+        } else if rest.starts_with('#') {
+            Ok(Some(rest))
+        } else {
+            let offset = start + self.source[start..end].find(rest).unwrap_or(0);
+            Err(one_item_per_line(self.source, offset))
+        }
+    }
+
+    fn push_comment(&mut self, comment: Option<&str>) {
+        if let Some(comment) = comment {
+            let last = self.lines.last_mut().unwrap();
+            last.push(' ');
+            last.push_str(comment);
+        }
+    }
+
+    /// Emits a line at `level`, preceded by whatever blank line and comments
+    /// belong before it. `opens` marks lines that start a list, whose first
+    /// entry never gets a blank line.
+    fn emit(&mut self, level: usize, kind: Kind, text: &str, opens: bool) {
+        let blank = match kind {
+            Kind::Section => !self.lines.is_empty(),
+            Kind::Block => self.block_has_items,
+            Kind::Item => self.block_has_items && self.pending_blank,
+            Kind::Close => self.block_has_items && self.pending_blank && !self.pending_comments.is_empty(),
+            Kind::Continuation => false,
+        };
+        if blank {
+            self.lines.push(String::new());
+        }
+
+        let comment_level = match kind {
+            Kind::Close => self.last_level.max(level + 1),
+            _ => level,
+        };
+        for comment in std::mem::take(&mut self.pending_comments) {
+            self.lines.push(format!("{}{}", " ".repeat(comment_level * INDENT), comment));
+        }
+
+        self.lines.push(format!("{}{}", " ".repeat(level * INDENT), text));
+        self.pending_blank = false;
+        self.block_has_items = !opens;
+        self.last_level = level;
+    }
+}
+
+fn line_end(source: &str, offset: usize) -> usize {
+    source[offset..].find('\n').map_or(source.len(), |i| offset + i)
+}
+
+/// The source text of `start..end`, with the first line indented by
+/// `indent` spaces and later lines kept where they were relative to it.
+fn reindent(source: &str, start: usize, end: usize, indent: usize) -> Vec<String> {
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let first_column = source[line_start..start].chars().count();
+
+    source[start..end]
+        .split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.trim_end();
+            let content = line.trim_start();
+            if content.is_empty() {
+                return String::new();
+            }
+            let column = if i == 0 {
+                first_column
+            } else {
+                line.chars().count() - content.chars().count()
+            };
+            let column = indent + column.saturating_sub(first_column);
+            format!("{}{}", " ".repeat(column), content)
+        })
+        .collect()
+}
+
+fn one_item_per_line(source: &str, offset: usize) -> ParseError {
+    ParseError::expected(Span::new(source).take_from(offset), "each item on a line of its own")
+}
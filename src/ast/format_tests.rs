@@ -0,0 +1,63 @@
+use super::format::*;
+use super::*;
+
+#[test]
+fn test_samples_format_idempotently_and_reparse_unchanged() {
+    let mut formatted_samples = 0;
+    for entry in std::fs::read_dir("sample").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "l") {
+            continue;
+        }
+        let source = std::fs::read_to_string(&path).unwrap();
+        // Some samples predate the current grammar.
+        let Ok(original) = parser::parse_source(&source) else {
+            continue;
+        };
+        formatted_samples += 1;
+
+        let formatted = format_source(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        let reparsed = parser::parse_source(&formatted).unwrap();
+        assert_eq!(original.to_string(), reparsed.to_string(), "{}", path.display());
+        assert_eq!(format_source(&formatted).unwrap(), formatted, "{}", path.display());
+    }
+    assert!(formatted_samples > 0);
+}
+
+#[test]
+fn test_comments_and_sugar_are_kept() {
+    let source = "# Inventory\nBegin Facts:\n  item(sword)   # starter\n\n\n  item(shield)\nEnd Facts\nBegin Global:\n# Pickups\nRule Pick:\n  item(X) & ready(X) & X .< 2.5\n  ---\n  has(X)\n  # done\nEnd Global\n# trailer\n";
+    let expected = "# Inventory\nBegin Facts:\n    item(sword) # starter\n\n    item(shield)\nEnd Facts\n\nBegin Global:\n    # Pickups\n    Rule Pick:\n    item(X) & ready(X) & X .< 2.5\n    -----------------------------\n    has(X)\n    # done\nEnd Global\n\n# trailer\n";
+    let formatted = format_source(source).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn test_divider_matches_wider_side() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Global:\n    Rule Grow:\n    a(X)\n    ----------------------\n    bigger(X, X)\nEnd Global\n";
+    let formatted = format_source(source).unwrap();
+    assert!(formatted.contains("    a(X)\n    ------------\n    bigger(X, X)\n"), "{}", formatted);
+}
+
+#[test]
+fn test_stage_layout() {
+    let source = "Begin Facts:\nEnd Facts\nBegin Global:\nEnd Global\nBegin Stage Play:\nRule Move:\npos(X)\n--\nnext_pos(X)\nBegin State Constraints:\n\neq(A, B)\nEnd State Constraints\nWith\nready(X)\nDraw\nsprite(X)\nDraw\ntext(X)\nEnd Stage Play\n";
+    let expected = "Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage Play:\n    Rule Move:\n    pos(X)\n    -----------\n    next_pos(X)\n\n    Begin State Constraints:\n        eq(A, B)\n    End State Constraints\n\n    With\n        ready(X)\n    Draw\n        sprite(X)\n\n    Draw\n        text(X)\nEnd Stage Play\n";
+    assert_eq!(format_source(source).unwrap(), expected);
+}
+
+#[test]
+fn test_initial_state_layout() {
+    let source = "Begin Facts:\nEnd Facts\nBegin Initial State:\nStateVars  A,B\nA = 1 # start\nB = A\nEnd Initial State\nBegin Global:\nEnd Global\n";
+    let expected = "Begin Facts:\nEnd Facts\n\nBegin Initial State:\n    StateVars A, B\n    A = 1 # start\n    B = A\nEnd Initial State\n\nBegin Global:\nEnd Global\n";
+    assert_eq!(format_source(source).unwrap(), expected);
+}
+
+#[test]
+fn test_code_after_keyword_is_an_error() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    assert!(format_source(source).is_ok());
+    let err = format_source("Begin Facts: # ok\nEnd Facts a(1)\nBegin Global:\nEnd Global\n").unwrap_err();
+    assert_eq!(err.span.start.line, 2);
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+use crate::ast::parser::{self, ParseError};
+use crate::ast::{Module, Stage};
+use crate::diagnostic::Diagnostic;
+
+/// Where `Include` directives get their source text from.
+pub trait SourceLoader {
+    fn load(&self, path: &str) -> Result<String, String>;
+}
+
+/// Reads included files from the filesystem.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FsLoader;
+
+impl SourceLoader for FsLoader {
+    fn load(&self, path: &str) -> Result<String, String> {
+        std::fs::read_to_string(path).map_err(|e| e.to_string())
+    }
+}
+
+/// Serves included files from memory, for hosts without a filesystem.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryLoader {
+    files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: &str, source: &str) {
+        self.files.insert(normalize(Path::new(path)), source.to_string());
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(&normalize(Path::new(path)))
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+impl SourceLoader for MemoryLoader {
+    fn load(&self, path: &str) -> Result<String, String> {
+        self.files
+            .get(&normalize(Path::new(path)))
+            .cloned()
+            .ok_or_else(|| "no such file".to_string())
+    }
+}
+
+/// Resolves `path` against the directory of the file that included it.
+pub(crate) fn resolve(including: Option<&str>, path: &str) -> String {
+    let base = including
+        .and_then(|file| Path::new(file).parent())
+        .unwrap_or(Path::new(""));
+    normalize(&base.join(path))
+}
+
+/// Collapses `.` and `..` components so that one file always has one name.
+fn normalize(path: &Path) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            }
+            Component::ParentDir => parts.push(".."),
+            Component::Normal(part) => parts.push(part.to_str().unwrap_or_default()),
+            Component::RootDir => parts.push(""),
+            Component::Prefix(prefix) => parts.push(prefix.as_os_str().to_str().unwrap_or_default()),
+        }
+    }
+    if parts == [""] {
+        return "/".to_string();
+    }
+    parts.join("/")
+}
+
+/// Loads a program spread over several files, following `Include`
+/// directives through a [`SourceLoader`] and merging every file into a
+/// single module. Each file is loaded at most once; include cycles,
+/// missing files and stages defined twice are reported as diagnostics.
+pub struct Includer<'a> {
+    loader: &'a dyn SourceLoader,
+    root: String,
+    /// Source text of every included file, by resolved path.
+    sources: HashMap<String, String>,
+    /// Files currently being expanded, outermost first.
+    stack: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Includer<'a> {
+    pub fn new(loader: &'a dyn SourceLoader) -> Self {
+        Self {
+            loader,
+            root: String::new(),
+            sources: HashMap::new(),
+            stack: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Parses `source` as an unnamed root file and merges in everything it
+    /// includes. Includes are resolved relative to the current directory.
+    pub fn load(&mut self, source: &str) -> Module {
+        self.root = source.to_string();
+        let (module, errors) = parser::parse_module_recovering(source);
+        self.record_parse_errors(None, errors);
+        self.expand(None, module)
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Renders `diagnostic` against the file its span points into.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let source = match &diagnostic.span.file {
+            None => Some(&self.root),
+            Some(file) => self.sources.get(&**file),
+        };
+        match source {
+            Some(source) => diagnostic.render(source),
+            None => diagnostic.to_string(),
+        }
+    }
+
+    fn record_parse_errors(&mut self, file: Option<&str>, errors: Vec<ParseError>) {
+        for err in errors {
+            let mut diagnostic = err.to_diagnostic();
+            diagnostic.span.file = file.map(Into::into);
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Replaces `module`'s includes with the contents of the included files,
+    /// which come before the module's own facts, rules and stages.
+    fn expand(&mut self, file: Option<&str>, mut module: Module) -> Module {
+        let includes = std::mem::take(&mut module.includes);
+        let mut merged = Module {
+            includes: Vec::new(),
+            uses: Vec::new(),
+            relations: Vec::new(),
+            modes: Vec::new(),
+            tables: Vec::new(),
+            costs: Vec::new(),
+            state_vars: Vec::new(),
+            facts: Vec::new(),
+            initial_state: None,
+            global_stage: Stage {
+                name: module.global_stage.name.clone(),
+                rules: Vec::new(),
+                state_constraints: Vec::new(),
+                draw_directives: Vec::new(),
+                span: module.global_stage.span.clone(),
+            },
+            stages: Vec::new(),
+            span: module.span.clone(),
+        };
+
+        for include in includes {
+            let path = resolve(file, &include.path);
+            if let Some(start) = self.stack.iter().position(|p| *p == path) {
+                let mut cycle = self.stack[start..].to_vec();
+                cycle.push(path);
+                self.diagnostics.push(Diagnostic::error(
+                    format!("include cycle: {}", cycle.join(" -> ")),
+                    include.span,
+                ));
+                continue;
+            }
+            if self.sources.contains_key(&path) {
+                continue;
+            }
+
+            let source = match self.loader.load(&path) {
+                Ok(source) => source,
+                Err(reason) => {
+                    self.diagnostics.push(Diagnostic::error(
+                        format!("cannot include `{}`: {}", path, reason),
+                        include.span,
+                    ));
+                    continue;
+                }
+            };
+            let (mut included, errors) = parser::parse_module_recovering(&source);
+            self.sources.insert(path.clone(), source);
+            included.set_file(&path);
+            self.record_parse_errors(Some(&path), errors);
+
+            self.stack.push(path.clone());
+            let included = self.expand(Some(&path), included);
+            self.stack.pop();
+            self.merge(&mut merged, included);
+        }
+
+        self.merge(&mut merged, module);
+        merged
+    }
+
+    fn merge(&mut self, into: &mut Module, from: Module) {
+        for state_var in from.state_vars {
+            if !into.state_vars.contains(&state_var) {
+                into.state_vars.push(state_var);
+            }
+        }
+        into.uses.extend(from.uses);
+        into.relations.extend(from.relations);
+        into.modes.extend(from.modes);
+        into.tables.extend(from.tables);
+        into.costs.extend(from.costs);
+        into.facts.extend(from.facts);
+        if let Some(initial) = from.initial_state {
+            match &mut into.initial_state {
+                Some(existing) => {
+                    existing.state_vars.extend(initial.state_vars);
+                    existing.constraints.extend(initial.constraints);
+                }
+                None => into.initial_state = Some(initial),
+            }
+        }
+        into.global_stage.rules.extend(from.global_stage.rules);
+
+        for stage in from.stages {
+            if let Some(existing) = into.stages.iter().find(|s| s.name == stage.name) {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("stage `{}` is already defined at {}", stage.name, existing.span),
+                    stage.span,
+                ));
+                continue;
+            }
+            into.stages.push(stage);
+        }
+    }
+}
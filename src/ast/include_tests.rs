@@ -0,0 +1,93 @@
+use super::include::*;
+use super::*;
+use crate::diagnostic::Diagnostic;
+
+const EMPTY: &str = "Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+
+fn load(loader: &MemoryLoader, source: &str) -> (Module, Vec<Diagnostic>) {
+    let mut includer = Includer::new(loader);
+    let module = includer.load(source);
+    (module, includer.take_diagnostics())
+}
+
+#[test]
+fn test_resolve_is_relative_to_including_file() {
+    assert_eq!(resolve(None, "physics.l"), "physics.l");
+    assert_eq!(resolve(Some("lib/world.l"), "physics.l"), "lib/physics.l");
+    assert_eq!(resolve(Some("lib/world.l"), "../common/./util.l"), "common/util.l");
+}
+
+#[test]
+fn test_included_files_merge_into_one_module() {
+    let mut loader = MemoryLoader::new();
+    loader.insert(
+        "physics.l",
+        "Begin Facts:\n    gravity(1)\nEnd Facts\n\nBegin Global:\nRule Fall:\n    gravity(G)\n    ----\n    falls(G)\nEnd Global\n\nBegin Stage Physics:\nEnd Stage Physics\n",
+    );
+    let (module, diagnostics) = load(
+        &loader,
+        "Include \"physics.l\"\n\nBegin Facts:\n    player(1)\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage Play:\nEnd Stage Play\n",
+    );
+
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(module.facts.len(), 2);
+    assert_eq!(module.facts[0].span.file.as_deref(), Some("physics.l"));
+    assert_eq!(module.facts[1].span.file, None);
+    assert_eq!(module.global_stage.rules.len(), 1);
+    let stages: Vec<&str> = module.stages.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(stages, vec!["Physics", "Play"]);
+}
+
+#[test]
+fn test_shared_include_is_loaded_once() {
+    let mut loader = MemoryLoader::new();
+    loader.insert("a.l", &format!("Include \"common.l\"\n{}", EMPTY));
+    loader.insert("b.l", &format!("Include \"common.l\"\n{}", EMPTY));
+    loader.insert("common.l", "Begin Facts:\n    shared(1)\nEnd Facts\n\nBegin Global:\nEnd Global\n");
+    let (module, diagnostics) = load(&loader, &format!("Include \"a.l\"\nImport \"b.l\"\n{}", EMPTY));
+
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(module.facts.len(), 1);
+}
+
+#[test]
+fn test_include_cycle_is_reported() {
+    let mut loader = MemoryLoader::new();
+    loader.insert("a.l", &format!("Include \"b.l\"\n{}", EMPTY));
+    loader.insert("b.l", &format!("Include \"a.l\"\n{}", EMPTY));
+    let (_, diagnostics) = load(&loader, &format!("Include \"a.l\"\n{}", EMPTY));
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "include cycle: a.l -> b.l -> a.l");
+    assert_eq!(diagnostics[0].span.file.as_deref(), Some("b.l"));
+}
+
+#[test]
+fn test_duplicate_stage_is_reported() {
+    let mut loader = MemoryLoader::new();
+    loader.insert("stages.l", &format!("{}\nBegin Stage Play:\nEnd Stage Play\n", EMPTY));
+    let (module, diagnostics) = load(
+        &loader,
+        &format!("Include \"stages.l\"\n{}\nBegin Stage Play:\nEnd Stage Play\n", EMPTY),
+    );
+
+    assert_eq!(module.stages.len(), 1);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "stage `Play` is already defined at stages.l:7:1");
+    assert_eq!(diagnostics[0].span.start.line, 8);
+}
+
+#[test]
+fn test_missing_include_and_parse_errors_are_located() {
+    let mut loader = MemoryLoader::new();
+    loader.insert("broken.l", "Begin Facts:\n    a(\nEnd Facts\n\nBegin Global:\nEnd Global\n");
+    let mut includer = Includer::new(&loader);
+    includer.load(&format!("Include \"missing.l\"\nInclude \"broken.l\"\n{}", EMPTY));
+    let diagnostics = includer.diagnostics();
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].message, "cannot include `missing.l`: no such file");
+    assert_eq!(diagnostics[0].span.start.line, 1);
+    assert_eq!(diagnostics[1].span.file.as_deref(), Some("broken.l"));
+    assert!(includer.render(&diagnostics[1]).contains("--> broken.l:3:5"));
+}
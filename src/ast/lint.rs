@@ -0,0 +1,143 @@
+//! Warnings about variables that are probably mistakes.
+//!
+//! A misspelled variable silently becomes a fresh logic variable, which
+//! usually makes a rule or a state constraint trivially true. This pass
+//! reports variables that occur only once in their scope, `next(X)` where
+//! `X` is not a declared state variable, and state variables a stage reads
+//! without saying what they become.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Module, Rule, SourceSpan, Stage, Term, TermContents};
+use crate::diagnostic::{closest_name, Diagnostic};
+
+struct Occurrence<'t> {
+    name: &'t str,
+    span: &'t SourceSpan,
+    /// Whether this is `next(X)` or an argument of `preserve`, which say
+    /// what the state variable becomes rather than reading it.
+    next: bool,
+}
+
+fn collect_occurrences<'t>(term: &'t Term, out: &mut Vec<Occurrence<'t>>) {
+    match &term.contents {
+        TermContents::Var { name } => out.push(Occurrence { name, span: &term.span, next: false }),
+        TermContents::App { rel, args } => {
+            let is_next = rel.name() == "next" && args.len() == 1;
+            if is_next || rel.name() == "preserve" {
+                for arg in args {
+                    if let TermContents::Var { name } = &arg.contents {
+                        let span = if is_next { &term.span } else { &arg.span };
+                        out.push(Occurrence { name, span, next: true });
+                    } else {
+                        collect_occurrences(arg, out);
+                    }
+                }
+            } else {
+                args.iter().for_each(|arg| collect_occurrences(arg, out));
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Lint<'m> {
+    state_vars: Vec<String>,
+    /// Variables bound by the facts, which rules and stages share.
+    globals: HashSet<&'m str>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'m> Lint<'m> {
+    /// Reports variables occurring once in `occurrences`, which make up the
+    /// whole of one scope, described by `scope`.
+    fn singletons(&mut self, occurrences: &[Occurrence], scope: &str) {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for occ in occurrences {
+            *counts.entry(occ.name).or_default() += 1;
+        }
+
+        for occ in occurrences {
+            if counts[occ.name] > 1 || occ.next || occ.name.starts_with('_') || self.globals.contains(occ.name) {
+                continue;
+            }
+            let candidates = self.globals.iter().copied().chain(counts.keys().copied());
+            let hint = match closest_name(occ.name, candidates) {
+                Some(candidate) => format!("did you mean `{}`?", candidate),
+                None => format!("write `_{}` if that is intended", occ.name),
+            };
+            self.diagnostics.push(Diagnostic::warning(
+                format!("variable `{}` occurs only once in {}; {}", occ.name, scope, hint),
+                occ.span.clone(),
+            ));
+        }
+    }
+
+    fn undeclared_next(&mut self, occurrences: &[Occurrence]) {
+        for occ in occurrences.iter().filter(|o| o.next) {
+            if self.state_vars.iter().any(|v| v == occ.name) {
+                continue;
+            }
+            let mut message = format!("`{}` is not a declared state variable", occ.name);
+            if let Some(candidate) = closest_name(occ.name, self.state_vars.iter().map(String::as_str)) {
+                message.push_str(&format!("; did you mean `{}`?", candidate));
+            }
+            self.diagnostics.push(Diagnostic::warning(message, occ.span.clone()));
+        }
+    }
+
+    fn rule(&mut self, rule: &Rule) {
+        let mut occurrences = Vec::new();
+        collect_occurrences(&rule.premise, &mut occurrences);
+        collect_occurrences(&rule.conclusion, &mut occurrences);
+        self.singletons(&occurrences, &format!("rule `{}`", rule.name));
+        self.undeclared_next(&occurrences);
+    }
+
+    fn stage(&mut self, stage: &Stage) {
+        stage.rules.iter().for_each(|rule| self.rule(rule));
+
+        let mut occurrences = Vec::new();
+        for constraint in &stage.state_constraints {
+            collect_occurrences(constraint, &mut occurrences);
+        }
+        self.singletons(&occurrences, &format!("the state constraints of stage `{}`", stage.name));
+        self.undeclared_next(&occurrences);
+
+        let mut reported = HashSet::new();
+        for occ in &occurrences {
+            let is_state_var = self.state_vars.iter().any(|v| v == occ.name);
+            if occ.next || !is_state_var || occurrences.iter().any(|o| o.next && o.name == occ.name) {
+                continue;
+            }
+            if reported.insert(occ.name) {
+                self.diagnostics.push(Diagnostic::warning(
+                    format!(
+                        "stage `{}` reads state variable `{}` but never constrains `next({})`; add `preserve({})` if it should keep its value",
+                        stage.name, occ.name, occ.name, occ.name
+                    ),
+                    occ.span.clone(),
+                ));
+            }
+        }
+    }
+}
+
+/// Lints every rule and the state constraints of every stage in `module`.
+pub fn lint_module(module: &Module) -> Vec<Diagnostic> {
+    let mut fact_vars = Vec::new();
+    let initial_constraints = module.initial_state.iter().flat_map(|i| &i.constraints);
+    for fact in module.facts.iter().chain(initial_constraints) {
+        collect_occurrences(fact, &mut fact_vars);
+    }
+
+    let declared = module.initial_state.iter().flat_map(|i| &i.state_vars).map(|d| d.name.as_str());
+    let mut lint = Lint {
+        globals: fact_vars.iter().map(|o| o.name).chain(module.state_vars.iter().map(String::as_str)).chain(declared).collect(),
+        state_vars: module.declared_state_vars(),
+        diagnostics: Vec::new(),
+    };
+    lint.stage(&module.global_stage);
+    module.stages.iter().for_each(|stage| lint.stage(stage));
+    lint.diagnostics
+}
@@ -0,0 +1,61 @@
+use super::lint::*;
+use super::parser::parse_source;
+
+fn lint(source: &str) -> Vec<String> {
+    let module = parse_source(source).unwrap();
+    lint_module(&module)
+        .into_iter()
+        .map(|d| format!("{}: {}", d.span, d.message))
+        .collect()
+}
+
+#[test]
+fn test_misspelled_variable_in_rule() {
+    let source = "Begin Facts:\n    StateVar RunnerVY\n    RunnerVY = 1.0\nEnd Facts\n\nBegin Global:\n    Rule Falling:\n    RunnerVy .< 0.0\n    ---------------\n    falling()\nEnd Global\n";
+    assert_eq!(
+        lint(source),
+        vec!["8:5: variable `RunnerVy` occurs only once in rule `Falling`; did you mean `RunnerVY`?"]
+    );
+}
+
+#[test]
+fn test_underscore_prefixed_singletons_are_allowed() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Global:\n    Rule InCart:\n    true()\n    ------\n    inCart(I, cons(I, _Rest))\n\n    Rule Tail:\n    inCart(I, Rest)\n    ---------------\n    inCart(I, cons(Y, Rest))\nEnd Global\n";
+    assert_eq!(
+        lint(source),
+        vec!["13:20: variable `Y` occurs only once in rule `Tail`; write `_Y` if that is intended"]
+    );
+}
+
+#[test]
+fn test_next_of_undeclared_state_variable() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Initial State:\n    StateVar Health\n    Health = 3\nEnd Initial State\n\nBegin Global:\nEnd Global\n\nBegin Stage Damage:\n    Begin State Constraints:\n        next(Health) = Health - 1 & next(Healht) = 0\n    End State Constraints\nEnd Stage Damage\n";
+    assert_eq!(
+        lint(source),
+        vec!["14:37: `Healht` is not a declared state variable; did you mean `Health`?"]
+    );
+}
+
+#[test]
+fn test_stage_reading_a_state_variable_it_never_constrains() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Initial State:\n    StateVars Score, Dead\n    Score = 0\n    Dead = no\nEnd Initial State\n\nBegin Global:\nEnd Global\n\nBegin Stage Score:\n    Begin State Constraints:\n        when Dead = no: next(Score) = Score + 1\n        otherwise: preserve(Score)\n    End State Constraints\nEnd Stage Score\n\nBegin Stage Tick:\n    Begin State Constraints:\n        next(Score) = Score + 1 & preserve(Dead)\n    End State Constraints\nEnd Stage Tick\n";
+    assert_eq!(
+        lint(source),
+        vec!["15:14: stage `Score` reads state variable `Dead` but never constrains `next(Dead)`; add `preserve(Dead)` if it should keep its value"]
+    );
+}
+
+#[test]
+fn test_lint_fires_on_the_samples() {
+    let sample = |path| lint(&std::fs::read_to_string(path).unwrap());
+    assert_eq!(sample("sample/runner.l"), vec![
+        "53:33: variable `NewVY` occurs only once in the state constraints of stage `Physics`; did you mean `NewY`?",
+    ]);
+    assert_eq!(sample("sample/inventory.l"), vec![
+        "25:22: variable `MaxSize` occurs only once in rule `CartCostEmpty`; write `_MaxSize` if that is intended",
+        "35:23: variable `Rest` occurs only once in rule `InCart`; write `_Rest` if that is intended",
+        "40:20: variable `X` occurs only once in rule `InCartTail`; write `_X` if that is intended",
+        "60:19: variable `I` occurs only once in rule `CartSizeAdd`; write `_I` if that is intended",
+    ]);
+    assert_eq!(sample("sample/state_basic.l"), Vec::<String>::new());
+}
@@ -1,20 +1,28 @@
-use nom::{IResult, Parser};
+use nom::{Finish, IResult, Parser};
 use nom_locate::{position, LocatedSpan};
 
 use nom::{
     AsChar,
     branch::alt,
     bytes::complete::{tag, take_while, take_while1, take_till},
-    character::complete::{char, digit1, multispace0, line_ending},
-    combinator::{map, opt, recognize},
-    multi::{many0, separated_list0},
+    character::complete::{char, digit1, multispace0, line_ending, satisfy},
+    combinator::{cut, map, not, opt, recognize},
+    multi::{many0, separated_list0, separated_list1},
     sequence::delimited,
 };
 
-use crate::ast::{DrawDirective, Module, Rule, Stage, Term, TermContents, Rel};
+use crate::ast::{
+    DrawDirective, Include, InitialState, Mode, ModeDecl, Module, Rel, RelationDecl, Rule, SourcePos, SourceSpan, Stage,
+    StateVarDecl, TableDecl, CostDecl, Term, TermContents, Type, Use,
+};
+
+mod error;
+mod recover;
+pub use error::{Construct, ParseError};
+pub use recover::parse_module_recovering;
 
 /// Skips a line comment: # followed by everything until (but not including) newline or EOF
-fn skip_line_comment(s: Span) -> IResult<Span, ()> {
+fn skip_line_comment(s: Span) -> IResult<Span, (), ParseError> {
     let (s, _) = char('#')(s)?;
     let (s, _) = take_till(|c| c == '\n' || c == '\r')(s)?;
     Ok((s, ()))
@@ -22,7 +30,7 @@ fn skip_line_comment(s: Span) -> IResult<Span, ()> {
 
 /// Skips whitespace and comments (zero or more). 
 /// Replaces multispace0 but also handles # comments.
-fn ws0(s: Span) -> IResult<Span, ()> {
+fn ws0(s: Span) -> IResult<Span, (), ParseError> {
     let mut s = s;
     loop {
         // Skip any whitespace first
@@ -42,21 +50,21 @@ fn ws0(s: Span) -> IResult<Span, ()> {
 
 /// Skips whitespace and comments, requiring at least one whitespace char or comment.
 /// Replaces multispace1.
-fn ws1(s: Span) -> IResult<Span, ()> {
+fn ws1(s: Span) -> IResult<Span, (), ParseError> {
     // Must have at least one whitespace char OR a comment
     let start = s;
     let (s, _) = ws0(s)?;
     
     // Check we actually consumed something
     if s.location_offset() == start.location_offset() {
-        return Err(nom::Err::Error(nom::error::Error::new(s, nom::error::ErrorKind::Space)));
+        return Err(nom::Err::Error(ParseError::new(s)));
     }
     Ok((s, ()))
 }
 
 /// Skips optional inline comment at end of line (before line_ending).
 /// Use this after parsing content that may have a trailing comment.
-fn skip_trailing_comment(s: Span) -> IResult<Span, ()> {
+fn skip_trailing_comment(s: Span) -> IResult<Span, (), ParseError> {
     let (s, _) = take_while(|c| c == ' ' || c == '\t')(s)?;
     let (s, _) = opt(skip_line_comment).parse(s)?;
     Ok((s, ()))
@@ -68,55 +76,196 @@ fn user_rel(name: &str) -> Rel {
 
 pub type Span<'a> = LocatedSpan<&'a str>;
 
-pub fn parse_rule(s: Span) -> IResult<Span, Rule> {
-    let (s, _) = position(s)?;
+fn source_pos(s: Span) -> SourcePos {
+    SourcePos {
+        offset: s.location_offset(),
+        line: s.location_line(),
+        column: s.get_utf8_column() as u32,
+    }
+}
+
+/// The span of the input consumed between `start` and `end`.
+fn span_between(start: Span, end: Span) -> SourceSpan {
+    SourceSpan::new(source_pos(start), source_pos(end))
+}
+
+fn binary(rel: Rel, left: Term, right: Term) -> Term {
+    let span = left.span.to(&right.span);
+    Term {
+        contents: TermContents::App {
+            rel,
+            args: vec![left, right],
+        },
+        span,
+    }
+}
+
+/// Labels any error from `parser` with the construct being parsed.
+fn within<'a, T>(
+    construct: Construct,
+    mut parser: impl Parser<Span<'a>, Output = T, Error = ParseError>,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, T, ParseError> {
+    move |s| {
+        parser.parse(s).map_err(|e| {
+            e.map(|mut e| {
+                e.push_context(construct);
+                e
+            })
+        })
+    }
+}
+
+/// Describes what `parser` was looking for when it backtracks without a
+/// more specific explanation. Committed failures are passed through as-is.
+fn expect<'a, T>(
+    expected: &'static str,
+    mut parser: impl Parser<Span<'a>, Output = T, Error = ParseError>,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, T, ParseError> {
+    move |s| {
+        parser.parse(s).map_err(|e| match e {
+            nom::Err::Error(e) if e.expected.is_none() || e.span.start.offset == s.location_offset() => {
+                nom::Err::Error(ParseError::expected(s, expected))
+            }
+            e => e,
+        })
+    }
+}
+
+pub fn parse_rule(s: Span) -> IResult<Span, Rule, ParseError> {
+    let (s, start) = position(s)?;
 
     let (s, _) = tag("Rule")(s)?;
     let (s, _) = ws1(s)?;
 
-    let (s, name) = parse_identifier(s)?;
+    cut(within(Construct::Rule, move |s| parse_rule_body(s, start))).parse(s)
+}
 
-    let (s, _) = char(':')(s)?;
-    let (s, _) = line_ending(s)?;
+fn parse_rule_body<'a>(s: Span<'a>, start: Span<'a>) -> IResult<Span<'a>, Rule, ParseError> {
+    let (s, name) = expect("a rule name", parse_identifier).parse(s)?;
+
+    let (s, _) = expect("`:` after the rule name", char(':')).parse(s)?;
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line after the rule name", line_ending).parse(s)?;
 
     let (s, _) = ws0(s)?;
     let (s, premise) = parse_term(s)?;
     let (s, _) = skip_trailing_comment(s)?;
-    let (s, _) = line_ending(s)?;
+    let (s, _) = expect("end of line after the premise", line_ending).parse(s)?;
 
     let (s, _) = ws0(s)?;
-    let (s, _) = take_while1(|c| c == '-')(s)?;
-    let (s, _) = line_ending(s)?;
+    let (s, _) = expect("a divider line of `-`", take_while1(|c| c == '-')).parse(s)?;
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line after the divider", line_ending).parse(s)?;
 
     let (s, _) = ws0(s)?;
     let (s, conclusion) = parse_term(s)?;
+    let span = span_between(start, s);
     let (s, _) = skip_trailing_comment(s)?;
 
     Ok((s, Rule {
         name: name.to_string(),
         premise,
         conclusion,
+        span,
     }))
 }
 
-fn parse_state_constraints(s: Span) -> IResult<Span, Vec<Term>> {
+fn parse_state_constraints(s: Span) -> IResult<Span, Vec<Term>, ParseError> {
     let (s, _) = tag("Begin State Constraints:")(s)?;
-    let (s, _) = line_ending(s)?;
+    cut(within(Construct::StateConstraints, parse_state_constraints_body)).parse(s)
+}
+
+fn parse_state_constraints_body(s: Span) -> IResult<Span, Vec<Term>, ParseError> {
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line", line_ending).parse(s)?;
 
     let (s, constraints) = many0(|s| {
         let (s, _) = ws0(s)?;
-        let (s, term) = parse_term(s)?;
-        let (s, _) = skip_trailing_comment(s)?;
-        let (s, _) = line_ending(s)?;
-        Ok((s, term))
+        if s.fragment().starts_with("End") {
+            return Err(nom::Err::Error(ParseError::new(s)));
+        }
+        cut(|s| {
+            let (s, term) = alt((parse_when, parse_term)).parse(s)?;
+            let (s, _) = skip_trailing_comment(s)?;
+            let (s, _) = expect("end of line after the constraint", line_ending).parse(s)?;
+            Ok((s, term))
+        }).parse(s)
     }).parse(s)?;
 
     let (s, _) = ws0(s)?;
-    let (s, _) = tag("End State Constraints")(s)?;
+    let (s, _) = expect("`End State Constraints`", tag("End State Constraints")).parse(s)?;
 
     Ok((s, constraints))
 }
 
+/// Parses `when C: A`, any number of `otherwise when C: A` arms and an
+/// optional `otherwise: B` into nested `cond(C, A, ...)` terms. Without an
+/// `otherwise` arm the chain ends in `true`, constraining nothing when no
+/// condition holds.
+fn parse_when(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = (tag("when"), ws1).parse(s)?;
+    cut(move |s| parse_when_chain(s, start)).parse(s)
+}
+
+fn parse_when_arm(s: Span) -> IResult<Span, (Term, Term), ParseError> {
+    let (s, condition) = parse_term(s)?;
+    let (s, _) = take_while(|c| c == ' ' || c == '\t')(s)?;
+    let (s, _) = expect("`:` after the condition", char(':')).parse(s)?;
+    let (s, _) = ws0(s)?;
+    let (s, body) = parse_term(s)?;
+    Ok((s, (condition, body)))
+}
+
+fn parse_when_chain<'a>(s: Span<'a>, start: Span<'a>) -> IResult<Span<'a>, Term, ParseError> {
+    let (mut s, arm) = parse_when_arm(s)?;
+    let mut arms = vec![arm];
+    let mut otherwise = None;
+
+    loop {
+        let (next, _) = ws0(s)?;
+        // A constraint such as `otherwiseOk()` after the chain isn't an arm.
+        let mut keyword = (tag::<_, _, ParseError>("otherwise"), not(satisfy(is_alphanumeric_or_underscore)));
+        let Ok((next, _)) = keyword.parse(next) else {
+            break;
+        };
+        let (next, _) = take_while(|c| c == ' ' || c == '\t')(next)?;
+        if let Ok((next, _)) = (tag::<_, _, ParseError>("when"), ws1).parse(next) {
+            let (next, arm) = parse_when_arm(next)?;
+            arms.push(arm);
+            s = next;
+            continue;
+        }
+        let (next, _) = expect("`when` or `:` after `otherwise`", char(':')).parse(next)?;
+        let (next, _) = ws0(next)?;
+        let (next, body) = parse_term(next)?;
+        otherwise = Some(body);
+        s = next;
+        break;
+    }
+
+    let end = source_pos(s);
+    let mut term = otherwise.unwrap_or_else(|| Term {
+        contents: TermContents::Atom { text: "true".to_string() },
+        span: SourceSpan::new(end, end),
+    });
+    for (i, (condition, body)) in arms.into_iter().enumerate().rev() {
+        let span = if i == 0 {
+            span_between(start, s)
+        } else {
+            SourceSpan::new(condition.span.start, end)
+        };
+        term = Term {
+            contents: TermContents::App {
+                rel: user_rel("cond"),
+                args: vec![condition, body, term],
+            },
+            span,
+        };
+    }
+    Ok((s, term))
+}
+
 fn is_draw_terminator(s: Span) -> bool {
     let fragment = s.fragment();
     fragment.starts_with("With")
@@ -126,48 +275,100 @@ fn is_draw_terminator(s: Span) -> bool {
         || fragment.starts_with("End")
 }
 
-fn parse_with_clause(s: Span) -> IResult<Span, Term> {
+fn parse_with_clause(s: Span) -> IResult<Span, Term, ParseError> {
     let (s, _) = tag("With")(s)?;
-    let (s, _) = line_ending(s)?;
-    let (s, _) = ws0(s)?;
-    let (s, term) = parse_term(s)?;
-    let (s, _) = skip_trailing_comment(s)?;
-    let (s, _) = line_ending(s)?;
-    let (s, _) = ws0(s)?;
-    Ok((s, term))
+    cut(|s| {
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after `With`", line_ending).parse(s)?;
+        let (s, _) = ws0(s)?;
+        let (s, term) = parse_term(s)?;
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the condition", line_ending).parse(s)?;
+        let (s, _) = ws0(s)?;
+        Ok((s, term))
+    }).parse(s)
 }
 
-fn parse_draw_item(s: Span) -> IResult<Span, Term> {
+fn parse_draw_item(s: Span) -> IResult<Span, Term, ParseError> {
     let (s, _) = ws0(s)?;
     if is_draw_terminator(s) {
-        return Err(nom::Err::Error(nom::error::Error::new(s, nom::error::ErrorKind::Tag)));
+        return Err(nom::Err::Error(ParseError::new(s)));
     }
-    let (s, term) = parse_term(s)?;
-    let (s, _) = skip_trailing_comment(s)?;
-    let (s, _) = line_ending(s)?;
-    Ok((s, term))
+    cut(|s| {
+        let (s, term) = parse_term(s)?;
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the draw", line_ending).parse(s)?;
+        Ok((s, term))
+    }).parse(s)
 }
 
-fn parse_draw_directive(s: Span) -> IResult<Span, DrawDirective> {
+fn parse_draw_directive(s: Span) -> IResult<Span, DrawDirective, ParseError> {
     let (s, _) = ws0(s)?;
+    let (s, start) = position(s)?;
     let (s, condition) = opt(parse_with_clause).parse(s)?;
-    let (s, _) = tag("Draw")(s)?;
-    let (s, _) = line_ending(s)?;
+    let (s, _) = if condition.is_some() {
+        cut(expect("`Draw` after the `With` condition", tag("Draw"))).parse(s)?
+    } else {
+        tag("Draw")(s)?
+    };
+    let header_span = span_between(start, s);
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = cut(expect("end of line after `Draw`", line_ending)).parse(s)?;
     let (s, draws) = many0(parse_draw_item).parse(s)?;
 
-    Ok((s, DrawDirective { condition, draws }))
+    let span = match draws.last() {
+        Some(last) => header_span.to(&last.span),
+        None => header_span,
+    };
+
+    Ok((s, DrawDirective { condition, draws, span }))
 }
 
-pub fn parse_stage(s: Span) -> IResult<Span, Stage> {
-    let (s, _) = position(s)?;
+pub fn parse_stage(s: Span) -> IResult<Span, Stage, ParseError> {
+    let (s, start) = position(s)?;
 
     let (s, _) = tag("Begin Stage")(s)?;
     let (s, _) = ws1(s)?;
 
-    let (s, name) = parse_identifier(s)?;
+    cut(within(Construct::Stage, move |s| parse_stage_body(s, start))).parse(s)
+}
+
+fn parse_stage_header(s: Span<'_>) -> IResult<Span<'_>, &str, ParseError> {
+    let (s, name) = expect("a stage name", parse_identifier).parse(s)?;
+
+    let (s, _) = expect("`:` after the stage name", char(':')).parse(s)?;
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line after the stage name", line_ending).parse(s)?;
+    Ok((s, name))
+}
+
+/// What may follow once a stage has moved past its rules.
+fn stage_item_expectation(past_rules: bool) -> &'static str {
+    if past_rules {
+        "a draw directive or `End Stage`"
+    } else {
+        "a rule, draw directive or `End Stage`"
+    }
+}
+
+/// Parses the stage name after `End Stage` and checks it matches `name`.
+fn parse_stage_end<'a>(s: Span<'a>, name: &str) -> IResult<Span<'a>, (), ParseError> {
+    let (s, _) = ws1(s)?;
+
+    let end_start = s;
+    let (s, end_name) = expect("the stage name after `End Stage`", parse_identifier).parse(s)?;
+
+    if name != end_name {
+        return Err(nom::Err::Error(ParseError::expected(
+            end_start,
+            format!("`{}` to match `Begin Stage {}`", name, name),
+        )));
+    }
+    Ok((s, ()))
+}
 
-    let (s, _) = char(':')(s)?;
-    let (s, _) = line_ending(s)?;
+fn parse_stage_body<'a>(s: Span<'a>, start: Span<'a>) -> IResult<Span<'a>, Stage, ParseError> {
+    let (s, name) = parse_stage_header(s)?;
 
     let (s, rules) = many0(|s| {
         let (s, _) = ws0(s)?;
@@ -178,42 +379,60 @@ pub fn parse_stage(s: Span) -> IResult<Span, Stage> {
 
     let (s, _) = ws0(s)?;
     let (s, state_constraints) = opt(parse_state_constraints).parse(s)?;
+    let has_constraints = state_constraints.is_some();
     let state_constraints = state_constraints.unwrap_or_default();
 
     let (s, _) = ws0(s)?;
-    let (s, draw_directives) = many0(parse_draw_directive).parse(s)?;
+    let (s, draw_directives) = many0(within(Construct::DrawDirective, parse_draw_directive)).parse(s)?;
 
+    let past_rules = has_constraints || !draw_directives.is_empty();
     let (s, _) = ws0(s)?;
-    let (s, _) = tag("End Stage")(s)?;
-    let (s, _) = ws1(s)?;
-
-    let (s, end_name) = parse_identifier(s)?;
-
-    if name != end_name {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            s,
-            nom::error::ErrorKind::Verify,
-        )));
-    }
+    let (s, _) = expect(stage_item_expectation(past_rules), tag("End Stage")).parse(s)?;
+    let (s, _) = parse_stage_end(s, name)?;
 
     Ok((s, Stage {
         name: name.to_string(),
         rules,
         state_constraints,
         draw_directives,
+        span: span_between(start, s),
     }))
 }
 
-fn parse_state_var(s: Span) -> IResult<Span, String> {
-    let (s, _) = tag("StateVar")(s)?;
-    let (s, _) = ws1(s)?;
+fn parse_state_var_name(s: Span) -> IResult<Span, StateVarDecl, ParseError> {
+    let name_start = s;
     let (s, name) = parse_identifier(s)?;
 
-    if !name.chars().next().unwrap().is_uppercase() {
-        return Err(nom::Err::Error(nom::error::Error::new(s, nom::error::ErrorKind::Verify)));
+    // `_`-prefixed names are variables the lint pass expects to occur once.
+    let first = name.chars().next().unwrap();
+    if !first.is_uppercase() && first != '_' {
+        return Err(nom::Err::Failure(ParseError::expected(
+            name_start,
+            "a capitalized state variable name",
+        )));
     }
 
-    Ok((s, name.to_string()))
+    Ok((s, StateVarDecl {
+        name: name.to_string(),
+        span: span_between(name_start, s),
+    }))
+}
+
+fn parse_state_var(s: Span) -> IResult<Span, String, ParseError> {
+    let (s, _) = tag("StateVar")(s)?;
+    let (s, _) = ws1(s)?;
+    let (s, decl) = parse_state_var_name(s)?;
+    Ok((s, decl.name))
+}
+
+/// Parses `StateVars A, B, C`, or a single `StateVar A`.
+fn parse_state_vars(s: Span) -> IResult<Span, Vec<StateVarDecl>, ParseError> {
+    let (s, _) = (alt((tag("StateVars"), tag("StateVar"))), ws1).parse(s)?;
+    let space = |s| take_while(|c| c == ' ' || c == '\t')(s);
+    cut(separated_list1(
+        (space, char(','), space),
+        expect("a state variable name", parse_state_var_name),
+    )).parse(s)
 }
 
 enum FactOrStateVar {
@@ -221,30 +440,284 @@ enum FactOrStateVar {
     StateVar(String),
 }
 
-pub fn parse_module(s: Span) -> IResult<Span, Module> {
-    let (s, _) = position(s)?;
+fn is_facts_terminator(s: Span) -> bool {
+    let fragment = s.fragment();
+    fragment.starts_with("End Facts")
+        || fragment.starts_with("Begin Initial State")
+        || fragment.starts_with("Begin Global")
+}
+
+fn parse_fact_item(s: Span) -> IResult<Span, FactOrStateVar, ParseError> {
+    let (s, item) = alt((
+        |s| {
+            let (s, sv) = parse_state_var(s)?;
+            Ok((s, FactOrStateVar::StateVar(sv)))
+        },
+        |s| {
+            let (s, term) = parse_term(s)?;
+            Ok((s, FactOrStateVar::Fact(term)))
+        },
+    )).parse(s)?;
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line after the fact", line_ending).parse(s)?;
+    Ok((s, item))
+}
+
+fn parse_facts_body(s: Span) -> IResult<Span, Vec<FactOrStateVar>, ParseError> {
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line after `Begin Facts:`", line_ending).parse(s)?;
+
+    let (s, items) = many0(|s| {
+        let (s, _) = ws0(s)?;
+        if is_facts_terminator(s) {
+            return Err(nom::Err::Error(ParseError::new(s)));
+        }
+        cut(parse_fact_item).parse(s)
+    }).parse(s)?;
+
     let (s, _) = ws0(s)?;
+    let (s, _) = expect("`End Facts`", tag("End Facts")).parse(s)?;
+    Ok((s, items))
+}
 
-    let (s, _) = tag("Begin Facts:")(s)?;
-    let (s, _) = line_ending(s)?;
+enum InitialItem {
+    StateVars(Vec<StateVarDecl>),
+    Constraint(Term),
+}
+
+fn parse_initial_item(s: Span) -> IResult<Span, InitialItem, ParseError> {
+    let (s, item) = alt((
+        map(parse_state_vars, InitialItem::StateVars),
+        map(parse_term, InitialItem::Constraint),
+    )).parse(s)?;
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line", line_ending).parse(s)?;
+    Ok((s, item))
+}
+
+pub(crate) fn parse_initial_state(s: Span) -> IResult<Span, InitialState, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = tag("Begin Initial State:")(s)?;
+    cut(within(Construct::InitialState, move |s| parse_initial_state_body(s, start))).parse(s)
+}
+
+fn parse_initial_state_body<'a>(s: Span<'a>, start: Span<'a>) -> IResult<Span<'a>, InitialState, ParseError> {
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line after `Begin Initial State:`", line_ending).parse(s)?;
 
     let (s, items) = many0(|s| {
         let (s, _) = ws0(s)?;
+        if s.fragment().starts_with("End") || s.fragment().starts_with("Begin") {
+            return Err(nom::Err::Error(ParseError::new(s)));
+        }
+        cut(parse_initial_item).parse(s)
+    }).parse(s)?;
+
+    let (s, _) = ws0(s)?;
+    let (s, _) = expect("`End Initial State`", tag("End Initial State")).parse(s)?;
+
+    let mut state_vars = Vec::new();
+    let mut constraints = Vec::new();
+    for item in items {
+        match item {
+            InitialItem::StateVars(decls) => state_vars.extend(decls),
+            InitialItem::Constraint(term) => constraints.push(term),
+        }
+    }
+    Ok((s, InitialState {
+        state_vars,
+        constraints,
+        span: span_between(start, s),
+    }))
+}
+
+fn parse_global_rules(s: Span) -> IResult<Span, Vec<Rule>, ParseError> {
+    let (s, _) = skip_trailing_comment(s)?;
+    let (s, _) = expect("end of line after `Begin Global:`", line_ending).parse(s)?;
+
+    let (s, global_rules) = many0(|s| {
+        let (s, _) = ws0(s)?;
+        let (s, rule) = parse_rule(s)?;
+        let (s, _) = ws0(s)?;
+        Ok((s, rule))
+    }).parse(s)?;
+
+    let (s, _) = ws0(s)?;
+    let (s, _) = expect("a rule or `End Global`", tag("End Global")).parse(s)?;
+    Ok((s, global_rules))
+}
+
+pub fn parse_module(s: Span) -> IResult<Span, Module, ParseError> {
+    within(Construct::Module, parse_module_body).parse(s)
+}
+
+fn parse_include(s: Span) -> IResult<Span, Include, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = (alt((tag("Include"), tag("Import"))), ws1).parse(s)?;
+
+    cut(within(Construct::Include, move |s| {
+        let (s, path) = expect(
+            "a quoted file path",
+            delimited(char('"'), take_till(|c| c == '"' || c == '\n'), char('"')),
+        ).parse(s)?;
+        let span = span_between(start, s);
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the include", line_ending).parse(s)?;
+        Ok((s, Include { path: path.fragment().to_string(), span }))
+    })).parse(s)
+}
+
+fn parse_use(s: Span) -> IResult<Span, Use, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = (tag("Use"), ws1).parse(s)?;
+
+    cut(within(Construct::Use, move |s| {
+        let (s, module) = expect("a library module name", parse_identifier).parse(s)?;
+        let span = span_between(start, s);
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the directive", line_ending).parse(s)?;
+        Ok((s, Use { module: module.to_string(), span }))
+    })).parse(s)
+}
+
+fn parse_type(s: Span) -> IResult<Span, Type, ParseError> {
+    let (rest, name) = parse_identifier(s)?;
+    if !name.chars().next().unwrap().is_uppercase() {
+        return Err(nom::Err::Failure(ParseError::expected(s, "a capitalized type name")));
+    }
+    Ok((rest, Type::from_name(name)))
+}
+
+fn parse_relation_decl(s: Span) -> IResult<Span, RelationDecl, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = (tag("Relation"), ws1).parse(s)?;
+
+    cut(within(Construct::RelationDecl, move |s| {
+        let space = |s| take_while(|c| c == ' ' || c == '\t')(s);
+        let (s, name) = expect("a relation name", parse_identifier).parse(s)?;
+        let (s, _) = (space, expect("`(`", char('('))).parse(s)?;
+        let (s, _) = space(s)?;
+        let (s, params) = separated_list0((space, char(','), space), expect("a type", parse_type)).parse(s)?;
+        let (s, _) = (space, expect("`,` or `)`", char(')'))).parse(s)?;
+        let span = span_between(start, s);
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the declaration", line_ending).parse(s)?;
+        Ok((s, RelationDecl {
+            name: name.to_string(),
+            params,
+            span,
+        }))
+    })).parse(s)
+}
+
+fn parse_mode(s: Span) -> IResult<Span, Mode, ParseError> {
+    if s.fragment().starts_with(|c: char| c.is_alphanumeric()) {
+        return Err(nom::Err::Failure(ParseError::expected(s, "`+`, `-` or `?`")));
+    }
+    alt((
+        map(char('+'), |_| Mode::In),
+        map(char('-'), |_| Mode::Out),
+        map(char('?'), |_| Mode::Any),
+    )).parse(s)
+}
+
+fn parse_mode_decl(s: Span) -> IResult<Span, ModeDecl, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = (tag("Mode"), ws1).parse(s)?;
+
+    cut(within(Construct::ModeDecl, move |s| {
+        let space = |s| take_while(|c| c == ' ' || c == '\t')(s);
+        let (s, name) = expect("a relation name", parse_identifier).parse(s)?;
+        let (s, _) = (space, expect("`(`", char('('))).parse(s)?;
+        let (s, _) = space(s)?;
+        let (s, params) = separated_list0((space, char(','), space), expect("`+`, `-` or `?`", parse_mode)).parse(s)?;
+        let (s, _) = (space, expect("`,` or `)`", char(')'))).parse(s)?;
+        let span = span_between(start, s);
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the declaration", line_ending).parse(s)?;
+        Ok((s, ModeDecl {
+            name: name.to_string(),
+            params,
+            span,
+        }))
+    })).parse(s)
+}
+
+fn parse_table_decl(s: Span) -> IResult<Span, TableDecl, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = (tag("Table"), ws1).parse(s)?;
+
+    cut(within(Construct::TableDecl, move |s| {
+        let (s, name) = expect("a relation name", parse_identifier).parse(s)?;
+        let span = span_between(start, s);
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the declaration", line_ending).parse(s)?;
+        Ok((s, TableDecl { name: name.to_string(), span }))
+    })).parse(s)
+}
+
+fn parse_cost_decl(s: Span) -> IResult<Span, CostDecl, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = (tag("Cost"), ws1).parse(s)?;
+
+    cut(within(Construct::CostDecl, move |s| {
+        let space = |s| take_while(|c| c == ' ' || c == '\t')(s);
+        let (s, head) = expect("a relation call", parse_app).parse(s)?;
+        let (s, _) = (space, expect("`=`", char('=')), space).parse(s)?;
+        let (s, cost) = parse_term(s)?;
+        let span = span_between(start, s);
+        let (s, _) = skip_trailing_comment(s)?;
+        let (s, _) = expect("end of line after the declaration", line_ending).parse(s)?;
+        Ok((s, CostDecl { head, cost, span }))
+    })).parse(s)
+}
+
+enum PreambleItem {
+    Include(Include),
+    Use(Use),
+    Relation(RelationDecl),
+    Mode(ModeDecl),
+    Table(TableDecl),
+    Cost(CostDecl),
+}
+
+fn parse_module_body(s: Span) -> IResult<Span, Module, ParseError> {
+    let (s, _) = ws0(s)?;
+    let (s, start) = position(s)?;
+
+    let (s, preamble) = many0(|s| {
         let (s, item) = alt((
-            |s| {
-                let (s, sv) = parse_state_var(s)?;
-                Ok((s, FactOrStateVar::StateVar(sv)))
-            },
-            |s| {
-                let (s, term) = parse_term(s)?;
-                Ok((s, FactOrStateVar::Fact(term)))
-            },
+            map(parse_include, PreambleItem::Include),
+            map(parse_use, PreambleItem::Use),
+            map(parse_relation_decl, PreambleItem::Relation),
+            map(parse_mode_decl, PreambleItem::Mode),
+            map(parse_table_decl, PreambleItem::Table),
+            map(parse_cost_decl, PreambleItem::Cost),
         )).parse(s)?;
-        let (s, _) = skip_trailing_comment(s)?;
-        let (s, _) = line_ending(s)?;
+        let (s, _) = ws0(s)?;
         Ok((s, item))
     }).parse(s)?;
 
+    let mut includes = Vec::new();
+    let mut uses = Vec::new();
+    let mut relations = Vec::new();
+    let mut modes = Vec::new();
+    let mut tables = Vec::new();
+    let mut costs = Vec::new();
+    for item in preamble {
+        match item {
+            PreambleItem::Include(include) => includes.push(include),
+            PreambleItem::Use(use_) => uses.push(use_),
+            PreambleItem::Relation(relation) => relations.push(relation),
+            PreambleItem::Mode(mode) => modes.push(mode),
+            PreambleItem::Table(table) => tables.push(table),
+            PreambleItem::Cost(cost) => costs.push(cost),
+        }
+    }
+
+    let (s, _) = expect("`Begin Facts:`", tag("Begin Facts:")).parse(s)?;
+    let (s, items) = cut(within(Construct::Facts, parse_facts_body)).parse(s)?;
+
     let mut state_vars = Vec::new();
     let mut facts = Vec::new();
     for item in items {
@@ -254,23 +727,18 @@ pub fn parse_module(s: Span) -> IResult<Span, Module> {
         }
     }
 
-    let (s, _) = ws0(s)?;
-    let (s, _) = tag("End Facts")(s)?;
     let (s, _) = ws1(s)?;
 
-    let (s, _) = tag("Begin Global:")(s)?;
-    let (s, _) = line_ending(s)?;
-
-    let (s, _) = position(s)?;
-    let (s, global_rules) = many0(|s| {
-        let (s, _) = ws0(s)?;
-        let (s, rule) = parse_rule(s)?;
-        let (s, _) = ws0(s)?;
-        Ok((s, rule))
+    let (s, initial_state) = opt(|s| {
+        let (s, initial) = parse_initial_state(s)?;
+        let (s, _) = ws1(s)?;
+        Ok((s, initial))
     }).parse(s)?;
 
-    let (s, _) = ws0(s)?;
-    let (s, _) = tag("End Global")(s)?;
+    let (s, global_start) = position(s)?;
+    let (s, _) = expect("`Begin Global:`", tag("Begin Global:")).parse(s)?;
+    let (s, global_rules) = cut(within(Construct::Global, parse_global_rules)).parse(s)?;
+    let global_span = span_between(global_start, s);
     let (s, _) = ws0(s)?;
 
     let global_stage = Stage {
@@ -278,6 +746,7 @@ pub fn parse_module(s: Span) -> IResult<Span, Module> {
         rules: global_rules,
         state_constraints: Vec::new(),
         draw_directives: Vec::new(),
+        span: global_span,
     };
 
     let (s, stages) = many0(|s| {
@@ -287,11 +756,21 @@ pub fn parse_module(s: Span) -> IResult<Span, Module> {
         Ok((s, stage))
     }).parse(s)?;
 
+    let end = stages.last().unwrap_or(&global_stage).span.end;
+
     Ok((s, Module {
+        includes,
+        uses,
+        relations,
+        modes,
+        tables,
+        costs,
         state_vars,
         facts,
+        initial_state,
         global_stage,
         stages,
+        span: SourceSpan::new(source_pos(start), end),
     }))
 }
 
@@ -303,7 +782,7 @@ fn is_alphanumeric_or_underscore(c: char) -> bool {
     c.is_alphanum() || c == '_'
 }
 
-fn parse_identifier(s: Span<'_>) -> IResult<Span<'_>, &str> {
+fn parse_identifier(s: Span<'_>) -> IResult<Span<'_>, &str, ParseError> {
     let (s, result) = recognize((
         take_while1(is_alpha_or_underscore),
         take_while(is_alphanumeric_or_underscore),
@@ -311,8 +790,9 @@ fn parse_identifier(s: Span<'_>) -> IResult<Span<'_>, &str> {
     Ok((s, *result.fragment()))
 }
 
-fn parse_int(s: Span) -> IResult<Span, Term> {
-    let (s, _) = position(s)?;
+fn parse_int(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
+    let literal = s;
     let (s, sign) = opt(char('-')).parse(s)?;
     let (s, digits) = digit1(s)?;
 
@@ -321,15 +801,18 @@ fn parse_int(s: Span) -> IResult<Span, Term> {
     } else {
         (*digits.fragment()).to_string()
     };
-    let val = val_str.parse::<i32>().unwrap();
+    let val = val_str.parse::<i32>().map_err(|_| {
+        nom::Err::Failure(ParseError::expected(literal, "an integer that fits in 32 bits"))
+    })?;
 
     Ok((s, Term {
         contents: TermContents::Int { val },
+        span: span_between(start, s),
     }))
 }
 
-fn parse_float(s: Span) -> IResult<Span, Term> {
-    let (s, _) = position(s)?;
+fn parse_float(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
     let (s, sign) = opt(char('-')).parse(s)?;
     let (s, (int_part, _, frac_part)) = (digit1, char('.'), digit1).parse(s)?;
 
@@ -342,94 +825,160 @@ fn parse_float(s: Span) -> IResult<Span, Term> {
 
     Ok((s, Term {
         contents: TermContents::Float { val },
+        span: span_between(start, s),
     }))
 }
 
-fn parse_var(s: Span) -> IResult<Span, Term> {
-    let (s, _) = position(s)?;
+fn parse_var(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
     let (s, name) = parse_identifier(s)?;
 
-    if !name.chars().next().unwrap().is_uppercase() {
-        return Err(nom::Err::Error(nom::error::Error::new(s, nom::error::ErrorKind::Verify)));
+    // `_`-prefixed names are variables the lint pass expects to occur once.
+    let first = name.chars().next().unwrap();
+    if !first.is_uppercase() && first != '_' {
+        return Err(nom::Err::Error(ParseError::new(start)));
     }
 
     Ok((s, Term {
         contents: TermContents::Var { name: name.to_string() },
+        span: span_between(start, s),
     }))
 }
 
-fn parse_atom(s: Span) -> IResult<Span, Term> {
-    let (s, _) = position(s)?;
+fn parse_atom(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
     let (s, text) = parse_identifier(s)?;
 
     if !text.chars().next().unwrap().is_lowercase() {
-        return Err(nom::Err::Error(nom::error::Error::new(s, nom::error::ErrorKind::Verify)));
+        return Err(nom::Err::Error(ParseError::new(start)));
     }
 
     Ok((s, Term {
         contents: TermContents::Atom { text: text.to_string() },
+        span: span_between(start, s),
     }))
 }
 
-fn parse_app(s: Span) -> IResult<Span, Term> {
-    let (s, _) = position(s)?;
-    let (s, rel_name) = parse_identifier(s)?;
-    let (s, _) = ws0(s)?;
-
-    fn ws_term(s: Span) -> IResult<Span, Term> {
-        let (s, _) = ws0(s)?;
-        let (s, term) = parse_term(s)?;
-        let (s, _) = ws0(s)?;
-        Ok((s, term))
-    }
-
-    fn ws_comma(s: Span) -> IResult<Span, char> {
-        let (s, _) = ws0(s)?;
-        let (s, c) = char(',')(s)?;
-        let (s, _) = ws0(s)?;
-        Ok((s, c))
+fn parse_app(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
+    // Backtrack from the start so that a bare identifier is reported as
+    // such rather than as a missing `(`.
+    let (s, (rel_name, _, _)) = (parse_identifier, ws0, char('('))
+        .parse(s)
+        .map_err(|_| nom::Err::Error(ParseError::new(start)))?;
+
+    fn parse_args(s: Span) -> IResult<Span, Vec<Term>, ParseError> {
+        let (mut s, _) = ws0(s)?;
+        let mut args = Vec::new();
+        if !s.fragment().starts_with(')') {
+            loop {
+                let (s2, term) = parse_term(s)?;
+                let (s2, _) = ws0(s2)?;
+                args.push(term);
+                match char::<_, ParseError>(',')(s2) {
+                    Ok((s3, _)) => s = ws0(s3)?.0,
+                    Err(_) => {
+                        s = s2;
+                        break;
+                    }
+                }
+            }
+        }
+        let (s, _) = expect("`,` or `)`", char(')')).parse(s)?;
+        Ok((s, args))
     }
 
-    let (s, args) = delimited(
-        char('('),
-        separated_list0(ws_comma, ws_term),
-        char(')'),
-    ).parse(s)?;
+    let (s, args) = cut(parse_args).parse(s)?;
 
     let rel = Rel::UserRel { name: rel_name.to_string() };
 
     Ok((s, Term {
         contents: TermContents::App { rel, args },
+        span: span_between(start, s),
     }))
 }
 
-fn parse_paren_term(s: Span) -> IResult<Span, Term> {
+fn parse_paren_term(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
     let (s, _) = char('(')(s)?;
-    let (s, _) = ws0(s)?;
-    let (s, term) = parse_term(s)?;
-    let (s, _) = ws0(s)?;
-    let (s, _) = char(')')(s)?;
+    let (s, (_, mut term, _, _)) = cut((ws0, parse_term, ws0, expect("`)`", char(')')))).parse(s)?;
+    term.span = span_between(start, s);
     Ok((s, term))
 }
 
-fn parse_primary(s: Span) -> IResult<Span, Term> {
-    alt((
+/// Parses a list literal, `[a, b, c]` or `[H | T]`, into `cons` cells
+/// ending in `nil` or the given tail. Elements are parsed above `|`, so a
+/// disjunction inside a list needs parentheses.
+fn parse_list(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
+    let (s, _) = char('[')(s)?;
+    cut(move |s| parse_list_body(s, start)).parse(s)
+}
+
+fn parse_list_body<'a>(s: Span<'a>, start: Span<'a>) -> IResult<Span<'a>, Term, ParseError> {
+    let (mut s, _) = ws0(s)?;
+    let mut elements = Vec::new();
+    let mut tail = None;
+    if !s.fragment().starts_with(']') {
+        loop {
+            let (s2, element) = parse_and(s)?;
+            let (s2, _) = ws0(s2)?;
+            elements.push(element);
+            if let Ok((s3, _)) = char::<_, ParseError>(',')(s2) {
+                s = ws0(s3)?.0;
+            } else if let Ok((s3, _)) = char::<_, ParseError>('|')(s2) {
+                let (s3, _) = ws0(s3)?;
+                let (s3, rest) = parse_and(s3)?;
+                tail = Some(rest);
+                s = ws0(s3)?.0;
+                break;
+            } else {
+                s = s2;
+                break;
+            }
+        }
+    }
+    let expected = if tail.is_some() || elements.is_empty() { "`]`" } else { "`,`, `|` or `]`" };
+    let (s, _) = expect(expected, char(']')).parse(s)?;
+
+    let span = span_between(start, s);
+    let mut list = tail.unwrap_or_else(|| Term {
+        contents: TermContents::Atom { text: "nil".to_string() },
+        span: span.clone(),
+    });
+    for (i, element) in elements.into_iter().enumerate().rev() {
+        let span = if i == 0 { span.clone() } else { element.span.to(&span) };
+        list = Term {
+            contents: TermContents::App {
+                rel: user_rel("cons"),
+                args: vec![element, list],
+            },
+            span,
+        };
+    }
+    Ok((s, list))
+}
+
+fn parse_primary(s: Span) -> IResult<Span, Term, ParseError> {
+    expect("a term", alt((
         parse_float,
         parse_int,
         parse_app,
         parse_var,
         parse_atom,
         parse_paren_term,
-    )).parse(s)
+        parse_list,
+    ))).parse(s)
 }
 
-fn parse_not_prefix(s: Span) -> IResult<Span, ()> {
+fn parse_not_prefix(s: Span) -> IResult<Span, (), ParseError> {
     let (s, _) = alt((char('¬'), char('!'))).parse(s)?;
     let (s, _) = ws0(s)?;
     Ok((s, ()))
 }
 
-fn parse_unary(s: Span) -> IResult<Span, Term> {
+fn parse_unary(s: Span) -> IResult<Span, Term, ParseError> {
+    let (s, start) = position(s)?;
     let (s, nots) = many0(parse_not_prefix).parse(s)?;
     let (s, mut term) = parse_primary(s)?;
 
@@ -439,13 +988,14 @@ fn parse_unary(s: Span) -> IResult<Span, Term> {
                 rel: user_rel("not"),
                 args: vec![term],
             },
+            span: span_between(start, s),
         };
     }
 
     Ok((s, term))
 }
 
-fn parse_cmp_op(s: Span) -> IResult<Span, Rel> {
+fn parse_cmp_op(s: Span) -> IResult<Span, Rel, ParseError> {
     alt((
         map(tag(".=="), |_| user_rel("real_eq")),
         map(tag(".<="), |_| user_rel("real_le")),
@@ -460,109 +1010,129 @@ fn parse_cmp_op(s: Span) -> IResult<Span, Rel> {
     )).parse(s)
 }
 
-fn parse_eq_op(s: Span) -> IResult<Span, Rel> {
+fn parse_eq_op(s: Span) -> IResult<Span, Rel, ParseError> {
     let (s, _) = char('=')(s)?;
     // Make sure this isn't `==` (int_eq)
     if s.fragment().starts_with('=') {
-        return Err(nom::Err::Error(nom::error::Error::new(s, nom::error::ErrorKind::Char)));
+        return Err(nom::Err::Error(ParseError::new(s)));
     }
     Ok((s, user_rel("eq")))
 }
 
-fn parse_eq(s: Span) -> IResult<Span, Term> {
-    let (mut s, mut left) = parse_unary(s)?;
+/// Parses `operand (op operand)*`, associating to the left. An operator
+/// at the start of a new line only continues the term if an operand follows
+/// it, so that a malformed line after a term (such as a `====` divider) is
+/// reported on its own.
+fn parse_binary_level<'a>(
+    s: Span<'a>,
+    operand: fn(Span<'a>) -> IResult<Span<'a>, Term, ParseError>,
+    op: fn(Span<'a>) -> IResult<Span<'a>, Rel, ParseError>,
+) -> IResult<Span<'a>, Term, ParseError> {
+    let (mut s, mut left) = operand(s)?;
     loop {
         let (s2, _) = ws0(s)?;
-        if let Ok((s3, rel)) = parse_eq_op(s2) {
-            let (s4, _) = ws0(s3)?;
-            let (s5, right) = parse_unary(s4)?;
-            left = Term {
-                contents: TermContents::App {
-                    rel,
-                    args: vec![left, right],
-                },
-            };
-            s = s5;
-        } else {
+        let Ok((s3, rel)) = op(s2) else {
             break;
+        };
+        let (s4, _) = ws0(s3)?;
+        let right = if s2.location_line() == s.location_line() {
+            cut(operand).parse(s4)
+        } else {
+            operand(s4)
+        };
+        match right {
+            Ok((s5, right)) => {
+                left = binary(rel, left, right);
+                s = s5;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
         }
     }
     Ok((s, left))
 }
 
-fn parse_cmp(s: Span) -> IResult<Span, Term> {
-    let (mut s, mut left) = parse_eq(s)?;
-    loop {
-        let (s2, _) = ws0(s)?;
-        if let Ok((s3, rel)) = parse_cmp_op(s2) {
-            let (s4, _) = ws0(s3)?;
-            let (s5, right) = parse_eq(s4)?;
-            left = Term {
-                contents: TermContents::App {
-                    rel,
-                    args: vec![left, right],
-                },
-            };
-            s = s5;
-        } else {
-            break;
-        }
+fn arith_rel(op: &str) -> Rel {
+    Rel::SMTRel { name: op.to_string() }
+}
+
+fn parse_mul_op(s: Span) -> IResult<Span, Rel, ParseError> {
+    alt((
+        map(char('*'), |_| arith_rel("*")),
+        map(char('/'), |_| arith_rel("/")),
+    )).parse(s)
+}
+
+fn parse_add_op(s: Span) -> IResult<Span, Rel, ParseError> {
+    let (rest, op) = alt((char('+'), char('-'))).parse(s)?;
+    // A second `-` starts a rule divider, not a subtraction.
+    if op == '-' && rest.fragment().starts_with('-') {
+        return Err(nom::Err::Error(ParseError::new(s)));
     }
-    Ok((s, left))
+    Ok((rest, arith_rel(&op.to_string())))
+}
+
+fn parse_mul(s: Span) -> IResult<Span, Term, ParseError> {
+    parse_binary_level(s, parse_unary, parse_mul_op)
+}
+
+fn parse_add(s: Span) -> IResult<Span, Term, ParseError> {
+    parse_binary_level(s, parse_mul, parse_add_op)
+}
+
+fn parse_eq(s: Span) -> IResult<Span, Term, ParseError> {
+    parse_binary_level(s, parse_add, parse_eq_op)
 }
 
-fn parse_and_op(s: Span) -> IResult<Span, Rel> {
+fn parse_cmp(s: Span) -> IResult<Span, Term, ParseError> {
+    parse_binary_level(s, parse_eq, parse_cmp_op)
+}
+
+fn parse_and_op(s: Span) -> IResult<Span, Rel, ParseError> {
     let (s, _) = alt((char('∧'), char('&'))).parse(s)?;
     Ok((s, user_rel("and")))
 }
 
-fn parse_and(s: Span) -> IResult<Span, Term> {
-    let (mut s, mut left) = parse_cmp(s)?;
-    loop {
-        let (s2, _) = ws0(s)?;
-        if let Ok((s3, rel)) = parse_and_op(s2) {
-            let (s4, _) = ws0(s3)?;
-            let (s5, right) = parse_cmp(s4)?;
-            left = Term {
-                contents: TermContents::App {
-                    rel,
-                    args: vec![left, right],
-                },
-            };
-            s = s5;
-        } else {
-            break;
-        }
-    }
-    Ok((s, left))
+fn parse_and(s: Span) -> IResult<Span, Term, ParseError> {
+    parse_binary_level(s, parse_cmp, parse_and_op)
 }
 
-fn parse_or_op(s: Span) -> IResult<Span, Rel> {
+fn parse_or_op(s: Span) -> IResult<Span, Rel, ParseError> {
     let (s, _) = alt((char('∨'), char('|'))).parse(s)?;
     Ok((s, user_rel("or")))
 }
 
-fn parse_or(s: Span) -> IResult<Span, Term> {
-    let (mut s, mut left) = parse_and(s)?;
-    loop {
-        let (s2, _) = ws0(s)?;
-        if let Ok((s3, rel)) = parse_or_op(s2) {
-            let (s4, _) = ws0(s3)?;
-            let (s5, right) = parse_and(s4)?;
-            left = Term {
-                contents: TermContents::App {
-                    rel,
-                    args: vec![left, right],
-                },
-            };
-            s = s5;
-        } else {
-            break;
-        }
+fn parse_or(s: Span) -> IResult<Span, Term, ParseError> {
+    parse_binary_level(s, parse_and, parse_or_op)
+}
+
+pub fn parse_term(s: Span) -> IResult<Span, Term, ParseError> {
+    within(Construct::Term, parse_or).parse(s)
+}
+
+/// Parses a file holding nothing but rules, such as a standard library
+/// module.
+pub fn parse_rules(source: &str) -> Result<Vec<Rule>, ParseError> {
+    let (rest, rules) = many0(|s| {
+        let (s, _) = ws0(s)?;
+        parse_rule(s)
+    }).parse(source.into()).finish()?;
+    let (rest, _) = ws0(rest).finish()?;
+    if !rest.fragment().is_empty() {
+        return Err(ParseError::expected(rest, "a rule or end of input"));
     }
-    Ok((s, left))
+    Ok(rules)
 }
 
-pub fn parse_term(s: Span) -> IResult<Span, Term> {
-    parse_or(s)
+/// Parses a whole source file, failing if anything but whitespace and
+/// comments follows the module.
+pub fn parse_source(source: &str) -> Result<Module, ParseError> {
+    let (rest, module) = parse_module(source.into()).finish()?;
+    let (rest, _) = ws0(rest).finish()?;
+    if !rest.fragment().is_empty() {
+        let mut err = ParseError::expected(rest, "`Begin Stage` or end of input");
+        err.push_context(Construct::Module);
+        return Err(err);
+    }
+    Ok(module)
 }
@@ -0,0 +1,182 @@
+use std::fmt;
+
+use nom::error::ErrorKind;
+
+use crate::ast::{SourcePos, SourceSpan};
+use crate::diagnostic::Diagnostic;
+
+use super::Span;
+
+/// The syntactic construct the parser was in the middle of when it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construct {
+    Module,
+    Include,
+    Use,
+    RelationDecl,
+    ModeDecl,
+    TableDecl,
+    CostDecl,
+    Facts,
+    InitialState,
+    Global,
+    Rule,
+    Stage,
+    StateConstraints,
+    DrawDirective,
+    Term,
+}
+
+impl Construct {
+    fn describe(self) -> &'static str {
+        match self {
+            Construct::Module => "module",
+            Construct::Include => "include directive",
+            Construct::Use => "use directive",
+            Construct::RelationDecl => "relation declaration",
+            Construct::ModeDecl => "mode declaration",
+            Construct::TableDecl => "table declaration",
+            Construct::CostDecl => "cost declaration",
+            Construct::Facts => "facts block",
+            Construct::InitialState => "initial state block",
+            Construct::Global => "global block",
+            Construct::Rule => "rule",
+            Construct::Stage => "stage",
+            Construct::StateConstraints => "state constraints",
+            Construct::DrawDirective => "draw directive",
+            Construct::Term => "term",
+        }
+    }
+}
+
+/// A syntax error with enough context to explain itself: what was being
+/// parsed, what the parser expected there, and what it found instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Enclosing constructs, innermost first.
+    pub contexts: Vec<Construct>,
+    pub expected: Option<Box<str>>,
+    pub found: Box<str>,
+    pub span: SourceSpan,
+}
+
+impl ParseError {
+    pub fn new(input: Span) -> Self {
+        let (found, span) = describe_input(input);
+        Self {
+            contexts: Vec::new(),
+            expected: None,
+            found: found.into_boxed_str(),
+            span,
+        }
+    }
+
+    pub fn expected(input: Span, expected: impl Into<String>) -> Self {
+        Self {
+            expected: Some(expected.into().into_boxed_str()),
+            ..Self::new(input)
+        }
+    }
+
+    pub fn construct(&self) -> Option<Construct> {
+        self.contexts.first().copied()
+    }
+
+    pub fn message(&self) -> String {
+        let mut message = match &self.expected {
+            Some(expected) => format!("expected {}, found {}", expected, self.found),
+            None => format!("unexpected {}", self.found),
+        };
+        let mut contexts = self.contexts.iter();
+        if let Some(construct) = contexts.next() {
+            message.push_str(&format!(" while parsing {}", construct.describe()));
+        }
+        if let Some(outer) = contexts.next().filter(|c| **c != Construct::Module) {
+            message.push_str(&format!(" in {}", outer.describe()));
+        }
+        message
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.message(), self.span.clone())
+    }
+
+    /// Renders the error with a caret snippet pointing into `source`.
+    pub fn render(&self, source: &str) -> String {
+        self.to_diagnostic().render(source)
+    }
+
+    pub(super) fn push_context(&mut self, construct: Construct) {
+        if self.contexts.last() != Some(&construct) {
+            self.contexts.push(construct);
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_diagnostic())
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(err: ParseError) -> Self {
+        err.to_diagnostic()
+    }
+}
+
+impl<'a> nom::error::ParseError<Span<'a>> for ParseError {
+    fn from_error_kind(input: Span<'a>, _kind: ErrorKind) -> Self {
+        Self::new(input)
+    }
+
+    fn append(_input: Span<'a>, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    /// Between two failed alternatives, report the one that got further.
+    fn or(self, other: Self) -> Self {
+        match self.span.start.offset.cmp(&other.span.start.offset) {
+            std::cmp::Ordering::Greater => self,
+            std::cmp::Ordering::Less => other,
+            std::cmp::Ordering::Equal if self.expected.is_some() && other.expected.is_none() => self,
+            std::cmp::Ordering::Equal => other,
+        }
+    }
+}
+
+/// Describes the token at the start of `input` and the span it covers.
+fn describe_input(input: Span) -> (String, SourceSpan) {
+    let fragment: &str = input.fragment();
+    let start = SourcePos {
+        offset: input.location_offset(),
+        line: input.location_line(),
+        column: input.get_utf8_column() as u32,
+    };
+
+    let found = if fragment.is_empty() {
+        "end of input".to_string()
+    } else if fragment.starts_with(['\n', '\r']) {
+        "end of line".to_string()
+    } else {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+        let is_delimiter = |c: char| c.is_whitespace() || is_word(c) || "(),".contains(c);
+        let first = fragment.chars().next().unwrap();
+        let len = if is_word(first) {
+            fragment.find(|c: char| !is_word(c)).unwrap_or(fragment.len())
+        } else if "(),".contains(first) {
+            1
+        } else {
+            fragment.find(is_delimiter).unwrap_or(fragment.len())
+        };
+        let token = &fragment[..len];
+        let end = SourcePos {
+            offset: start.offset + token.len(),
+            line: start.line,
+            column: start.column + token.chars().count() as u32,
+        };
+        return (format!("`{}`", token), SourceSpan::new(start, end));
+    };
+
+    (found, SourceSpan::new(start, start))
+}
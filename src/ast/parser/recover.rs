@@ -0,0 +1,393 @@
+use nom::bytes::complete::tag;
+use nom::character::complete::line_ending;
+use nom::{Input, Parser};
+
+use crate::ast::{InitialState, Module, SourceSpan, Stage, Term};
+
+use super::{
+    expect, is_facts_terminator, parse_draw_directive, parse_fact_item, parse_identifier, parse_include,
+    parse_initial_state, parse_mode_decl, parse_relation_decl, parse_table_decl, parse_cost_decl, parse_rule, parse_use, parse_stage_end, parse_stage_header, parse_state_constraints,
+    skip_trailing_comment, source_pos, span_between, stage_item_expectation, within, ws0, ws1,
+    Construct, FactOrStateVar, ParseError, Span,
+};
+
+/// Lines starting with one of these are where parsing resumes after a
+/// syntax error.
+const SYNC_KEYWORDS: [&str; 4] = ["Rule", "Begin Stage", "End Stage", "End Global"];
+
+const FACTS: &[Construct] = &[Construct::Facts, Construct::Module];
+const GLOBAL: &[Construct] = &[Construct::Global, Construct::Module];
+const STAGE: &[Construct] = &[Construct::Stage, Construct::Module];
+
+/// Parses a module without stopping at the first syntax error. After an
+/// error the parser skips ahead to the next `Rule`, `Begin Stage`,
+/// `End Stage` or `End Global` line (or the next line, inside a facts
+/// block) and carries on. Returns everything that did parse along with
+/// every error, in source order; the module is complete exactly when no
+/// errors are returned.
+pub fn parse_module_recovering(source: &str) -> (Module, Vec<ParseError>) {
+    let mut recovery = Recovery::default();
+    let module = recovery.module(source.into());
+    (module, recovery.errors)
+}
+
+#[derive(Default)]
+struct Recovery {
+    errors: Vec<ParseError>,
+}
+
+fn skip_ws(s: Span) -> Span {
+    ws0(s).map_or(s, |(s, _)| s)
+}
+
+/// Skips past the end of the line `s` is on.
+fn skip_line(s: Span) -> Span {
+    let fragment = s.fragment();
+    let len = fragment.find('\n').map_or(fragment.len(), |i| i + 1);
+    s.take_from(len)
+}
+
+/// Skips the rest of the line `s` is on, then whole lines until one whose
+/// first token satisfies `stop`.
+fn skip_lines_until(s: Span, stop: impl Fn(&str) -> bool) -> Span {
+    let mut s = skip_ws(skip_line(s));
+    while !s.fragment().is_empty() && !stop(s.fragment()) {
+        s = skip_ws(skip_line(s));
+    }
+    s
+}
+
+fn skip_to_sync(s: Span) -> Span {
+    skip_lines_until(s, |f| SYNC_KEYWORDS.iter().any(|k| f.starts_with(k)))
+}
+
+fn into_error(err: nom::Err<ParseError>) -> ParseError {
+    match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e,
+        nom::Err::Incomplete(_) => unreachable!("complete parsers never report incomplete input"),
+    }
+}
+
+impl Recovery {
+    fn record(&mut self, err: nom::Err<ParseError>, contexts: &[Construct]) {
+        let mut err = into_error(err);
+        for &construct in contexts {
+            err.push_context(construct);
+        }
+        // Several recovery paths can trip over the same token; report it once.
+        let offset = err.span.start.offset;
+        if self.errors.last().is_some_and(|last| last.span.start.offset == offset) {
+            return;
+        }
+        self.errors.push(err);
+    }
+
+    fn expected(&mut self, s: Span, expected: impl Into<String>, contexts: &[Construct]) {
+        self.record(nom::Err::Error(ParseError::expected(s, expected)), contexts);
+    }
+
+    /// Consumes the rest of a block header line such as `Begin Facts:`.
+    fn header_end<'a>(&mut self, s: Span<'a>, expected: &'static str, contexts: &[Construct]) -> Span<'a> {
+        match (skip_trailing_comment, expect(expected, line_ending)).parse(s) {
+            Ok((s, _)) => s,
+            Err(e) => {
+                self.record(e, contexts);
+                skip_line(s)
+            }
+        }
+    }
+
+    fn module(&mut self, s: Span) -> Module {
+        let mut s = skip_ws(s);
+        let start = source_pos(s);
+
+        let mut includes = Vec::new();
+        let mut uses = Vec::new();
+        let mut relations = Vec::new();
+        let mut modes = Vec::new();
+        let mut tables = Vec::new();
+        let mut costs = Vec::new();
+        loop {
+            let fragment = s.fragment();
+            let result = if fragment.starts_with("Include") || fragment.starts_with("Import") {
+                parse_include(s).map(|(rest, include)| {
+                    includes.push(include);
+                    rest
+                })
+            } else if fragment.starts_with("Use") {
+                parse_use(s).map(|(rest, use_)| {
+                    uses.push(use_);
+                    rest
+                })
+            } else if fragment.starts_with("Relation") {
+                parse_relation_decl(s).map(|(rest, relation)| {
+                    relations.push(relation);
+                    rest
+                })
+            } else if fragment.starts_with("Mode") {
+                parse_mode_decl(s).map(|(rest, mode)| {
+                    modes.push(mode);
+                    rest
+                })
+            } else if fragment.starts_with("Table") {
+                parse_table_decl(s).map(|(rest, table)| {
+                    tables.push(table);
+                    rest
+                })
+            } else if fragment.starts_with("Cost") {
+                parse_cost_decl(s).map(|(rest, cost)| {
+                    costs.push(cost);
+                    rest
+                })
+            } else {
+                break;
+            };
+            s = match result {
+                Ok(rest) => rest,
+                Err(e) => {
+                    self.record(e, &[Construct::Module]);
+                    skip_line(s)
+                }
+            };
+            s = skip_ws(s);
+        }
+
+        let (s, state_vars, facts) = self.facts(s);
+        let (s, initial_state) = self.initial_state(s);
+        let (mut s, global_stage) = self.global(s);
+
+        let mut stages = Vec::new();
+        loop {
+            s = skip_ws(s);
+            if s.fragment().is_empty() {
+                break;
+            }
+            if s.fragment().starts_with("Begin Stage") {
+                let (rest, stage) = self.stage(s);
+                stages.push(stage);
+                s = rest;
+            } else {
+                self.expected(s, "`Begin Stage` or end of input", &[Construct::Module]);
+                s = skip_to_sync(s);
+            }
+        }
+
+        let end = stages.last().unwrap_or(&global_stage).span.end;
+        Module {
+            includes,
+            uses,
+            relations,
+            modes,
+            tables,
+            costs,
+            state_vars,
+            facts,
+            initial_state,
+            global_stage,
+            stages,
+            span: SourceSpan::new(start, end),
+        }
+    }
+
+    fn facts<'a>(&mut self, s: Span<'a>) -> (Span<'a>, Vec<String>, Vec<Term>) {
+        let mut state_vars = Vec::new();
+        let mut facts = Vec::new();
+
+        let Ok((s, _)) = tag::<_, _, ParseError>("Begin Facts:").parse(s) else {
+            self.expected(s, "`Begin Facts:`", &[Construct::Module]);
+            return (s, state_vars, facts);
+        };
+        let mut s = self.header_end(s, "end of line after `Begin Facts:`", FACTS);
+
+        loop {
+            s = skip_ws(s);
+            if let Ok((rest, _)) = tag::<_, _, ParseError>("End Facts").parse(s) {
+                return (rest, state_vars, facts);
+            }
+            if s.fragment().is_empty() || is_facts_terminator(s) || s.fragment().starts_with("Begin Stage") {
+                self.expected(s, "`End Facts`", FACTS);
+                return (s, state_vars, facts);
+            }
+            match parse_fact_item(s) {
+                Ok((rest, FactOrStateVar::StateVar(sv))) => {
+                    state_vars.push(sv);
+                    s = rest;
+                }
+                Ok((rest, FactOrStateVar::Fact(term))) => {
+                    facts.push(term);
+                    s = rest;
+                }
+                Err(e) => {
+                    self.record(e, FACTS);
+                    s = skip_line(s);
+                }
+            }
+        }
+    }
+
+    fn initial_state<'a>(&mut self, s: Span<'a>) -> (Span<'a>, Option<InitialState>) {
+        let s = skip_ws(s);
+        if !s.fragment().starts_with("Begin Initial State") {
+            return (s, None);
+        }
+        match parse_initial_state(s) {
+            Ok((rest, initial)) => (rest, Some(initial)),
+            Err(e) => {
+                self.record(e, &[Construct::Module]);
+                let rest = skip_lines_until(s, |f| f.starts_with("End Initial State") || f.starts_with("Begin"));
+                let rest = if rest.fragment().starts_with("End Initial State") { skip_line(rest) } else { rest };
+                (rest, None)
+            }
+        }
+    }
+
+    fn global<'a>(&mut self, s: Span<'a>) -> (Span<'a>, Stage) {
+        let mut s = skip_ws(s);
+        if !s.fragment().starts_with("Begin Global:") {
+            self.expected(s, "`Begin Global:`", &[Construct::Module]);
+            s = skip_lines_until(s, |f| f.starts_with("Begin Global:") || f.starts_with("Begin Stage"));
+        }
+
+        let global_start = s;
+        let mut rules = Vec::new();
+        let mut end = s;
+
+        if let Ok((rest, _)) = tag::<_, _, ParseError>("Begin Global:").parse(s) {
+            s = self.header_end(rest, "end of line after `Begin Global:`", GLOBAL);
+            end = rest;
+            loop {
+                s = skip_ws(s);
+                if let Ok((rest, _)) = tag::<_, _, ParseError>("End Global").parse(s) {
+                    end = rest;
+                    s = rest;
+                    break;
+                }
+                let fragment = s.fragment();
+                if fragment.is_empty() || fragment.starts_with("Begin Stage") || fragment.starts_with("End Stage") {
+                    self.expected(s, "a rule or `End Global`", GLOBAL);
+                    break;
+                }
+                match expect("a rule or `End Global`", parse_rule).parse(s) {
+                    Ok((rest, rule)) => {
+                        rules.push(rule);
+                        end = rest;
+                        s = rest;
+                    }
+                    Err(e) => {
+                        self.record(e, GLOBAL);
+                        s = skip_to_sync(s);
+                    }
+                }
+            }
+        }
+
+        (s, Stage {
+            name: "Global".to_string(),
+            rules,
+            state_constraints: Vec::new(),
+            draw_directives: Vec::new(),
+            span: span_between(global_start, end),
+        })
+    }
+
+    fn stage<'a>(&mut self, start: Span<'a>) -> (Span<'a>, Stage) {
+        let after_keyword = start.take_from("Begin Stage".len());
+        let (mut s, name) = match (ws1, parse_stage_header).parse(after_keyword) {
+            Ok((rest, (_, name))) => (rest, name.to_string()),
+            Err(e) => {
+                self.record(e, STAGE);
+                let name = (ws1, parse_identifier)
+                    .parse(after_keyword)
+                    .map_or_else(|_| String::new(), |(_, (_, name))| name.to_string());
+                (skip_line(start), name)
+            }
+        };
+
+        let mut rules = Vec::new();
+        let mut state_constraints = Vec::new();
+        let mut draw_directives = Vec::new();
+        let mut past_rules = false;
+        let mut end = s;
+
+        loop {
+            s = skip_ws(s);
+            let fragment = s.fragment();
+
+            if let Ok((rest, _)) = tag::<_, _, ParseError>("End Stage").parse(s) {
+                match parse_stage_end(rest, &name) {
+                    Ok((rest, _)) => {
+                        end = rest;
+                        s = rest;
+                    }
+                    Err(e) => {
+                        self.record(e, STAGE);
+                        end = rest;
+                        s = skip_line(s);
+                    }
+                }
+                break;
+            }
+            if fragment.is_empty() || fragment.starts_with("Begin Stage") {
+                self.expected(s, format!("`End Stage {}`", name), STAGE);
+                break;
+            }
+
+            if fragment.starts_with("Rule") {
+                if past_rules {
+                    self.expected(s, stage_item_expectation(true), STAGE);
+                }
+                match expect(stage_item_expectation(past_rules), parse_rule).parse(s) {
+                    Ok((rest, rule)) => {
+                        rules.push(rule);
+                        end = rest;
+                        s = rest;
+                    }
+                    Err(e) => {
+                        self.record(e, STAGE);
+                        s = skip_to_sync(s);
+                    }
+                }
+            } else if fragment.starts_with("Begin State Constraints:") {
+                if past_rules {
+                    self.expected(s, stage_item_expectation(true), STAGE);
+                }
+                past_rules = true;
+                match parse_state_constraints(s) {
+                    Ok((rest, constraints)) => {
+                        state_constraints.extend(constraints);
+                        end = rest;
+                        s = rest;
+                    }
+                    Err(e) => {
+                        self.record(e, STAGE);
+                        s = skip_to_sync(s);
+                    }
+                }
+            } else if fragment.starts_with("With") || fragment.starts_with("Draw") {
+                past_rules = true;
+                match within(Construct::DrawDirective, parse_draw_directive)(s) {
+                    Ok((rest, directive)) => {
+                        end = rest;
+                        draw_directives.push(directive);
+                        s = rest;
+                    }
+                    Err(e) => {
+                        self.record(e, STAGE);
+                        s = skip_to_sync(s);
+                    }
+                }
+            } else {
+                self.expected(s, stage_item_expectation(past_rules), STAGE);
+                s = skip_to_sync(s);
+            }
+        }
+
+        (s, Stage {
+            name,
+            rules,
+            state_constraints,
+            draw_directives,
+            span: span_between(start, end),
+        })
+    }
+}
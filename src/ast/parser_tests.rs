@@ -888,3 +888,388 @@ fn test_comment_with_hash_in_string_context() {
     // Should leave the newline and nothing else
     assert!(remaining.fragment().starts_with(" #") || remaining.fragment().starts_with("#") || remaining.fragment().trim().is_empty() || remaining.fragment().starts_with("\n"));
 }
+
+#[test]
+fn test_term_spans() {
+    let input = Span::new("foo(X, bar(1))");
+    let (_, term) = parse_term(input).unwrap();
+
+    assert_eq!(term.span.start.line, 1);
+    assert_eq!(term.span.start.column, 1);
+    assert_eq!(term.span.end.column, 15);
+
+    match &term.contents {
+        TermContents::App { args, .. } => {
+            assert_eq!(args[0].span.start.column, 5);
+            assert_eq!(args[1].span.start.column, 8);
+            assert_eq!(args[1].span.end.column, 14);
+        }
+        _ => panic!("Expected App"),
+    }
+}
+
+#[test]
+fn test_rule_and_stage_spans() {
+    let input = Span::new("Begin Stage S:\nRule R:\n    a(X)\n    ----\n    b(X)\nEnd Stage S");
+    let (_, stage) = parse_stage(input).unwrap();
+
+    assert_eq!(stage.span.start.line, 1);
+    assert_eq!(stage.span.end.line, 6);
+
+    let rule = &stage.rules[0];
+    assert_eq!(rule.span.start.line, 2);
+    assert_eq!(rule.span.end.line, 5);
+    assert_eq!(rule.premise.span.start.line, 3);
+    assert_eq!(rule.premise.span.start.column, 5);
+    assert_eq!(rule.conclusion.span.start.line, 5);
+}
+
+#[test]
+fn test_parse_source_reports_location() {
+    let err = parse_source("Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage S:\nEnd Stage T\n")
+        .unwrap_err();
+
+    assert_eq!(err.construct(), Some(Construct::Stage));
+    assert_eq!(err.span.start.line, 8);
+}
+
+#[test]
+fn test_set_file_tags_spans() {
+    let (_, mut module) = parse_module(Span::new("Begin Facts:\n    a(1)\nEnd Facts\n\nBegin Global:\nEnd Global\n")).unwrap();
+    module.set_file("main.l");
+
+    assert_eq!(module.facts[0].span.file.as_deref(), Some("main.l"));
+    assert_eq!(module.facts[0].span.to_string(), "main.l:2:5");
+}
+
+#[test]
+fn test_missing_end_global_error() {
+    let err = parse_source("Begin Facts:\nEnd Facts\n\nBegin Global:\n\nBegin Stage S:\nEnd Stage S\n")
+        .unwrap_err();
+
+    assert_eq!(err.construct(), Some(Construct::Global));
+    assert_eq!(err.message(), "expected a rule or `End Global`, found `Begin` while parsing global block");
+    assert_eq!(err.span.start.line, 6);
+}
+
+#[test]
+fn test_bad_divider_error() {
+    let err = parse_rule(Span::new("Rule Foo:\n    a(X)\n    ====\n    b(X)")).unwrap_err();
+    let nom::Err::Failure(err) = err else {
+        panic!("Expected a committed failure, got {:?}", err);
+    };
+
+    assert_eq!(err.construct(), Some(Construct::Rule));
+    assert_eq!(err.expected.as_deref(), Some("a divider line of `-`"));
+    assert_eq!(&*err.found, "`====`");
+}
+
+#[test]
+fn test_term_error_names_enclosing_construct() {
+    let err = parse_source("Begin Facts:\n    a(1, )\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap_err();
+
+    assert_eq!(err.construct(), Some(Construct::Term));
+    assert_eq!(err.message(), "expected a term, found `)` while parsing term in facts block");
+}
+
+#[test]
+fn test_parse_error_renders_caret_snippet() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Global:\nRule Foo\n    a(X)\n    ----\n    b(X)\nEnd Global\n";
+    let err = parse_source(source).unwrap_err();
+
+    assert_eq!(
+        err.render(source),
+        "error: expected `:` after the rule name, found end of line while parsing rule in global block\n \
+         --> 5:9\n  |\n5 | Rule Foo\n  |         ^"
+    );
+}
+
+#[test]
+fn test_int_overflow_is_an_error() {
+    let err = parse_term(Span::new("a(99999999999)")).unwrap_err();
+    let nom::Err::Failure(err) = err else {
+        panic!("Expected a committed failure, got {:?}", err);
+    };
+
+    assert_eq!(&*err.found, "`99999999999`");
+}
+
+const BROKEN_MODULE: &str = "Begin Facts:
+    tile(1, 2)
+    tile(3,
+    tile(5, 6)
+End Facts
+
+Begin Global:
+Rule Bad:
+    a(X)
+    ====
+    b(X)
+Rule Good:
+    c(X)
+    ----
+    d(X)
+End Global
+
+Begin Stage Play:
+Rule Broken
+    e(X)
+    ----
+    f(X)
+Rule Fine:
+    g(X)
+    ----
+    h(X)
+End Stage Play
+";
+
+#[test]
+fn test_recovering_parser_reports_every_error() {
+    let (_, errors) = parse_module_recovering(BROKEN_MODULE);
+
+    let lines: Vec<u32> = errors.iter().map(|e| e.span.start.line).collect();
+    assert_eq!(lines, vec![5, 10, 19]);
+    assert_eq!(errors[0].construct(), Some(Construct::Term));
+    assert_eq!(errors[1].construct(), Some(Construct::Rule));
+    assert_eq!(errors[2].construct(), Some(Construct::Rule));
+}
+
+#[test]
+fn test_recovering_parser_keeps_what_parsed() {
+    let (module, _) = parse_module_recovering(BROKEN_MODULE);
+
+    assert_eq!(module.facts.len(), 2);
+    let global: Vec<&str> = module.global_stage.rules.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(global, vec!["Good"]);
+    assert_eq!(module.stages.len(), 1);
+    let stage: Vec<&str> = module.stages[0].rules.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(stage, vec!["Fine"]);
+}
+
+#[test]
+fn test_recovering_parser_closes_unterminated_stage() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage A:\n\nBegin Stage B:\nEnd Stage B\n";
+    let (module, errors) = parse_module_recovering(source);
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message(), "expected `End Stage A`, found `Begin` while parsing stage");
+    let names: Vec<&str> = module.stages.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["A", "B"]);
+}
+
+#[test]
+fn test_recovering_parser_matches_strict_parser_on_valid_input() {
+    let contents = std::fs::read_to_string("tests/parser/test_module.l").unwrap();
+    let (recovered, errors) = parse_module_recovering(&contents);
+    let strict = parse_source(&contents).unwrap();
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(recovered.to_string(), strict.to_string());
+    assert_eq!(recovered.span, strict.span);
+}
+
+#[test]
+fn test_parse_includes() {
+    let input = "Include \"physics.l\"  # shared rules\nImport \"lib/draw.l\"\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    let module = parse_source(input).unwrap();
+
+    let paths: Vec<&str> = module.includes.iter().map(|i| i.path.as_str()).collect();
+    assert_eq!(paths, vec!["physics.l", "lib/draw.l"]);
+    assert_eq!(module.includes[1].span.start.line, 2);
+    assert_eq!(module.span.start.line, 1);
+}
+
+#[test]
+fn test_include_requires_quoted_path() {
+    let err = parse_source("Include physics.l\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap_err();
+
+    assert_eq!(err.message(), "expected a quoted file path, found `physics.l` while parsing include directive");
+}
+
+#[test]
+fn test_parse_use_directives() {
+    let input = "Use lists  # append, reverseOf\nInclude \"lib.l\"\nUse math\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    let module = parse_source(input).unwrap();
+
+    let names: Vec<&str> = module.uses.iter().map(|u| u.module.as_str()).collect();
+    assert_eq!(names, vec!["lists", "math"]);
+    assert_eq!(module.uses[0].span.end.column, 10);
+    assert_eq!(parse_source(&module.to_string()).unwrap().to_string(), module.to_string());
+
+    let (recovered, errors) = parse_module_recovering(input);
+    assert!(errors.is_empty());
+    assert_eq!(recovered.uses.len(), 2);
+
+    let err = parse_source("Use \"lists\"\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap_err();
+    assert_eq!(err.message(), "expected a library module name, found `\"` while parsing use directive");
+}
+
+#[test]
+fn test_parse_table_declarations() {
+    let input = "Relation edge(Node, Node)\nTable reach  # left-recursive\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    let module = parse_source(input).unwrap();
+
+    let names: Vec<&str> = module.tables.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["reach"]);
+    assert_eq!(module.tables[0].span.start.line, 2);
+    assert_eq!(parse_source(&module.to_string()).unwrap().to_string(), module.to_string());
+
+    let (recovered, errors) = parse_module_recovering(input);
+    assert!(errors.is_empty());
+    assert_eq!(recovered.tables.len(), 1);
+
+    let err = parse_source("Table reach(X)\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap_err();
+    assert_eq!(err.message(), "expected end of line after the declaration, found `(` while parsing table declaration");
+}
+
+#[test]
+fn test_parse_cost_declarations() {
+    let input = "Cost road(_, _, D) = D\nCost step(X, water) = 5  # wading\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    let module = parse_source(input).unwrap();
+
+    let costs: Vec<String> = module.costs.iter().map(|c| format!("{} = {}", c.head, c.cost)).collect();
+    assert_eq!(costs, vec!["road(_, _, D) = D", "step(X, water) = 5"]);
+    assert_eq!(module.costs[1].span.start.line, 2);
+    assert_eq!(parse_source(&module.to_string()).unwrap().to_string(), module.to_string());
+
+    let (recovered, errors) = parse_module_recovering(input);
+    assert!(errors.is_empty());
+    assert_eq!(recovered.costs.len(), 2);
+
+    let err = parse_source("Cost road\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap_err();
+    assert_eq!(err.message(), "expected a relation call, found `road` while parsing cost declaration");
+}
+
+#[test]
+fn test_list_literals_desugar_to_cons() {
+    let (_, list) = parse_term("[1, X, [] | T]".into()).unwrap();
+    assert_eq!(list.to_string(), "cons(1, cons(X, cons(nil, T)))");
+
+    let (_, empty) = parse_term("[ ]".into()).unwrap();
+    assert_eq!(empty.to_string(), "nil");
+
+    let (_, nested) = parse_term("eq(L, [a & b, (c | d)])".into()).unwrap();
+    assert_eq!(nested.to_string(), "eq(L, cons(and(a, b), cons(or(c, d), nil)))");
+}
+
+#[test]
+fn test_unclosed_list_error() {
+    let err = parse_term("[1, 2".into()).unwrap_err();
+    let nom::Err::Failure(err) = err else { panic!("expected a failure, got {:?}", err) };
+    assert_eq!(err.message(), "expected `,`, `|` or `]`, found end of input while parsing term");
+}
+
+#[test]
+fn test_parse_arithmetic_precedence() {
+    let (_, term) = parse_term("next(Y) = Y + V * Dt - 1".into()).unwrap();
+    assert_eq!(term.to_string(), "eq(next(Y), ((Y + (V * Dt)) - 1))");
+
+    let (_, term) = parse_term("X / 2 .< Y".into()).unwrap();
+    assert_eq!(term.to_string(), "real_lt((X / 2), Y)");
+}
+
+#[test]
+fn test_divider_is_not_subtraction() {
+    let input = "Rule R:\n    a(X)\n    ----\n    b(X)\n";
+    let (_, rule) = parse_rule(input.into()).unwrap();
+    assert_eq!(rule.premise.to_string(), "a(X)");
+}
+
+#[test]
+fn test_when_chain_desugars_to_cond() {
+    let input = "Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage S:\nBegin State Constraints:\n    when shouldJump(): next(VY) = 3.5\n    otherwise when falling():\n        next(VY) = 0.0 # landed\n    otherwise: preserve(VY)\n    when dead(): next(Lives) = 0\nEnd State Constraints\nEnd Stage S\n";
+    let module = parse_source(input).unwrap();
+    let constraints = &module.stages[0].state_constraints;
+
+    assert_eq!(constraints.len(), 2);
+    assert_eq!(
+        constraints[0].to_string(),
+        "cond(shouldJump, eq(next(VY), 3.5), cond(falling, eq(next(VY), 0), preserve(VY)))"
+    );
+    assert_eq!(constraints[0].span.start.line, 9);
+    assert_eq!(constraints[0].span.end.line, 12);
+    assert_eq!(constraints[1].to_string(), "cond(dead, eq(next(Lives), 0), true)");
+}
+
+#[test]
+fn test_when_requires_colon() {
+    let err = parse_source("Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage S:\nBegin State Constraints:\n    when ready() next(X) = 1\nEnd State Constraints\nEnd Stage S\n").unwrap_err();
+    assert_eq!(err.span.start.line, 9);
+    assert_eq!(err.message(), "expected `:` after the condition, found `next` while parsing state constraints in stage");
+}
+
+#[test]
+fn test_when_chain_ends_before_a_constraint_named_like_otherwise() {
+    let input = "Begin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage S:\nBegin State Constraints:\n    when ready(): next(X) = 1\n    otherwiseOk()\nEnd State Constraints\nEnd Stage S\n";
+    let module = parse_source(input).unwrap();
+    let constraints = &module.stages[0].state_constraints;
+
+    assert_eq!(constraints.len(), 2);
+    assert_eq!(constraints[0].to_string(), "cond(ready, eq(next(X), 1), true)");
+    assert_eq!(constraints[1].to_string(), "otherwiseOk");
+}
+
+#[test]
+fn test_parse_initial_state_block() {
+    let input = "Begin Facts:\n    StateVar Lives\n    Lives = 3\nEnd Facts\n\nBegin Initial State:\n    StateVars RunnerY, RunnerVY\n    StateVar Dead\n    RunnerY = 0.0 # on the ground\n    RunnerVY = 0.0\n    Dead = no\nEnd Initial State\n\nBegin Global:\nEnd Global\n";
+    let module = parse_source(input).unwrap();
+
+    let initial = module.initial_state.as_ref().unwrap();
+    let names: Vec<&str> = initial.state_vars.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, vec!["RunnerY", "RunnerVY", "Dead"]);
+    assert_eq!(initial.state_vars[1].span.start.column, 24);
+    assert_eq!(initial.constraints.len(), 3);
+    assert_eq!(module.declared_state_vars(), vec!["Lives", "RunnerY", "RunnerVY", "Dead"]);
+
+    let reparsed = parse_source(&module.to_string()).unwrap();
+    assert_eq!(reparsed.to_string(), module.to_string());
+}
+
+#[test]
+fn test_initial_state_requires_capitalized_names() {
+    let err = parse_source("Begin Facts:\nEnd Facts\n\nBegin Initial State:\n    StateVars A, b\nEnd Initial State\n\nBegin Global:\nEnd Global\n").unwrap_err();
+    assert_eq!(err.message(), "expected a capitalized state variable name, found `b` while parsing initial state block");
+}
+
+#[test]
+fn test_parse_relation_declarations() {
+    let input = "Include \"lib.l\"\nRelation pos(Entity, Real, Real) # where things are\nRelation tick()\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    let module = parse_source(input).unwrap();
+
+    assert_eq!(module.relations.len(), 2);
+    assert_eq!(module.relations[0].params, vec![Type::Named("Entity".to_string()), Type::Real, Type::Real]);
+    assert_eq!(module.relations[0].span.end.column, 33);
+    assert!(module.relations[1].params.is_empty());
+    assert_eq!(parse_source(&module.to_string()).unwrap().to_string(), module.to_string());
+
+    let err = parse_source("Relation pos(entity)\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap_err();
+    assert_eq!(err.message(), "expected a capitalized type name, found `entity` while parsing relation declaration");
+}
+
+#[test]
+fn test_parse_underscore_prefixed_var() {
+    let input = "inCart(I, cons(I, _Rest))";
+    let (_, term) = parse_term(input.into()).unwrap();
+
+    if let TermContents::App { args, .. } = &term.contents
+        && let TermContents::App { args: cons_args, .. } = &args[1].contents
+    {
+        assert!(matches!(&cons_args[1].contents, TermContents::Var { name } if name == "_Rest"));
+    } else {
+        panic!("expected nested applications, got {}", term);
+    }
+}
+
+#[test]
+fn test_parse_mode_declarations() {
+    let input = "Mode length(+, -)\nRelation length(Any, Int)\nMode length(?, +)\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    let module = parse_source(input).unwrap();
+
+    assert_eq!(module.modes.len(), 2);
+    assert_eq!(module.modes[0].params, vec![Mode::In, Mode::Out]);
+    assert_eq!(module.modes[1].to_string(), "Mode length(?, +)");
+    assert_eq!(parse_source(&module.to_string()).unwrap().to_string(), module.to_string());
+
+    let err = parse_source("Mode length(in, out)\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap_err();
+    assert_eq!(err.message(), "expected `+`, `-` or `?`, found `in` while parsing mode declaration");
+}
@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock};
+
+use crate::ast::{parser, Rel, Rule, Term, TermContents, Use};
+use crate::diagnostic::Diagnostic;
+
+/// The standard library modules a program can bring in with `Use`.
+pub(crate) const MODULES: &[(&str, &str)] = &[
+    ("lists", include_str!("../stdlib/lists.l")),
+    ("math", include_str!("../stdlib/math.l")),
+    ("parity", include_str!("../stdlib/parity.l")),
+];
+
+/// Every module's rules, parsed the first time any program uses the
+/// standard library. Spans point into `<stdlib>/<module>.l`.
+pub(crate) static PARSED: LazyLock<Vec<Result<Vec<Rule>, Diagnostic>>> = LazyLock::new(|| {
+    MODULES.iter().map(|&(name, source)| parse_module(name, source)).collect()
+});
+
+fn parse_module(name: &str, source: &str) -> Result<Vec<Rule>, Diagnostic> {
+    let file: Arc<str> = format!("<stdlib>/{}.l", name).into();
+    match parser::parse_rules(source) {
+        Ok(mut rules) => {
+            for rule in &mut rules {
+                rule.span.file = Some(file.clone());
+                for term in [&mut rule.premise, &mut rule.conclusion] {
+                    term.walk_mut(&mut |t| t.span.file = Some(file.clone()));
+                }
+            }
+            Ok(rules)
+        }
+        Err(err) => {
+            let mut diagnostic = err.to_diagnostic();
+            diagnostic.message = format!("the standard library failed to parse: {}", diagnostic.message);
+            diagnostic.span.file = Some(file);
+            Err(diagnostic)
+        }
+    }
+}
+
+pub(crate) fn head_name(rule: &Rule) -> Option<&str> {
+    match &rule.conclusion.contents {
+        TermContents::App { rel: Rel::UserRel { name }, .. } => Some(name),
+        _ => None,
+    }
+}
+
+/// The rules of every module named by `uses`, each module once, along with
+/// errors for names that aren't standard library modules.
+///
+/// A library relation that the program defines itself, one of `defined`,
+/// is shadowed: the library's clauses for it are renamed to
+/// `<module>.<name>`, which no program can mention, so the program sees
+/// only its own definition while the library's other rules keep calling
+/// theirs.
+pub fn used_rules(uses: &[Use], defined: &HashSet<String>) -> (Vec<Rule>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let mut seen = HashSet::new();
+    let mut rules = Vec::new();
+    for use_ in uses {
+        let Some(index) = MODULES.iter().position(|&(name, _)| name == use_.module) else {
+            let names: Vec<String> = MODULES.iter().map(|(name, _)| format!("`{}`", name)).collect();
+            diagnostics.push(Diagnostic::error(
+                format!("unknown library module `{}`; expected one of {}", use_.module, names.join(", ")),
+                use_.span.clone(),
+            ));
+            continue;
+        };
+        if !seen.insert(index) {
+            continue;
+        }
+        match &PARSED[index] {
+            Ok(module_rules) => rules.extend(shadowed(MODULES[index].0, module_rules, defined)),
+            Err(diagnostic) => diagnostics.push(diagnostic.clone()),
+        }
+    }
+    (rules, diagnostics)
+}
+
+fn shadowed(module: &str, rules: &[Rule], defined: &HashSet<String>) -> Vec<Rule> {
+    let renamed: HashSet<&str> = rules.iter().filter_map(head_name).filter(|name| defined.contains(*name)).collect();
+    if renamed.is_empty() {
+        return rules.to_vec();
+    }
+    let rename = |t: &mut Term| {
+        if let TermContents::App { rel: Rel::UserRel { name }, .. } = &mut t.contents
+            && renamed.contains(name.as_str())
+        {
+            *name = format!("{}.{}", module, name);
+        }
+    };
+    let mut rules = rules.to_vec();
+    for rule in &mut rules {
+        rule.premise.walk_mut(&mut |t| rename(t));
+        rule.conclusion.walk_mut(&mut |t| rename(t));
+    }
+    rules
+}
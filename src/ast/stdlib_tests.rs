@@ -0,0 +1,42 @@
+use super::stdlib::*;
+use super::*;
+
+fn use_of(module: &str) -> Use {
+    Use { module: module.to_string(), span: SourceSpan::default() }
+}
+
+#[test]
+fn test_every_module_parses() {
+    for (result, (name, _)) in PARSED.iter().zip(MODULES) {
+        assert!(result.as_ref().is_ok_and(|rules| !rules.is_empty()), "{}: {:?}", name, result);
+    }
+}
+
+#[test]
+fn test_each_module_is_used_once() {
+    let (once, _) = used_rules(&[use_of("math")], &HashSet::new());
+    let (twice, diagnostics) = used_rules(&[use_of("math"), use_of("math")], &HashSet::new());
+    assert_eq!(once.len(), twice.len());
+    assert!(diagnostics.is_empty());
+}
+
+#[test]
+fn test_unknown_module_is_reported() {
+    let (rules, diagnostics) = used_rules(&[use_of("strings")], &HashSet::new());
+    assert!(rules.is_empty());
+    assert_eq!(
+        diagnostics[0].message,
+        "unknown library module `strings`; expected one of `lists`, `math`, `parity`"
+    );
+}
+
+#[test]
+fn test_shadowed_relations_are_renamed_everywhere() {
+    let defined = HashSet::from(["append".to_string()]);
+    let (rules, _) = used_rules(&[use_of("lists")], &defined);
+    let heads: HashSet<&str> = rules.iter().filter_map(head_name).collect();
+    assert!(heads.contains("lists.append") && !heads.contains("append"));
+
+    let reverse = rules.iter().find(|r| r.name == "ReverseRec").unwrap();
+    assert!(reverse.premise.to_string().contains("lists.append("), "{}", reverse.premise);
+}
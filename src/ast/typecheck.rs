@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::compile::{SMT_INT_RELATIONS, SMT_REAL_RELATIONS};
+use crate::ast::{is_arith_op, Module, Rel, SourceSpan, Term, TermContents, Type};
+use crate::diagnostic::Diagnostic;
+
+/// Checks every use of a declared relation, and of the built-in `int_*` and
+/// `real_*` relations, against its signature: the number of arguments, the
+/// numeric family of each number, and numbers and atoms standing where the
+/// other is expected. Variables take the type of their first typed use in a
+/// rule, stage or the facts, and later uses must agree with it. Relations
+/// without a declaration are not checked.
+pub fn check_module(module: &Module) -> Vec<Diagnostic> {
+    let mut checker = Checker::default();
+    checker.declare_builtins();
+    for decl in &module.relations {
+        checker.declare(&decl.name, &decl.params, &decl.span);
+    }
+
+    let initial_constraints = module.initial_state.iter().flat_map(|i| &i.constraints);
+    for fact in module.facts.iter().chain(initial_constraints) {
+        checker.check_prop(fact);
+    }
+    let global_vars = std::mem::take(&mut checker.vars);
+
+    for stage in std::iter::once(&module.global_stage).chain(&module.stages) {
+        for rule in &stage.rules {
+            checker.vars = global_vars.clone();
+            checker.check_prop(&rule.premise);
+            checker.check_prop(&rule.conclusion);
+        }
+        checker.vars = global_vars.clone();
+        for constraint in &stage.state_constraints {
+            checker.check_prop(constraint);
+        }
+        for directive in &stage.draw_directives {
+            if let Some(condition) = &directive.condition {
+                checker.check_prop(condition);
+            }
+        }
+    }
+
+    checker.diagnostics
+}
+
+/// The generic arithmetic relations. Each stands for the `int_` or `real_`
+/// relation of the same name, whichever its arguments call for.
+pub(crate) const GENERIC_RELATIONS: &[(&str, usize)] = &[
+    ("neq", 2),
+    ("lt", 2),
+    ("le", 2),
+    ("gt", 2),
+    ("ge", 2),
+    ("add", 3),
+    ("sub", 3),
+    ("mul", 3),
+    ("div", 3),
+];
+
+/// Rewrites each generic arithmetic relation in the module, such as
+/// `add(X, Y, Z)` or `lt(X, 1.0)`, into its `int_` or `real_` form. Real
+/// literals and variables typed by their other uses in the same rule, stage
+/// or the facts decide the family; state variables are typed by their
+/// initial values. Integer literals decide only when nothing else does,
+/// since `add(X, 1, Y)` is as likely real as not. A relation the module
+/// defines itself keeps its own meaning, and uses nothing decides are
+/// errors.
+pub fn resolve_arithmetic(module: &mut Module) -> Vec<Diagnostic> {
+    let defined = module.defined_relations();
+    let mut resolver = Resolver::new(&defined);
+    for decl in &module.relations {
+        resolver.checker.declare(&decl.name, &decl.params, &decl.span);
+    }
+
+    let initial_constraints = module.initial_state.iter_mut().flat_map(|i| &mut i.constraints);
+    resolver.resolve_scope(module.facts.iter_mut().chain(initial_constraints).collect());
+    let global_vars = std::mem::take(&mut resolver.checker.vars);
+
+    for stage in std::iter::once(&mut module.global_stage).chain(&mut module.stages) {
+        for rule in &mut stage.rules {
+            resolver.checker.vars = global_vars.clone();
+            resolver.resolve_scope(vec![&mut rule.premise, &mut rule.conclusion]);
+        }
+        resolver.checker.vars = global_vars.clone();
+        let conditions = stage.draw_directives.iter_mut().filter_map(|d| d.condition.as_mut());
+        resolver.resolve_scope(stage.state_constraints.iter_mut().chain(conditions).collect());
+    }
+
+    resolver.diagnostics
+}
+
+/// Resolves the generic arithmetic in a query or a fact added at run time,
+/// from the term and the numeric types of the program's fact and state
+/// variables.
+pub fn resolve_term(term: &mut Term, var_types: &HashMap<String, Type>) -> Vec<Diagnostic> {
+    let mut resolver = Resolver::new(&HashSet::new());
+    for (name, ty) in var_types {
+        resolver.checker.vars.insert(name.clone(), (ty.clone(), SourceSpan::default()));
+    }
+    resolver.resolve_scope(vec![term]);
+    resolver.diagnostics
+}
+
+struct Resolver {
+    /// Types the variables of the scope being resolved; its own
+    /// diagnostics are left to [`check_module`].
+    checker: Checker,
+    generic: HashMap<&'static str, usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn new(defined: &HashSet<String>) -> Self {
+        let mut checker = Checker::default();
+        checker.declare_builtins();
+        let generic = GENERIC_RELATIONS
+            .iter()
+            .filter(|(name, _)| !defined.contains(*name))
+            .copied()
+            .collect();
+        Self { checker, generic, diagnostics: Vec::new() }
+    }
+
+    fn resolve_scope(&mut self, mut props: Vec<&mut Term>) {
+        let mut int_literals = false;
+        loop {
+            for prop in &props {
+                self.checker.check_prop(prop);
+            }
+            let mut resolved = false;
+            for prop in &mut props {
+                resolved |= self.resolve_prop(prop, int_literals);
+            }
+            if resolved {
+                int_literals = false;
+            } else if !int_literals {
+                int_literals = true;
+            } else {
+                break;
+            }
+        }
+        for prop in &props {
+            self.report_unresolved(prop);
+        }
+    }
+
+    /// The relation `term` applies if it is a generic one.
+    fn generic_app<'t>(&self, term: &'t Term) -> Option<(&'t str, &'t [Term], usize)> {
+        match &term.contents {
+            TermContents::App { rel: Rel::UserRel { name }, args } => {
+                let arity = *self.generic.get(name.as_str())?;
+                Some((name, args, arity))
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolves the generic relations among the connectives of `term` that
+    /// the variable types known so far decide, reporting whether any were.
+    fn resolve_prop(&mut self, term: &mut Term, int_literals: bool) -> bool {
+        let family = match self.generic_app(term) {
+            Some((_, args, arity)) if args.len() == arity => {
+                let types: Vec<Type> = args.iter().filter_map(|a| self.family(a, int_literals)).collect();
+                types.iter().find(|t| **t == Type::Real).or(types.first()).cloned()
+            }
+            _ => None,
+        };
+        let TermContents::App { rel, args } = &mut term.contents else {
+            return false;
+        };
+        match (rel, family) {
+            (Rel::UserRel { name }, Some(Type::Real)) => {
+                *name = format!("real_{}", name);
+                // `add(X, 1, Y)` means `1.0` when `X` is real.
+                for arg in args {
+                    arg.walk_mut(&mut |t| {
+                        if let TermContents::Int { val } = t.contents {
+                            t.contents = TermContents::Float { val: val as f32 };
+                        }
+                    });
+                }
+                true
+            }
+            (Rel::UserRel { name }, Some(_)) => {
+                *name = format!("int_{}", name);
+                true
+            }
+            (rel, _) if is_connective(rel_name(rel), args.len()) => {
+                let mut resolved = false;
+                for arg in args {
+                    resolved |= self.resolve_prop(arg, int_literals);
+                }
+                resolved
+            }
+            _ => false,
+        }
+    }
+
+    /// The numeric family `term` says its relation belongs to.
+    fn family(&self, term: &Term, int_literals: bool) -> Option<Type> {
+        if let Some(name) = var_name(term) {
+            return self.checker.vars.get(name).map(|(ty, _)| ty.clone()).filter(Type::is_numeric);
+        }
+        match &term.contents {
+            TermContents::Int { .. } if int_literals => Some(Type::Int),
+            TermContents::Float { .. } => Some(Type::Real),
+            _ => {
+                let types: Vec<Type> = arith_args(term)?.iter().filter_map(|a| self.family(a, int_literals)).collect();
+                types.iter().find(|t| **t == Type::Real).or(types.first()).cloned()
+            }
+        }
+    }
+
+    fn report_unresolved(&mut self, term: &Term) {
+        if let Some((name, args, arity)) = self.generic_app(term) {
+            let message = if args.len() != arity {
+                format!(
+                    "`{}` takes {} arguments but {} {} given",
+                    name,
+                    arity,
+                    args.len(),
+                    if args.len() == 1 { "was" } else { "were" },
+                )
+            } else {
+                format!(
+                    "cannot tell whether `{}` is Int or Real arithmetic here; use `int_{}` or `real_{}`",
+                    name, name, name
+                )
+            };
+            self.diagnostics.push(Diagnostic::error(message, term.span.clone()));
+            return;
+        }
+        if let TermContents::App { rel, args } = &term.contents
+            && is_connective(rel_name(rel), args.len())
+        {
+            for arg in args {
+                self.report_unresolved(arg);
+            }
+        }
+    }
+}
+
+fn is_connective(name: &str, arity: usize) -> bool {
+    matches!((name, arity), ("and" | "or", 2) | ("not", 1) | ("cond", 3))
+}
+
+struct Signature {
+    params: Vec<Type>,
+    /// Where the relation was declared; `None` for built-ins.
+    declared_at: Option<SourceSpan>,
+}
+
+#[derive(Default)]
+struct Checker {
+    signatures: HashMap<String, Signature>,
+    /// The type of each variable in scope, and where it was first used so.
+    vars: HashMap<String, (Type, SourceSpan)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+fn rel_name(rel: &Rel) -> &str {
+    match rel {
+        Rel::SMTRel { name } | Rel::UserRel { name } => name,
+    }
+}
+
+/// The variable `term` refers to, looking through `next(X)`.
+fn var_name(term: &Term) -> Option<&str> {
+    match &term.contents {
+        TermContents::Var { name } => Some(name),
+        TermContents::App { rel, args } if rel_name(rel) == "next" && args.len() == 1 => var_name(&args[0]),
+        _ => None,
+    }
+}
+
+fn arith_args(term: &Term) -> Option<&[Term]> {
+    match &term.contents {
+        TermContents::App { rel: Rel::SMTRel { name }, args } if is_arith_op(name) && args.len() == 2 => Some(args),
+        _ => None,
+    }
+}
+
+impl Checker {
+    fn declare_builtins(&mut self) {
+        for (relations, ty) in [(SMT_INT_RELATIONS, Type::Int), (SMT_REAL_RELATIONS, Type::Real)] {
+            for &(name, arity) in relations {
+                self.signatures.insert(name.to_string(), Signature {
+                    params: vec![ty.clone(); arity],
+                    declared_at: None,
+                });
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str, params: &[Type], span: &SourceSpan) {
+        match self.signatures.get(name) {
+            Some(Signature { declared_at: None, .. }) => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("`{}` is a built-in relation and cannot be redeclared", name),
+                    span.clone(),
+                ));
+            }
+            Some(Signature { params: existing, declared_at: Some(at) }) if existing != params => {
+                self.diagnostics.push(Diagnostic::error(
+                    format!("relation `{}` is already declared differently at {}", name, at),
+                    span.clone(),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                self.signatures.insert(name.to_string(), Signature {
+                    params: params.to_vec(),
+                    declared_at: Some(span.clone()),
+                });
+            }
+        }
+    }
+
+    fn check_prop(&mut self, term: &Term) {
+        let TermContents::App { rel, args } = &term.contents else {
+            return;
+        };
+        let name = rel_name(rel);
+        match (name, args.len()) {
+            _ if is_connective(name, args.len()) => {
+                for arg in args {
+                    self.check_prop(arg);
+                }
+            }
+            ("eq", 2) => {
+                if let Some(ty) = self.numeric_type(&args[0]) {
+                    self.check_arg(&args[1], &ty, true);
+                } else if let Some(ty) = self.numeric_type(&args[1]) {
+                    self.check_arg(&args[0], &ty, true);
+                }
+            }
+            _ => {
+                let Some(signature) = self.signatures.get(name) else {
+                    return;
+                };
+                let params = signature.params.clone();
+                if params.len() != args.len() {
+                    let declared = match &signature.declared_at {
+                        Some(at) => format!(" (declared at {})", at),
+                        None => String::new(),
+                    };
+                    self.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}` takes {} argument{} but {} {} given{}",
+                            name,
+                            params.len(),
+                            if params.len() == 1 { "" } else { "s" },
+                            args.len(),
+                            if args.len() == 1 { "was" } else { "were" },
+                            declared,
+                        ),
+                        term.span.clone(),
+                    ));
+                    return;
+                }
+                for (arg, ty) in args.iter().zip(&params) {
+                    self.check_arg(arg, ty, false);
+                }
+            }
+        }
+    }
+
+    /// The numeric type `term` is known to have: that of a number, of a
+    /// variable already used as one, or of arithmetic over either.
+    fn numeric_type(&self, term: &Term) -> Option<Type> {
+        if let Some(name) = var_name(term) {
+            return self.vars.get(name).map(|(ty, _)| ty.clone()).filter(Type::is_numeric);
+        }
+        match &term.contents {
+            TermContents::Int { .. } => Some(Type::Int),
+            TermContents::Float { .. } => Some(Type::Real),
+            _ => {
+                let args = arith_args(term)?;
+                let types: Vec<Type> = args.iter().filter_map(|a| self.numeric_type(a)).collect();
+                // Integer literals in arithmetic adapt to the other operand.
+                types.iter().find(|t| **t == Type::Real).or(types.first()).cloned()
+            }
+        }
+    }
+
+    /// Checks that `arg` can stand where a value of type `expected` is
+    /// required. Inside infix arithmetic, integer literals may stand for
+    /// reals, since the compiler picks the relation family for them.
+    fn check_arg(&mut self, arg: &Term, expected: &Type, in_arith: bool) {
+        if let Some(name) = var_name(arg) {
+            self.use_var(name, expected, &arg.span);
+            return;
+        }
+        if let Some(operands) = arith_args(arg) {
+            if expected.is_numeric() {
+                for operand in operands {
+                    self.check_arg(operand, expected, true);
+                }
+            } else if *expected != Type::Any {
+                self.mismatch(arg, expected, "arithmetic");
+            } else if let Some(ty) = self.numeric_type(arg) {
+                for operand in operands {
+                    self.check_arg(operand, &ty, true);
+                }
+            }
+            return;
+        }
+
+        match (&arg.contents, expected) {
+            (_, Type::Any) => {}
+            (TermContents::Int { .. }, Type::Int) | (TermContents::Float { .. }, Type::Real) => {}
+            (TermContents::Int { .. }, Type::Real) if in_arith => {}
+            (TermContents::Int { .. }, _) => self.mismatch(arg, expected, "an Int"),
+            (TermContents::Float { .. }, _) => self.mismatch(arg, expected, "a Real"),
+            (TermContents::Atom { .. }, Type::Int | Type::Real) => self.mismatch(arg, expected, "an atom"),
+            (TermContents::App { .. }, Type::Int | Type::Real) => self.mismatch(arg, expected, "a compound term"),
+            _ => {}
+        }
+    }
+
+    fn mismatch(&mut self, arg: &Term, expected: &Type, found: &str) {
+        self.diagnostics.push(Diagnostic::error(
+            format!("expected {}, found {} `{}`", expected, found, arg),
+            arg.span.clone(),
+        ));
+    }
+
+    fn use_var(&mut self, name: &str, expected: &Type, span: &SourceSpan) {
+        // Each `_` is a variable of its own.
+        if *expected == Type::Any || name == "_" {
+            return;
+        }
+        let Some((ty, at)) = self.vars.get(name) else {
+            self.vars.insert(name.to_string(), (expected.clone(), span.clone()));
+            return;
+        };
+        // Different sorts of symbols are not told apart; only numbers are.
+        let conflicts = ty != expected && (ty.is_numeric() || expected.is_numeric());
+        if conflicts {
+            self.diagnostics.push(Diagnostic::error(
+                format!("`{}` is used as {} here but as {} at {}", name, expected, ty, at),
+                span.clone(),
+            ));
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use super::typecheck::*;
+use super::parser::parse_source;
+
+fn check(source: &str) -> Vec<String> {
+    let module = parse_source(source).unwrap();
+    check_module(&module)
+        .into_iter()
+        .map(|d| format!("{}: {}", d.span, d.message))
+        .collect()
+}
+
+const RELATIONS: &str = "Relation pos(Entity, Real, Real)\nRelation health(Entity, Int)\n\n";
+
+#[test]
+fn test_well_typed_module_has_no_errors() {
+    let source = format!(
+        "{}Begin Facts:\n    pos(player, 0.0, 1.5)\n    health(player, 3)\nEnd Facts\n\nBegin Global:\n    Rule Fall:\n    pos(E, X, Y) & real_sub(Y, 0.5, Y2)\n    -----------------------------------\n    pos(E, X + 1, Y2)\nEnd Global\n",
+        RELATIONS
+    );
+    assert_eq!(check(&source), Vec::<String>::new());
+}
+
+#[test]
+fn test_arity_mismatch_is_located() {
+    let source = format!("{}Begin Facts:\n    pos(player, 0.0)\nEnd Facts\n\nBegin Global:\nEnd Global\n", RELATIONS);
+    assert_eq!(check(&source), vec!["5:5: `pos` takes 3 arguments but 2 were given (declared at 1:1)"]);
+}
+
+#[test]
+fn test_wrong_numeric_family() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Global:\n    Rule Step:\n    real_add(X, 1, Y)\n    -----------------\n    step(Y)\nEnd Global\n";
+    assert_eq!(check(source), vec!["6:17: expected Real, found an Int `1`"]);
+}
+
+#[test]
+fn test_atoms_and_numbers_in_the_wrong_place() {
+    let source = format!("{}Begin Facts:\n    pos(player, left, 1.0)\n    health(7, 3)\nEnd Facts\n\nBegin Global:\nEnd Global\n", RELATIONS);
+    assert_eq!(check(&source), vec![
+        "5:17: expected Real, found an atom `left`",
+        "6:12: expected Entity, found an Int `7`",
+    ]);
+}
+
+#[test]
+fn test_variable_used_as_two_families() {
+    let source = "Begin Facts:\nEnd Facts\n\nBegin Global:\n    Rule Mixed:\n    int_add(X, 1, Y) & real_lt(X, 2.0)\n    ----------------------------------\n    mixed(Y)\nEnd Global\n";
+    assert_eq!(check(source), vec!["6:32: `X` is used as Real here but as Int at 6:13"]);
+}
+
+#[test]
+fn test_conflicting_declarations() {
+    let source = "Relation pos(Entity, Real)\nRelation pos(Entity, Int)\nRelation int_add(Int, Int)\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+    assert_eq!(check(source), vec![
+        "2:1: relation `pos` is already declared differently at 1:1",
+        "3:1: `int_add` is a built-in relation and cannot be redeclared",
+    ]);
+}
+
+fn resolve(source: &str) -> (Vec<String>, Vec<String>) {
+    let mut module = parse_source(source).unwrap();
+    let diagnostics = resolve_arithmetic(&mut module)
+        .into_iter()
+        .map(|d| format!("{}: {}", d.span, d.message))
+        .collect();
+    let rules = module.global_stage.rules.iter().map(|r| r.premise.to_string()).collect();
+    (rules, diagnostics)
+}
+
+fn rules(premises: &[&str]) -> String {
+    let rules: Vec<String> = premises
+        .iter()
+        .enumerate()
+        .map(|(i, premise)| format!("    Rule R{}:\n    {}\n    ----\n    r{}()\n", i, premise, i))
+        .collect();
+    format!("Begin Facts:\n    StateVar Speed\n    Speed = 1.5\nEnd Facts\n\nBegin Global:\n{}End Global\n", rules.concat())
+}
+
+#[test]
+fn test_generic_arithmetic_takes_the_family_of_its_arguments() {
+    let (premises, diagnostics) = resolve(&rules(&[
+        "add(X, 0.5, Y)",
+        "lt(Speed, 2)",
+        "real_lt(X, 2.0) & mul(X, 2, Y)",
+        "add(X, 1.0, Y) & gt(Y, Z)",
+        "sub(X, 1, Y)",
+        "not(le(X, Y + 0.5))",
+    ]));
+    assert_eq!(diagnostics, Vec::<String>::new());
+    assert_eq!(premises, vec![
+        "real_add(X, 0.5, Y)",
+        "real_lt(Speed, 2)",
+        "and(real_lt(X, 2), real_mul(X, 2, Y))",
+        "and(real_add(X, 1, Y), real_gt(Y, Z))",
+        "int_sub(X, 1, Y)",
+        "not(real_le(X, (Y + 0.5)))",
+    ]);
+}
+
+#[test]
+fn test_undecided_generic_arithmetic_is_an_error() {
+    let (_, diagnostics) = resolve(&rules(&["lt(X, Y)", "add(X, 1)"]));
+    assert_eq!(diagnostics, vec![
+        "8:5: cannot tell whether `lt` is Int or Real arithmetic here; use `int_lt` or `real_lt`",
+        "12:5: `add` takes 3 arguments but 2 were given",
+    ]);
+}
+
+#[test]
+fn test_module_definitions_shadow_generic_arithmetic() {
+    let source = "Begin Facts:\n    add(1, 2, 3)\nEnd Facts\n\nBegin Global:\n    Rule Lt:\n    add(X, Y, Z) & lt(X, 1.5)\n    ----\n    r(Z)\nEnd Global\n";
+    let (premises, diagnostics) = resolve(source);
+    assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    assert_eq!(premises, vec!["and(add(X, Y, Z), real_lt(X, 1.5))"]);
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+
+use crate::ast::SourceSpan;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A located message produced by the parser, the compiler or one of the
+/// analysis passes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: SourceSpan,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>, span: SourceSpan) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            span,
+        }
+    }
+
+    pub fn error(message: impl Into<String>, span: SourceSpan) -> Self {
+        Self::new(Severity::Error, message, span)
+    }
+
+    pub fn warning(message: impl Into<String>, span: SourceSpan) -> Self {
+        Self::new(Severity::Warning, message, span)
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Renders the diagnostic followed by the offending source line with a
+    /// caret underline, e.g.
+    ///
+    /// ```text
+    /// error: expected `:` after the rule name, found `Foo` while parsing rule
+    ///  --> 3:10
+    ///   |
+    /// 3 | Rule Bar Foo
+    ///   |          ^^^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("{}: {}", self.severity, self.message);
+        if self.span.is_unknown() {
+            return out;
+        }
+
+        let line_no = self.span.start.line as usize;
+        let Some(line) = source.lines().nth(line_no - 1) else {
+            return out;
+        };
+        let gutter = " ".repeat(line_no.to_string().len());
+        let column = self.span.start.column.max(1) as usize;
+        let width = if self.span.end.line == self.span.start.line {
+            (self.span.end.column as usize).saturating_sub(column).max(1)
+        } else {
+            line.chars().count().saturating_sub(column - 1).max(1)
+        };
+
+        out.push_str(&format!("\n{}--> {}", gutter, self.span));
+        out.push_str(&format!("\n{} |", gutter));
+        out.push_str(&format!("\n{} | {}", line_no, line));
+        out.push_str(&format!(
+            "\n{} | {}{}",
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(width)
+        ));
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.span.is_unknown() {
+            write!(f, "{}: ", self.span)?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(Diagnostic::is_error)
+}
+
+/// Levenshtein distance between two names, counted in characters.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to `name`, if any is near enough to be a likely
+/// misspelling of it.
+pub fn closest_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let threshold = name.chars().count() / 3;
+    candidates
+        .into_iter()
+        .filter(|&candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
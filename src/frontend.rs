@@ -1,15 +1,20 @@
 pub mod ffi;
 pub mod frontend_tests;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use nom::Finish;
 
-use crate::solver::ir::{Program, PropId, Prop, Term, TermId};
+use crate::solver::forward::{self, StepFn};
+use crate::solver::{datalog, optimize};
+use crate::solver::ir::{Clause, Program, PropId, Prop, RelId, Stage, Term, TermId};
 use crate::solver::{format_solution, Solver, SearchStrategy, SearchQueue, Subst, reify_term, TerminationReason, SolutionSet};
+use crate::solver::DEFAULT_MAX_DEPTH;
 
-use crate::ast::parser;
+use crate::ast::{parser, Rel, TermContents};
 use crate::ast::compile::Compiler;
+use crate::ast::include::{FsLoader, Includer, MemoryLoader, SourceLoader};
+use crate::diagnostic::{self, Diagnostic};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct DrawCommand {
@@ -17,6 +22,30 @@ pub struct DrawCommand {
     pub args: Vec<f32>,
 }
 
+/// The rules and stages as `load` compiled them, before the optimizer
+/// inlined the calls of `inlined` into them.
+struct Unoptimized {
+    global_rules: Vec<Clause>,
+    stages: Vec<Stage>,
+    inlined: HashSet<RelId>,
+}
+
+impl Unoptimized {
+    /// Runs [`optimize::optimize`] over `program`, keeping its rules as
+    /// compiled.
+    fn optimize(program: &mut Program) -> Self {
+        let global_rules = program.global_rules.clone();
+        let stages = program.stages.clone();
+        let inlined = optimize::optimize(program);
+        Self { global_rules, stages, inlined }
+    }
+}
+
+/// The native step functions of the stages of `program` that can run forward.
+fn forward_steps(program: &Program) -> Vec<Option<StepFn>> {
+    program.stages.iter().map(|stage| forward::compile_stage(program, stage)).collect()
+}
+
 struct TransitionQuery {
     goal: PropId,
     next_var_map: HashMap<String, TermId>,
@@ -28,11 +57,74 @@ pub struct Frontend {
     pub var_map: HashMap<String, TermId>,
     pub strategy: SearchStrategy,
     pub max_steps: usize,
+    /// The depth bound iterative deepening gets whenever the FFI selects it.
+    max_depth: usize,
     pending_queue: Option<SearchQueue>,
     pending_query_vars: Vec<(String, TermId)>,
     pub last_query_reason: Option<TerminationReason>,
     active_stage: Option<usize>,
     pub draw_cache: Vec<DrawCommand>,
+    /// Diagnostics produced by the last `load`, from syntax errors to the
+    /// lint and mode warnings the editor shows next to rules.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Files registered with `add_source`, consulted before `loader` when
+    /// resolving `Include` directives.
+    pub sources: MemoryLoader,
+    loader: Box<dyn SourceLoader>,
+    /// Native step functions for the stages whose state constraints can be
+    /// run forward, by stage index.
+    forward_steps: Vec<Option<StepFn>>,
+    /// Kept while `options.optimize` is on, so that `add_fact` can optimize
+    /// again once an inlined relation gains a fact.
+    unoptimized: Option<Unoptimized>,
+    /// Which optional passes and shortcuts are on.
+    pub options: FrontendOptions,
+}
+
+/// The passes `load` runs over the compiled program, and the shortcuts
+/// `run_stage` may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrontendOptions {
+    /// Whether `run_stage` may use the stages' native step functions
+    /// instead of the solver.
+    pub use_forward: bool,
+    /// Whether `load` simplifies the compiled program with
+    /// [`optimize::optimize`].
+    pub optimize: bool,
+    /// Whether `load` computes the Datalog-safe relations bottom-up with
+    /// [`datalog::materialize`], so that queries look them up. Off unless
+    /// asked for; [`Frontend::query_batch_bottom_up`] asks for one query.
+    pub use_datalog: bool,
+}
+
+impl FrontendOptions {
+    pub const DEFAULT: Self = Self {
+        use_forward: true,
+        optimize: true,
+        use_datalog: false,
+    };
+}
+
+impl Default for FrontendOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Looks up includes in the registered sources before falling back to the
+/// frontend's loader.
+struct FrontendLoader<'a> {
+    sources: &'a MemoryLoader,
+    fallback: &'a dyn SourceLoader,
+}
+
+impl SourceLoader for FrontendLoader<'_> {
+    fn load(&self, path: &str) -> Result<String, String> {
+        if self.sources.contains(path) {
+            return self.sources.load(path);
+        }
+        self.fallback.load(path)
+    }
 }
 
 impl Default for Frontend {
@@ -42,11 +134,18 @@ impl Default for Frontend {
             var_map: HashMap::new(),
             strategy: SearchStrategy::default(),
             max_steps: 10_000,
+            max_depth: DEFAULT_MAX_DEPTH,
             pending_queue: None,
             pending_query_vars: Vec::new(),
             last_query_reason: None,
             active_stage: None,
             draw_cache: Vec::new(),
+            diagnostics: Vec::new(),
+            sources: MemoryLoader::new(),
+            loader: Box::new(FsLoader),
+            forward_steps: Vec::new(),
+            unoptimized: None,
+            options: FrontendOptions::DEFAULT,
         }
     }
 }
@@ -56,36 +155,73 @@ impl Frontend {
         Self::default()
     }
 
+    pub fn with_options(options: FrontendOptions) -> Self {
+        Self { options, ..Self::default() }
+    }
+
+    /// Registers an in-memory file that `Include` directives can refer to.
+    pub fn add_source(&mut self, path: &str, source: &str) {
+        self.sources.insert(path, source);
+    }
+
+    /// Replaces the loader used for includes that are not registered with
+    /// `add_source`. Defaults to reading from the filesystem.
+    pub fn set_loader(&mut self, loader: Box<dyn SourceLoader>) {
+        self.loader = loader;
+    }
+
     pub fn load(&mut self, source: &str) -> Result<(), String> {
-        let result = parser::parse_module(source.into()).finish();
-        match result {
-            Ok((_, module)) => {
-                self.program = Program::default();
-                self.active_stage = None;
-                let mut compiler = Compiler::new(&mut self.program);
-                compiler.compile_module(&module);
-                self.var_map = compiler.into_var_map();
-                Ok(())
-            }
-            Err(e) => Err(format!("Parse error: {:?}", e)),
+        let loader = FrontendLoader {
+            sources: &self.sources,
+            fallback: self.loader.as_ref(),
+        };
+        // Whatever parsed is still compiled so that queries keep working
+        // against the intact parts of a file with syntax errors.
+        let mut includer = Includer::new(&loader);
+        let module = includer.load(source);
+
+        self.program = Program::default();
+        self.active_stage = None;
+        let mut compiler = Compiler::new(&mut self.program);
+        let compiled = compiler.compile_module(&module);
+        let compile_diagnostics = compiler.take_diagnostics();
+        self.var_map = compiler.into_var_map();
+        self.unoptimized = None;
+        if self.options.optimize {
+            self.unoptimized = Some(Unoptimized::optimize(&mut self.program));
+        }
+        self.forward_steps = forward_steps(&self.program);
+        if self.options.use_datalog {
+            datalog::materialize(&mut self.program);
+        }
+
+        if diagnostic::has_errors(includer.diagnostics()) {
+            let rendered: Vec<String> = includer.diagnostics().iter().map(|d| includer.render(d)).collect();
+            self.diagnostics = includer.take_diagnostics();
+            self.diagnostics.extend(compile_diagnostics);
+            return Err(format!("Parse error: {}", rendered.join("\n\n")));
         }
+        self.diagnostics = compile_diagnostics;
+
+        compiled.map_err(|err| format!("Compile error: {}", err))
     }
 
     fn push_stage_rules(&mut self, stage_index: usize) {
         if stage_index < self.program.stages.len() {
-            let rules = self.program.stages[stage_index].rules.clone();
-            self.program.global_rules.extend(rules);
+            for rule in self.program.stages[stage_index].rules.clone() {
+                self.program.add_rule(rule);
+            }
             self.active_stage = Some(stage_index);
         }
     }
 
     fn pop_stage_rules(&mut self) {
-        if let Some(stage_index) = self.active_stage.take() {
-            if stage_index < self.program.stages.len() {
-                let count = self.program.stages[stage_index].rules.len();
-                let new_len = self.program.global_rules.len().saturating_sub(count);
-                self.program.global_rules.truncate(new_len);
-            }
+        if let Some(stage_index) = self.active_stage.take()
+            && stage_index < self.program.stages.len()
+        {
+            let count = self.program.stages[stage_index].rules.len();
+            let new_len = self.program.global_rules.len().saturating_sub(count);
+            self.program.truncate_rules(new_len);
         }
     }
 
@@ -93,6 +229,29 @@ impl Frontend {
         self.query_batch_in_stage(query_str, limit, None)
     }
 
+    /// Answers `query_str`, a call of a Datalog-safe relation, from the
+    /// relation's facts computed bottom-up, even when `options.use_datalog` is off.
+    pub fn query_batch_bottom_up(&mut self, query_str: &str, limit: usize) -> Result<Vec<String>, String> {
+        let term = match parser::parse_term(query_str.into()).finish() {
+            Ok((_, term)) => term,
+            Err(e) => return Err(format!("Query parse error: {}", e)),
+        };
+        let TermContents::App { rel: Rel::UserRel { name }, .. } = &term.contents else {
+            return Err("Bottom-up queries must be a call of a single relation".to_string());
+        };
+        if !datalog::safe_relations(&self.program).contains(name) {
+            return Err(format!("`{}` is not a Datalog relation, so it can't be computed bottom-up", name));
+        }
+
+        if self.options.use_datalog {
+            return self.query_batch(query_str, limit);
+        }
+        datalog::materialize(&mut self.program);
+        let result = self.query_batch(query_str, limit);
+        datalog::clear(&mut self.program);
+        result
+    }
+
     pub fn query_batch_in_stage(
         &mut self,
         query_str: &str,
@@ -111,12 +270,21 @@ impl Frontend {
                 if stage_index.is_some() {
                     self.pop_stage_rules();
                 }
-                return Err(format!("Query parse error: {:?}", e));
+                return Err(format!("Query parse error: {}", e));
             }
         };
 
-        let (goal, query_vars) = Compiler::with_var_map(&mut self.program, self.var_map.clone())
+        let compiled = Compiler::with_var_map(&mut self.program, self.var_map.clone())
             .compile_query(&term);
+        let (goal, query_vars) = match compiled {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                if stage_index.is_some() {
+                    self.pop_stage_rules();
+                }
+                return Err(format!("Query compile error: {}", err));
+            }
+        };
 
         // State variables are synchronized via two mechanisms:
         // 1. var_map: Runtime tracking of current state variable term IDs
@@ -132,7 +300,7 @@ impl Frontend {
             solver.collect_solutions(goal, self.strategy, limit, max_steps)
         };
 
-        self.last_query_reason = Some(solution_set.reason.clone());
+        self.last_query_reason = Some(solution_set.reason);
 
         let results = solution_set
             .solutions()
@@ -168,12 +336,19 @@ impl Frontend {
             Ok((_, term)) => term,
             Err(e) => {
                 self.pop_stage_rules();
-                return Err(format!("Query parse error: {:?}", e));
+                return Err(format!("Query parse error: {}", e));
             }
         };
 
-        let (goal, query_vars) = Compiler::with_var_map(&mut self.program, self.var_map.clone())
+        let compiled = Compiler::with_var_map(&mut self.program, self.var_map.clone())
             .compile_query(&term);
+        let (goal, query_vars) = match compiled {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                self.pop_stage_rules();
+                return Err(format!("Query compile error: {}", err));
+            }
+        };
 
         self.pending_query_vars = query_vars;
 
@@ -243,6 +418,13 @@ impl Frontend {
         if self.program.stages[stage_index].state_constraints.is_empty() {
             return Ok(());
         }
+        if let Some(new_values) = self.run_stage_forward(stage_index) {
+            for (name, new_value) in &new_values {
+                self.var_map.insert(name.clone(), *new_value);
+            }
+            self.update_state_facts(&new_values);
+            return Ok(());
+        }
 
         let query = self.build_transition_query(stage_index);
 
@@ -254,6 +436,26 @@ impl Frontend {
         self.process_transition_result(solution_set, query.next_var_map, query.stage_name)
     }
 
+    /// Whether the stage at `stage_index` has a native step function, which
+    /// `run_stage` tries before the solver.
+    pub fn is_stage_compiled(&self, stage_index: usize) -> bool {
+        matches!(self.forward_steps.get(stage_index), Some(Some(_)))
+    }
+
+    fn run_stage_forward(&mut self, stage_index: usize) -> Option<Vec<(String, TermId)>> {
+        if !self.options.use_forward {
+            return None;
+        }
+        let step = self.forward_steps.get(stage_index)?.as_ref()?;
+        let values = step.run(&self.program, &self.var_map)?;
+        Some(
+            values
+                .into_iter()
+                .map(|(name, value)| (name, value.to_term(&mut self.program)))
+                .collect(),
+        )
+    }
+
     pub fn run_stage_by_name(&mut self, name: &str) -> Result<(), String> {
         let stage_index = self.program.stages
             .iter()
@@ -462,7 +664,7 @@ impl Frontend {
     fn update_state_facts(&mut self, new_values: &[(String, TermId)]) {
         let mut updated_facts = Vec::new();
 
-        let fact_ids: Vec<_> = self.program.facts.iter().copied().collect();
+        let fact_ids: Vec<_> = self.program.facts.to_vec();
         
         for fact_prop_id in fact_ids {
             let fact_prop = self.program.props.get(fact_prop_id).clone();
@@ -489,15 +691,15 @@ impl Frontend {
         new_values: &[(String, TermId)],
     ) -> Option<PropId> {
         for (name, original_term_id) in &self.program.state_var_term_ids.clone() {
-            if term_a == *original_term_id || term_b == *original_term_id {
-                if let Some((_, new_value)) = new_values.iter().find(|(n, _)| n == name) {
-                    let new_fact = if term_a == *original_term_id {
-                        self.program.props.alloc(Prop::Eq(*original_term_id, *new_value))
-                    } else {
-                        self.program.props.alloc(Prop::Eq(term_b, *new_value))
-                    };
-                    return Some(new_fact);
-                }
+            if (term_a == *original_term_id || term_b == *original_term_id)
+                && let Some((_, new_value)) = new_values.iter().find(|(n, _)| n == name)
+            {
+                let new_fact = if term_a == *original_term_id {
+                    self.program.props.alloc(Prop::Eq(*original_term_id, *new_value))
+                } else {
+                    self.program.props.alloc(Prop::Eq(term_b, *new_value))
+                };
+                return Some(new_fact);
             }
         }
         None
@@ -507,24 +709,34 @@ impl Frontend {
         let term_result = parser::parse_term(fact_str.into()).finish();
         let term = match term_result {
             Ok((_, term)) => term,
-            Err(e) => return Err(format!("Fact parse error: {:?}", e)),
+            Err(e) => return Err(format!("Fact parse error: {}", e)),
         };
 
         let prop = Compiler::with_var_map(&mut self.program, self.var_map.clone())
-            .compile_fact(&term);
-        self.program.facts.push(prop);
+            .compile_fact(&term)
+            .map_err(|err| format!("Fact compile error: {}", err))?;
+        self.program.add_fact(prop);
+
+        // The call sites of an inlined relation would never see the new fact,
+        // so the pass runs again, and now leaves the relation alone.
+        let inlined = match self.program.props.get(prop) {
+            Prop::App { rel, .. } => self.unoptimized.as_ref().is_some_and(|u| u.inlined.contains(rel)),
+            _ => false,
+        };
+        if inlined && let Some(unoptimized) = self.unoptimized.take() {
+            self.program.global_rules = unoptimized.global_rules;
+            self.program.stages = unoptimized.stages;
+            self.unoptimized = Some(Unoptimized::optimize(&mut self.program));
+            self.forward_steps = forward_steps(&self.program);
+        }
         Ok(())
     }
 
     pub fn clear_facts_by_relation(&mut self, relation_name: &str) {
-        self.program.facts.retain(|&fact_id| {
-            let prop = self.program.props.get(fact_id);
-            if let Prop::App { rel, .. } = prop {
-                let rel_data = self.program.rels.get(*rel);
-                return rel_data.name != relation_name;
-            }
-            true
-        });
+        let rels: Vec<_> = self.program.rels.iter().filter(|(_, r)| r.name == relation_name).map(|(id, _)| id).collect();
+        for rel in rels {
+            self.program.clear_facts(rel);
+        }
     }
 
     pub fn collect_draws(&mut self, stage_index: usize) -> Result<Vec<DrawCommand>, String> {
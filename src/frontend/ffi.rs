@@ -6,7 +6,8 @@ use std::os::raw::c_char;
 use nom::Finish;
 
 use crate::ast::Module;
-use crate::ast::parser;
+use crate::ast::{format, parser};
+use crate::diagnostic::Severity;
 use crate::solver::SearchStrategy;
 
 use super::Frontend;
@@ -21,12 +22,18 @@ pub unsafe extern "C" fn free_frontend(frontend: *mut Frontend) {
     unsafe { std::ptr::drop_in_place(frontend) }
 }
 
+/// Strategies are numbered 0 for BFS, 1 for DFS, 2 for interleaving, 3 for
+/// iterative deepening, bounded by the last `frontend_set_max_depth` or the
+/// default, and 4 for best-first.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn frontend_set_strategy(frontend: *mut Frontend, strategy: i32) {
     unsafe {
         (*frontend).strategy = match strategy {
             0 => SearchStrategy::BFS,
             1 => SearchStrategy::DFS,
+            2 => SearchStrategy::Interleave,
+            3 => SearchStrategy::IterativeDeepening { max_depth: (*frontend).max_depth },
+            4 => SearchStrategy::BestFirst,
             _ => SearchStrategy::BFS,
         };
     }
@@ -38,6 +45,31 @@ pub unsafe extern "C" fn frontend_get_strategy(frontend: *mut Frontend) -> i32 {
         match (*frontend).strategy {
             SearchStrategy::BFS => 0,
             SearchStrategy::DFS => 1,
+            SearchStrategy::Interleave => 2,
+            SearchStrategy::IterativeDeepening { .. } => 3,
+            SearchStrategy::BestFirst => 4,
+        }
+    }
+}
+
+/// Sets the depth bound of iterative deepening, now if it is selected and
+/// whenever it is selected later.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_set_max_depth(frontend: *mut Frontend, max_depth: i32) {
+    unsafe {
+        (*frontend).max_depth = max_depth.max(1) as usize;
+        if let SearchStrategy::IterativeDeepening { .. } = (*frontend).strategy {
+            (*frontend).strategy = SearchStrategy::IterativeDeepening { max_depth: (*frontend).max_depth };
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_get_max_depth(frontend: *mut Frontend) -> i32 {
+    unsafe {
+        match (*frontend).strategy {
+            SearchStrategy::IterativeDeepening { max_depth } => max_depth as i32,
+            _ => (*frontend).max_depth as i32,
         }
     }
 }
@@ -67,6 +99,74 @@ pub unsafe extern "C" fn frontend_load(frontend: *mut Frontend, source: *const c
     }
 }
 
+/// Registers an in-memory file for `Include` directives in later loads.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_add_source(
+    frontend: *mut Frontend,
+    path: *const c_char,
+    source: *const c_char,
+) {
+    unsafe {
+        let path_str = CStr::from_ptr(path).to_str().unwrap_or("");
+        let source_str = CStr::from_ptr(source).to_str().unwrap_or("");
+        (*frontend).add_source(path_str, source_str);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_clear_sources(frontend: *mut Frontend) {
+    unsafe { (*frontend).sources.clear() }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_diagnostic_count(frontend: *mut Frontend) -> i32 {
+    unsafe { (*frontend).diagnostics.len() as i32 }
+}
+
+/// 0 = error, 1 = warning, 2 = note, -1 if the index is out of range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_diagnostic_severity(frontend: *mut Frontend, index: i32) -> i32 {
+    unsafe {
+        match (&(*frontend).diagnostics).get(index as usize) {
+            Some(diag) => match diag.severity {
+                Severity::Error => 0,
+                Severity::Warning => 1,
+                Severity::Note => 2,
+            },
+            None => -1,
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_diagnostic_message(frontend: *mut Frontend, index: i32) -> *mut c_char {
+    unsafe {
+        if let Some(diag) = (&(*frontend).diagnostics).get(index as usize) {
+            CString::new(diag.message.clone()).unwrap().into_raw()
+        } else {
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Writes `[start_line, start_column, end_line, end_column]` of a diagnostic
+/// into `out`. Returns 0 if the index is out of range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn frontend_diagnostic_span(frontend: *mut Frontend, index: i32, out: *mut i32) -> i32 {
+    unsafe {
+        if let Some(diag) = (&(*frontend).diagnostics).get(index as usize) {
+            let span = &diag.span;
+            *out = span.start.line as i32;
+            *out.add(1) = span.start.column as i32;
+            *out.add(2) = span.end.line as i32;
+            *out.add(3) = span.end.column as i32;
+            1
+        } else {
+            0
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn frontend_query(frontend: *mut Frontend, query: *const c_char) -> *mut c_char {
     unsafe {
@@ -243,6 +343,18 @@ pub unsafe extern "C" fn module_to_string(module: *mut Module) -> *mut c_char {
     }
 }
 
+/// Formats `.l` source text, returning null if it does not parse.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn format_source(input: *const c_char) -> *mut c_char {
+    unsafe {
+        let inp = CStr::from_ptr(input).to_str().unwrap_or("");
+        match format::format_source(inp) {
+            Ok(formatted) => CString::new(formatted).unwrap().into_raw(),
+            Err(_e) => std::ptr::null_mut(),
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn free_module(module: *mut Module) {
     unsafe { std::ptr::drop_in_place(module) }
@@ -362,10 +474,10 @@ pub unsafe extern "C" fn frontend_draw_command_arg_count(frontend: *mut Frontend
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn frontend_draw_command_arg(frontend: *mut Frontend, index: i32, arg_index: i32) -> f32 {
     unsafe {
-        if let Some(cmd) = (&(*frontend).draw_cache).get(index as usize) {
-            if let Some(&arg) = cmd.args.get(arg_index as usize) {
-                return arg;
-            }
+        if let Some(cmd) = (&(*frontend).draw_cache).get(index as usize)
+            && let Some(&arg) = cmd.args.get(arg_index as usize)
+        {
+            return arg;
         }
         0.0
     }
@@ -46,6 +46,21 @@ mod tests {
         assert!(query_succeeds(&mut frontend, "eq(X, Y)"));
     }
 
+    #[test]
+    fn test_list_literals_print_as_lists() {
+        let mut frontend = Frontend::new();
+        frontend.load(
+            "Begin Facts:\n    items([sword, shield])\n    open([1 | T])\nEnd Facts\n\nBegin Global:\nEnd Global\n",
+        )
+        .unwrap();
+
+        let items = frontend.query_batch("items(L)", 10).unwrap();
+        assert!(items[0].contains("L = [sword, shield]"), "{:?}", items);
+        let open = frontend.query_batch("open(L)", 10).unwrap();
+        assert!(open[0].contains("L = [1 | ?T]"), "{:?}", open);
+        assert!(query_succeeds(&mut frontend, "items(cons(sword, Rest))"));
+    }
+
     #[test]
     fn test_eq_fact_with_rule_present() {
         let mut frontend = Frontend::new();
@@ -238,4 +253,203 @@ End Stage Draw
         assert_eq!(draws.len(), 1);
         assert_eq!(draws[0].args, vec![1.0, 0.0, 1.0, 1.0]);
     }
+
+    #[test]
+    fn test_load_reports_located_parse_error() {
+        let mut frontend = Frontend::new();
+        let result = frontend.load("Begin Facts:\n    a(1)\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage S:\nEnd Stage T\n");
+
+        assert!(result.is_err());
+        assert_eq!(frontend.diagnostics.len(), 1);
+        assert_eq!(frontend.diagnostics[0].span.start.line, 9);
+        assert!(result.unwrap_err().contains("9:11"));
+    }
+
+    #[test]
+    fn test_load_reports_all_parse_errors_and_keeps_good_rules() {
+        let mut frontend = Frontend::new();
+        let result = frontend.load(
+            "Begin Facts:\n    base(1)\n    oops(\nEnd Facts\n\nBegin Global:\nRule Bad:\n    base(X)\n    ====\n    bad(X)\nRule Good:\n    base(X)\n    ----\n    good(X)\nEnd Global\n",
+        );
+
+        assert!(result.is_err());
+        assert_eq!(frontend.diagnostics.len(), 2);
+        assert!(query_succeeds(&mut frontend, "good(1)"));
+        assert!(query_fails(&mut frontend, "bad(1)"));
+    }
+
+    #[test]
+    fn test_load_resolves_includes_from_added_sources() {
+        let mut frontend = Frontend::new();
+        frontend.add_source(
+            "rules/move.l",
+            "Begin Facts:\nEnd Facts\n\nBegin Global:\nRule Move:\n    pos(X)\n    ----\n    moved(X)\nEnd Global\n",
+        );
+        frontend.load(
+            "Include \"rules/move.l\"\n\nBegin Facts:\n    pos(3)\nEnd Facts\n\nBegin Global:\nEnd Global\n",
+        )
+        .unwrap();
+
+        assert!(query_succeeds(&mut frontend, "moved(3)"));
+    }
+
+    #[test]
+    fn test_load_reports_missing_include() {
+        let mut frontend = Frontend::new();
+        let result = frontend.load(
+            "Include \"does/not/exist.l\"\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n",
+        );
+
+        assert!(result.is_err());
+        assert_eq!(frontend.diagnostics.len(), 1);
+        assert!(frontend.diagnostics[0].message.starts_with("cannot include `does/not/exist.l`"));
+    }
+
+    #[test]
+    fn test_load_reports_compile_diagnostic() {
+        let mut frontend = Frontend::new();
+        let result = frontend.load("Begin Facts:\nEnd Facts\n\nBegin Global:\n    Rule Bad:\n    a(X) & b(X)\n    ----\n    42\nEnd Global\n");
+
+        assert!(result.is_err());
+        assert!(frontend.diagnostics[0].is_error());
+        assert_eq!(frontend.diagnostics[0].span.start.line, 8);
+    }
+
+    #[test]
+    fn test_query_and_fact_compile_errors_are_returned() {
+        let mut frontend = Frontend::new();
+        frontend.load("Begin Facts:\n    a(1)\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap();
+
+        let err = frontend.query_batch("a(X) & not(a(1), a(2))", 10).unwrap_err();
+        assert_eq!(err, "Query compile error: 1:8: error: `not` takes 1 argument but 2 were given");
+        let err = frontend.query_start("preserve(1)", None).unwrap_err();
+        assert!(err.contains("`preserve` expects state variables"));
+        assert!(frontend.add_fact("cond(a(2), a(3))").is_err());
+
+        assert!(query_succeeds(&mut frontend, "a(1)"));
+        assert!(query_fails(&mut frontend, "a(3)"));
+    }
+
+    #[test]
+    fn test_underscore_matches_independently() {
+        let mut frontend = Frontend::new();
+        frontend.load(
+            "Begin Facts:\n    item(sword, 10)\n    item(shield, 20)\n    pair(1, 2)\nEnd Facts\n\nBegin Global:\n    Rule Owned:\n    item(I, _)\n    ----------\n    owned(I)\nEnd Global\n",
+        )
+        .unwrap();
+
+        assert!(query_succeeds(&mut frontend, "pair(_, _)"));
+        let results = frontend.query_batch("owned(I) & item(_, 20)", 10).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| !r.contains('_')));
+    }
+
+    #[test]
+    fn test_stdlib_is_loaded_only_when_used() {
+        let mut frontend = Frontend::new();
+        frontend.load("Use lists\nUse parity\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap();
+
+        let lengths = frontend.query_batch("length([a, b, c], N)", 10).unwrap();
+        assert!(lengths[0].contains("N = 3"), "{:?}", lengths);
+        assert!(query_succeeds(&mut frontend, "reverseOf([1, 2, 3], [3, 2, 1])"));
+        assert_eq!(frontend.query_batch("even(4)", 1).unwrap(), ["yes"]);
+        assert!(query_fails(&mut frontend, "odd(0)"));
+        assert!(query_fails(&mut frontend, "even(1)"));
+
+        frontend.load("Use lists\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap();
+        assert!(query_fails(&mut frontend, "even(4)"));
+    }
+
+    #[test]
+    fn test_list_base_cases_need_no_true_fact() {
+        let mut frontend = Frontend::new();
+        frontend.load("Use lists\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n").unwrap();
+
+        assert_eq!(frontend.query_batch("length([a, b], N)", 10).unwrap(), ["N = 2"]);
+        assert!(query_succeeds(&mut frontend, "head([a, b], a)"));
+        assert!(query_succeeds(&mut frontend, "nth([a, b, c], 2, c)"));
+        assert!(query_succeeds(&mut frontend, "append([a], [b], [a, b])"));
+    }
+
+    #[test]
+    fn test_user_rules_shadow_stdlib_rules() {
+        let mut frontend = Frontend::new();
+        frontend.load(
+            "Use lists\n\nBegin Facts:\n    eq(X, 7)\nEnd Facts\n\nBegin Global:\n    Rule AppendOne:\n    true\n    ------\n    append(nil, Y, cons(Y, nil))\nEnd Global\n",
+        )
+        .unwrap();
+
+        let appended = frontend.query_batch("append(nil, 1, L)", 10).unwrap();
+        assert_eq!(appended.len(), 1, "{:?}", appended);
+        assert!(appended[0].contains("L = [1]"), "{:?}", appended);
+        assert!(query_fails(&mut frontend, "append(nil, [1], [1])"));
+        // The library's own `reverseOf` still calls the library's `append`,
+        // and its variables are not the program's `X`.
+        assert!(query_succeeds(&mut frontend, "reverseOf([1, 2], [2, 1])"));
+        assert!(query_succeeds(&mut frontend, "contains([1, 2], 2)"));
+    }
+
+    #[test]
+    fn test_unknown_library_module_is_an_error() {
+        let mut frontend = Frontend::new();
+        let result = frontend.load("Use strings\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n");
+
+        let err = result.unwrap_err();
+        assert!(err.contains("1:1: error: unknown library module `strings`"), "{}", err);
+    }
+
+    #[test]
+    fn test_generic_arithmetic_follows_its_arguments() {
+        let mut frontend = Frontend::new();
+        frontend.load(
+            "Begin Facts:\n    StateVar Y\n    StateVar Hits\n    Y = 0.5\n    Hits = 0\nEnd Facts\n\nBegin Global:\nEnd Global\n\nBegin Stage Fall:\n    Begin State Constraints:\n        add(Y, 1, next(Y))\n        add(Hits, 1, next(Hits))\n    End State Constraints\nEnd Stage Fall\n",
+        )
+        .unwrap();
+
+        frontend.run_stage(0).unwrap();
+        assert_eq!(frontend.get_state_var("Y").as_deref(), Some("1.5"));
+        assert_eq!(frontend.get_state_var("Hits").as_deref(), Some("1"));
+
+        let sums = frontend.query_batch("add(2, 3, X) & lt(X, 2.5)", 10).unwrap();
+        assert!(sums.is_empty(), "{:?}", sums);
+        let err = frontend.query_batch("lt(A, B)", 10).unwrap_err();
+        assert!(err.contains("cannot tell whether `lt` is Int or Real"), "{}", err);
+    }
+
+    #[test]
+    fn test_max_depth_applies_whenever_deepening_is_chosen() {
+        use crate::frontend::ffi::{frontend_get_max_depth, frontend_set_max_depth, frontend_set_strategy};
+        use crate::solver::SearchStrategy;
+
+        let mut frontend = Frontend::new();
+        let ptr: *mut Frontend = &mut frontend;
+        unsafe {
+            frontend_set_max_depth(ptr, 12);
+            assert_eq!(frontend_get_max_depth(ptr), 12);
+            frontend_set_strategy(ptr, 3);
+            assert_eq!((*ptr).strategy, SearchStrategy::IterativeDeepening { max_depth: 12 });
+            frontend_set_max_depth(ptr, 20);
+            assert_eq!((*ptr).strategy, SearchStrategy::IterativeDeepening { max_depth: 20 });
+            frontend_set_strategy(ptr, 0);
+            frontend_set_strategy(ptr, 3);
+            assert_eq!((*ptr).strategy, SearchStrategy::IterativeDeepening { max_depth: 20 });
+        }
+    }
+
+    #[test]
+    fn test_queries_follow_the_state_var_types() {
+        let mut frontend = Frontend::new();
+        frontend.load(
+            "Begin Facts:\n    StateVar Speed\n    Speed = 1.5\nEnd Facts\n\nBegin Global:\nEnd Global\n",
+        )
+        .unwrap();
+
+        assert!(query_succeeds(&mut frontend, "lt(Speed, 2)"));
+        assert!(query_fails(&mut frontend, "lt(Speed, 1)"));
+        for query in ["add(Speed, 1, X)", "X = Speed + 1"] {
+            let answers = frontend.query_batch(query, 10).unwrap();
+            assert!(answers.len() == 1 && answers[0].contains("X = 2.5"), "{:?}", answers);
+        }
+        frontend.add_fact("gt(Speed, 1)").unwrap();
+    }
 }
@@ -1,6 +1,7 @@
 #![allow(clippy::missing_safety_doc)]
 
 pub mod ast;
+pub mod diagnostic;
 pub mod solver;
 pub mod frontend;
 
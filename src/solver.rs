@@ -1,10 +1,41 @@
+pub mod analysis;
+pub mod codegen;
+pub mod datalog;
 mod engine;
+pub mod forward;
+pub mod index;
 pub mod ir;
+pub mod modes;
+pub mod optimize;
 
 pub use engine::{
     format_solution, reify_term, ArithConstraint, ConstraintStore, SearchQueue, SearchStrategy,
-    Solver, State, Subst, SolutionSet, TerminationReason,
+    DEFAULT_MAX_DEPTH, Solver, State, Subst, SolutionSet, TerminationReason,
 };
 
 #[cfg(test)]
 mod state_tests;
+
+#[cfg(test)]
+mod analysis_tests;
+
+#[cfg(test)]
+mod modes_tests;
+
+#[cfg(test)]
+mod forward_tests;
+
+#[cfg(test)]
+mod codegen_tests;
+#[cfg(test)]
+mod optimize_tests;
+#[cfg(test)]
+mod index_tests;
+#[cfg(test)]
+mod tabling_tests;
+#[cfg(test)]
+mod datalog_tests;
+#[cfg(test)]
+mod search_tests;
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,168 @@
+//! Checks over a compiled [`Program`] for mistakes the compiler lets through.
+//!
+//! Lowering registers any unknown name as a user relation, so a typo such as
+//! `colided()` compiles fine and only shows up as a stage that never fires.
+//! This pass warns about relations that are referenced but never defined by
+//! a fact or a rule head, and about relations used with more than one arity,
+//! suggesting the nearest existing name in both cases.
+//!
+//! Relations with a `Relation` signature count as defined even without
+//! facts, since a host may supply them at runtime through `add_fact`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::SourceSpan;
+use crate::diagnostic::{edit_distance, Diagnostic};
+use crate::solver::ir::{Clause, Program, Prop, PropId, RelId, RelKind};
+
+struct Use {
+    rel: RelId,
+    arity: usize,
+    span: SourceSpan,
+    /// Whether this use defines the relation (a fact or a rule head) rather
+    /// than asking for it.
+    defines: bool,
+}
+
+struct Analysis<'a> {
+    program: &'a Program,
+    uses: Vec<Use>,
+}
+
+impl<'a> Analysis<'a> {
+    fn visit_prop(&mut self, prop: PropId, span: &SourceSpan, defines: bool) {
+        let span = self.program.prop_spans.get(&prop).unwrap_or(span);
+        match self.program.props.get(prop) {
+            Prop::True | Prop::False | Prop::Eq(..) => {}
+            Prop::And(lhs, rhs) | Prop::Or(lhs, rhs) => {
+                self.visit_prop(*lhs, span, defines);
+                self.visit_prop(*rhs, span, defines);
+            }
+            Prop::Not(inner) => self.visit_prop(*inner, span, defines),
+            Prop::Cond(guard, then, otherwise) => {
+                self.visit_prop(*guard, span, defines);
+                self.visit_prop(*then, span, defines);
+                self.visit_prop(*otherwise, span, defines);
+            }
+            Prop::App { rel, args } => self.uses.push(Use {
+                rel: *rel,
+                arity: args.len(),
+                span: span.clone(),
+                defines,
+            }),
+        }
+    }
+
+    fn visit_clause(&mut self, clause: &Clause) {
+        self.uses.push(Use {
+            rel: clause.head_rel,
+            arity: clause.head_args.len(),
+            span: clause.span.clone(),
+            defines: true,
+        });
+        self.visit_prop(clause.body, &clause.span, false);
+    }
+
+    fn visit_program(&mut self) {
+        let program = self.program;
+        for &fact in &program.facts {
+            self.visit_prop(fact, &SourceSpan::default(), true);
+        }
+        for clause in &program.global_rules {
+            self.visit_clause(clause);
+        }
+        for stage in &program.stages {
+            for clause in &stage.rules {
+                self.visit_clause(clause);
+            }
+            for &constraint in &stage.state_constraints {
+                self.visit_prop(constraint, &stage.span, false);
+            }
+            for directive in &stage.draw_directives {
+                self.visit_prop(directive.condition, &stage.span, false);
+            }
+        }
+    }
+
+    fn is_defined(&self, rel: RelId) -> bool {
+        self.program.rels.get(rel).kind != RelKind::User
+            || self.program.declared_rels.contains(&rel)
+            || self.uses.iter().any(|u| u.rel == rel && u.defines)
+    }
+
+    /// The defined relation whose name is closest to `name`, preferring
+    /// ones that take `arity` arguments.
+    fn suggest(&self, rel: RelId, arity: usize) -> Option<&'a str> {
+        let name = &self.program.rels.get(rel).name;
+        let threshold = name.chars().count() / 3;
+        self.program
+            .rels
+            .iter()
+            .filter(|&(id, _)| id != rel && self.is_defined(id))
+            .map(|(_, info)| (info.arity != arity, edit_distance(name, &info.name), info.name.as_str()))
+            .filter(|&(_, distance, _)| distance <= threshold)
+            .min()
+            .map(|(_, _, candidate)| candidate)
+    }
+
+    fn undefined_relations(&self) -> Vec<Diagnostic> {
+        let mut reported = HashSet::new();
+        let mut diagnostics = Vec::new();
+        for u in &self.uses {
+            if u.defines || self.is_defined(u.rel) || !reported.insert(u.rel) {
+                continue;
+            }
+            let name = &self.program.rels.get(u.rel).name;
+            let mut message = format!("`{}` is never defined by a fact or a rule", name);
+            if let Some(candidate) = self.suggest(u.rel, u.arity) {
+                message.push_str(&format!("; did you mean `{}`?", candidate));
+            }
+            diagnostics.push(Diagnostic::warning(message, u.span.clone()));
+        }
+        diagnostics
+    }
+
+    fn arity_mismatches(&self) -> Vec<Diagnostic> {
+        // The first use of each relation fixes its arity.
+        let mut first: HashMap<RelId, &Use> = HashMap::new();
+        let mut reported = HashSet::new();
+        let mut diagnostics = Vec::new();
+        for u in &self.uses {
+            let info = self.program.rels.get(u.rel);
+            if info.kind != RelKind::User || self.program.declared_rels.contains(&u.rel) {
+                continue;
+            }
+            let expected = *first.entry(u.rel).or_insert(u);
+            if expected.arity == u.arity || !reported.insert((u.rel, u.arity)) {
+                continue;
+            }
+            let mut message = format!(
+                "`{}` is used with {} argument{} here but with {} at {}",
+                info.name,
+                u.arity,
+                if u.arity == 1 { "" } else { "s" },
+                expected.arity,
+                expected.span,
+            );
+            if let Some(candidate) = self.suggest(u.rel, u.arity) {
+                message.push_str(&format!("; did you mean `{}`?", candidate));
+            }
+            diagnostics.push(Diagnostic::warning(message, u.span.clone()));
+        }
+        diagnostics
+    }
+}
+
+/// Warns about relations that are referenced but never defined, and about
+/// relations used with inconsistent arities.
+pub fn check_program(program: &Program) -> Vec<Diagnostic> {
+    let mut analysis = Analysis {
+        program,
+        uses: Vec::new(),
+    };
+    analysis.visit_program();
+
+    let mut diagnostics = analysis.undefined_relations();
+    diagnostics.extend(analysis.arity_mismatches());
+    diagnostics
+}
@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod tests {
+    use crate::ast::parser::parse_source;
+    use crate::frontend::Frontend;
+    use crate::solver::analysis::check_program;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let mut frontend = Frontend::new();
+        frontend.load(source).unwrap();
+        frontend.diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_undefined_relation_suggests_nearest_name() {
+        let source = r#"Begin Facts:
+    collided(player)
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Hit:
+    Rule Bounce:
+    colided(E)
+    ----------
+    bounced(E)
+End Stage Hit
+"#;
+        assert_eq!(
+            warnings(source),
+            vec!["10:5: warning: `colided` is never defined by a fact or a rule; did you mean `collided`?"]
+        );
+    }
+
+    #[test]
+    fn test_undefined_relation_without_near_name() {
+        let source = "Begin Facts:\n    tree(oak)\nEnd Facts\n\nBegin Global:\n    Rule Shade:\n    weather(sunny) & tree(T)\n    ------------------------\n    shade(T)\nEnd Global\n";
+        assert_eq!(
+            warnings(source),
+            vec!["7:5: warning: `weather` is never defined by a fact or a rule"]
+        );
+    }
+
+    #[test]
+    fn test_arity_mismatch_is_reported_once() {
+        let source = r#"Begin Facts:
+    pos(player, 0, 0)
+    pos2(player, 1)
+End Facts
+
+Begin Global:
+    Rule Moved:
+    pos(E, X) & pos(E, Y)
+    ---------------------
+    moved(E, X, Y)
+End Global
+"#;
+        assert_eq!(
+            warnings(source),
+            vec!["8:5: warning: `pos` is used with 2 arguments here but with 3 at 2:5; did you mean `pos2`?"]
+        );
+    }
+
+    #[test]
+    fn test_declared_relations_are_left_to_the_type_checker() {
+        let source = "Relation pos(Entity, Int)\n\nBegin Facts:\n    pos(player, 0)\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+        let module = parse_source(source).unwrap();
+        let program = crate::ast::compile::compile(&module);
+        assert!(check_program(&program).is_empty());
+    }
+
+    #[test]
+    fn test_samples_have_no_undefined_relations() {
+        for path in ["sample/runner.l", "sample/inventory.l", "sample/state_basic.l"] {
+            let source = std::fs::read_to_string(path).unwrap();
+            let mut frontend = Frontend::new();
+            frontend.load(&source).unwrap();
+            assert_eq!(check_program(&frontend.program), vec![], "{}", path);
+        }
+    }
+}
@@ -0,0 +1,996 @@
+//! Rust code generation, for shipping a game without the solver.
+//!
+//! `generate_rust` turns a compiled program into a standalone Rust module: a
+//! `State` struct with one field per state variable, starting from the
+//! values the facts give them, a `run_<stage>` function for each stage with
+//! state constraints, and a `draw` function returning the draw commands of
+//! every stage. Everything is planned as by the forward pass, so it must run
+//! without search. Relations called with known arguments become functions
+//! that try each fact and rule in turn, and relations declared with
+//! `Relation` but given no rules become methods of a `Host` trait the game
+//! implements, as they are filled in through `add_fact` under the solver.
+//!
+//! Reals become `f32` and use `f32` arithmetic, which can differ from the
+//! solver's exact rationals in the last bits.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::ast::compile::CompileError;
+use crate::ast::SourceSpan;
+use crate::diagnostic::Diagnostic;
+use crate::solver::forward::{self, ArithOp, Cmp, Expr, Family, Input, Pattern, Plan, Step, Value};
+use crate::solver::ir::{Program, Prop, RelId, Term, VarId};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Ty {
+    Int,
+    Real,
+    Atom,
+}
+
+impl Ty {
+    fn of(family: Family) -> Ty {
+        match family {
+            Family::Int => Ty::Int,
+            Family::Real => Ty::Real,
+        }
+    }
+
+    fn rust(self) -> &'static str {
+        match self {
+            Ty::Int => "i32",
+            Ty::Real => "f32",
+            Ty::Atom => "Atom",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Ty::Int => "an Int",
+            Ty::Real => "a Real",
+            Ty::Atom => "an atom",
+        }
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+    "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self",
+    "Self", "static", "struct", "super", "trait", "true", "try", "type", "unsafe", "use", "where", "while", "yield",
+];
+
+/// `RunnerVY` as `runner_vy`, with a trailing `_` on keywords.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.trim_start_matches('_').chars().collect();
+    let mut out = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let boundary = match i.checked_sub(1).map(|j| chars[j]) {
+                Some(prev) if prev.is_lowercase() || prev.is_ascii_digit() => true,
+                Some(prev) if prev.is_uppercase() => chars.get(i + 1).is_some_and(|next| next.is_lowercase()),
+                _ => false,
+            };
+            if boundary && !out.ends_with('_') {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else if c.is_alphanumeric() {
+            out.push(c);
+        } else {
+            // Shadowed library relations are named `lists.append`.
+            out.push('_');
+        }
+    }
+    if !out.starts_with(|c: char| c.is_alphabetic()) {
+        out.insert(0, 'v');
+    }
+    unkeyword(out)
+}
+
+/// `space_bar` as `SpaceBar`.
+fn camel_case(name: &str) -> String {
+    let mut out: String = name
+        .split('_')
+        .flat_map(|part| {
+            let mut chars = part.chars();
+            chars.next().into_iter().flat_map(char::to_uppercase).chain(chars)
+        })
+        .collect();
+    if !out.starts_with(|c: char| c.is_alphabetic()) {
+        out.insert(0, 'A');
+    }
+    unkeyword(out)
+}
+
+fn unkeyword(name: String) -> String {
+    if KEYWORDS.contains(&name.as_str()) { format!("{}_", name) } else { name }
+}
+
+/// Wraps negative literals, which need it to have methods called on them.
+fn receiver(expr: &str) -> String {
+    if expr.starts_with('-') { format!("({})", expr) } else { expr.to_string() }
+}
+
+/// Lines of Rust at some indentation.
+struct Code {
+    text: String,
+    indent: usize,
+}
+
+impl Code {
+    fn new(indent: usize) -> Code {
+        Code { text: String::new(), indent }
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.text.push_str("    ");
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn open(&mut self, line: &str) {
+        self.line(line);
+        self.indent += 1;
+    }
+
+    fn close(&mut self, line: &str) {
+        self.indent -= 1;
+        self.line(line);
+    }
+
+    fn nested(&self) -> Code {
+        Code::new(self.indent + 1)
+    }
+
+    fn append(&mut self, nested: Code) {
+        self.text.push_str(&nested.text);
+    }
+
+    /// The same lines one level further out.
+    fn dedented(self) -> Code {
+        let text = self.text.lines().map(|line| format!("{}\n", line.strip_prefix("    ").unwrap_or(line))).collect();
+        Code { text, indent: self.indent - 1 }
+    }
+
+    /// Ends a block with `result`, folding a final `let result = value;`
+    /// into `value`.
+    fn finish_with(&mut self, result: &str) {
+        let prefix = format!("let {} = ", result);
+        let last = self.text.lines().last().map(str::trim_start);
+        if let Some(value) = last.and_then(|l| l.strip_prefix(&prefix)).and_then(|v| v.strip_suffix(';')) {
+            let value = value.to_string();
+            let cut = self.text.trim_end_matches('\n').rfind('\n').map_or(0, |i| i + 1);
+            self.text.truncate(cut);
+            self.line(&value);
+        } else {
+            self.line(result);
+        }
+    }
+
+    /// `if cond { fail; }`.
+    fn guard(&mut self, cond: &str, fail: &str) {
+        self.open(&format!("if {} {{", cond));
+        self.line(&format!("{};", fail));
+        self.close("}");
+    }
+}
+
+/// What a step does when it fails: return from the function, or break out
+/// of the labeled block of an enclosing `not` or `when`.
+struct Fail {
+    stmt: String,
+    label: Option<String>,
+}
+
+impl Fail {
+    fn returning(stmt: &str) -> Fail {
+        Fail { stmt: stmt.to_string(), label: None }
+    }
+
+    fn breaking(label: &str, value: &str) -> Fail {
+        let stmt = if value.is_empty() { format!("break '{}", label) } else { format!("break '{} {}", label, value) };
+        Fail { stmt, label: Some(label.to_string()) }
+    }
+}
+
+struct StateField {
+    name: String,
+    field: String,
+    ty: Ty,
+    initial: String,
+}
+
+/// A user relation the generated code calls.
+struct RelFn {
+    rel: RelId,
+    name: String,
+    params: Vec<Ty>,
+    /// Declared relations without facts or rules are left to the host.
+    host_only: bool,
+}
+
+struct Generator<'p> {
+    program: &'p Program,
+    fields: Vec<StateField>,
+    /// Values the facts give variables other than the state variables.
+    constants: HashMap<VarId, Value>,
+    /// Atom enum variants, with the atom each stands for.
+    atoms: BTreeMap<String, String>,
+    rels: Vec<RelFn>,
+    /// Names of the module's items.
+    items: HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'p> Generator<'p> {
+    fn error(&mut self, message: String, span: &SourceSpan) {
+        self.diagnostics.push(Diagnostic::error(message, span.clone()));
+    }
+
+    fn item_name(&mut self, base: String) -> String {
+        let mut name = base.clone();
+        let mut n = 2;
+        while !self.items.insert(name.clone()) {
+            name = format!("{}{}", base, n);
+            n += 1;
+        }
+        name
+    }
+
+    fn atom(&mut self, text: &str) -> Result<String, String> {
+        let variant = camel_case(text);
+        match self.atoms.get(&variant) {
+            Some(other) if other != text => {
+                Err(format!("atoms `{}` and `{}` would both become `Atom::{}`", other, text, variant))
+            }
+            _ => {
+                self.atoms.insert(variant.clone(), text.to_string());
+                Ok(format!("Atom::{}", variant))
+            }
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> Result<(String, Ty), String> {
+        match value {
+            Value::Int(i) => Ok((i.to_string(), Ty::Int)),
+            Value::Real(f) => Ok((format!("{:?}", f), Ty::Real)),
+            Value::Atom(sym) => Ok((self.atom(self.program.symbols.get(*sym))?, Ty::Atom)),
+            Value::App(sym, _) => Err(format!(
+                "compound terms such as `{}(...)` are not supported in generated code",
+                self.program.symbols.get(*sym)
+            )),
+        }
+    }
+
+    /// The function or host method for `rel`, checking the argument types
+    /// agree with earlier calls.
+    fn call(&mut self, rel: RelId, params: Vec<Ty>) -> Result<(String, bool), String> {
+        let name = &self.program.rels.get(rel).name;
+        if let Some(existing) = self.rels.iter().find(|r| r.rel == rel) {
+            if let Some(i) = (0..params.len()).find(|&i| existing.params[i] != params[i]) {
+                return Err(format!(
+                    "`{}` is called with {} as argument {} here but with {} elsewhere",
+                    name,
+                    params[i].describe(),
+                    i + 1,
+                    existing.params[i].describe()
+                ));
+            }
+            return Ok((existing.name.clone(), existing.host_only));
+        }
+
+        if self.program.tabled_rels.contains(&rel) {
+            return Err(format!("`{}` is tabled, and only the solver keeps answer tables", name));
+        }
+        let has_facts = self
+            .program
+            .facts
+            .iter()
+            .filter(|&&f| !self.program.datalog.is_derived(f))
+            .any(|&f| matches!(self.program.props.get(f), Prop::App { rel: r, .. } if *r == rel));
+        let has_rules = self.program.global_rules.iter().any(|c| c.head_rel == rel);
+        let declared = self.program.declared_rels.contains(&rel);
+        if !has_facts && !has_rules && !declared {
+            return Err(format!("`{}` is never defined by a fact or a rule", name));
+        }
+        let host_only = declared && !has_facts && !has_rules;
+        let fn_name = if host_only { snake_case(name) } else { self.item_name(snake_case(name)) };
+        self.rels.push(RelFn { rel, name: fn_name.clone(), params, host_only });
+        Ok((fn_name, host_only))
+    }
+}
+
+/// Emits the steps of one plan as the body of a function or block.
+struct FnEmitter<'g, 'p> {
+    generator: &'g mut Generator<'p>,
+    plan: &'g Plan,
+    inputs: HashMap<usize, Input>,
+    names: HashMap<usize, String>,
+    types: HashMap<usize, Ty>,
+    taken: HashSet<String>,
+    labels: usize,
+    used_labels: HashSet<String>,
+    uses_state: bool,
+    uses_host: bool,
+}
+
+impl<'g, 'p> FnEmitter<'g, 'p> {
+    fn new(generator: &'g mut Generator<'p>, plan: &'g Plan) -> FnEmitter<'g, 'p> {
+        FnEmitter {
+            generator,
+            plan,
+            inputs: plan.inputs.iter().map(|i| (i.slot, i.clone())).collect(),
+            names: HashMap::new(),
+            types: HashMap::new(),
+            taken: ["state", "host", "draws"].iter().map(|s| s.to_string()).collect(),
+            labels: 0,
+            used_labels: HashSet::new(),
+            uses_state: false,
+            uses_host: false,
+        }
+    }
+
+    fn fresh(&mut self, base: &str) -> String {
+        let mut name = base.to_string();
+        let mut n = 2;
+        while !self.taken.insert(name.clone()) {
+            name = format!("{}{}", base, n);
+            n += 1;
+        }
+        name
+    }
+
+    fn label(&mut self, base: &str) -> String {
+        self.labels += 1;
+        let label = format!("{}{}", base, self.labels);
+        self.taken.insert(label.clone());
+        label
+    }
+
+    fn fail(&mut self, fail: &Fail) -> String {
+        if let Some(label) = &fail.label {
+            self.used_labels.insert(label.clone());
+        }
+        fail.stmt.clone()
+    }
+
+    /// The Rust name of the variable in `slot`, the same wherever it's bound.
+    fn name(&mut self, slot: usize) -> String {
+        if let Some(name) = self.names.get(&slot) {
+            return name.clone();
+        }
+        let var = &self.generator.program.vars.get(self.plan.vars[slot]).name;
+        let base = if var.starts_with("_arith") { "t".to_string() } else { snake_case(var) };
+        let name = self.fresh(&base);
+        self.names.insert(slot, name.clone());
+        name
+    }
+
+    /// The name to bind `slot` to, `_` if nothing reads it afterwards.
+    fn binding(&mut self, slot: usize, ty: Ty, live: &HashSet<usize>) -> String {
+        self.types.insert(slot, ty);
+        if live.contains(&slot) { self.name(slot) } else { "_".to_string() }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<(String, Ty), String> {
+        match expr {
+            Expr::Slot(slot) => {
+                if let Some(input) = self.inputs.get(slot).cloned() {
+                    return match &input.state_var {
+                        Some(name) => {
+                            self.uses_state = true;
+                            let field = self.generator.fields.iter().find(|f| f.name == *name);
+                            let field = field.ok_or_else(|| format!("state variable `{}` has no initial value", name))?;
+                            Ok((format!("state.{}", field.field), field.ty))
+                        }
+                        None => {
+                            let var = &self.generator.program.vars.get(input.var).name;
+                            let value = self.generator.constants.get(&input.var).cloned();
+                            let value = value.ok_or_else(|| format!("`{}` has no value in the facts", var))?;
+                            self.generator.value(&value)
+                        }
+                    };
+                }
+                let ty = self.types.get(slot).copied().ok_or("a variable is read before it is bound")?;
+                Ok((self.name(*slot), ty))
+            }
+            Expr::Int(i) => self.generator.value(&Value::Int(*i)),
+            Expr::Real(f) => self.generator.value(&Value::Real(*f)),
+            Expr::Atom(sym) => self.generator.value(&Value::Atom(*sym)),
+            Expr::App(sym, _) => Err(format!(
+                "compound terms such as `{}(...)` are not supported in generated code",
+                self.generator.program.symbols.get(*sym)
+            )),
+        }
+    }
+
+    /// `expr` as an operand of `family` arithmetic.
+    fn number(&mut self, expr: &Expr, family: Family) -> Result<String, String> {
+        let (code, ty) = self.expr(expr)?;
+        match (family, ty) {
+            (Family::Int, Ty::Int) | (Family::Real, Ty::Real) => Ok(code),
+            (Family::Real, Ty::Int) => match code.parse::<i32>() {
+                Ok(i) => Ok(format!("{:?}", i as f32)),
+                Err(_) => Ok(format!("({} as f32)", code)),
+            },
+            (Family::Int, Ty::Real) => Err("a Real is used in integer arithmetic".to_string()),
+            (_, Ty::Atom) => Err(format!("`{}` is used in arithmetic", code)),
+        }
+    }
+
+    fn emit(&mut self, step: &Step, fail: &Fail, live: &HashSet<usize>, code: &mut Code) -> Result<(), String> {
+        match step {
+            Step::Const(true) => {}
+            Step::Const(false) => {
+                let fail = self.fail(fail);
+                code.line(&format!("{};", fail));
+            }
+            Step::Match(value, pattern) => match pattern {
+                Pattern::Check(expected) => {
+                    let ((lhs, lhs_ty), (rhs, rhs_ty)) = (self.expr(value)?, self.expr(expected)?);
+                    if lhs_ty != rhs_ty {
+                        return Err(format!("`{}` is {} but `{}` is {}", lhs, lhs_ty.describe(), rhs, rhs_ty.describe()));
+                    }
+                    let fail = self.fail(fail);
+                    code.guard(&format!("{} != {}", lhs, rhs), &fail);
+                }
+                &Pattern::Bind(slot) => {
+                    if let Expr::Slot(param) = value
+                        && *param < self.plan.params
+                    {
+                        // Name the head variable after the argument.
+                        let name = self.name(*param);
+                        self.names.insert(slot, name);
+                        self.types.insert(slot, self.types[param]);
+                        return Ok(());
+                    }
+                    let (value, ty) = self.expr(value)?;
+                    let name = self.binding(slot, ty, live);
+                    code.line(&format!("let {} = {};", name, value));
+                }
+                Pattern::App(sym, _) => {
+                    return Err(format!(
+                        "matching compound terms such as `{}(...)` is not supported in generated code",
+                        self.generator.program.symbols.get(*sym)
+                    ));
+                }
+            },
+            &Step::Compute { family, op, ref lhs, ref rhs, out } => {
+                let (lhs, rhs) = (self.number(lhs, family)?, self.number(rhs, family)?);
+                let name = self.binding(out, Ty::of(family), live);
+                if op == ArithOp::Div && family == Family::Int {
+                    let fail = self.fail(fail);
+                    code.line(&format!("let Some({}) = {}.checked_div_euclid({}) else {{ {} }};", name, receiver(&lhs), rhs, fail));
+                } else {
+                    code.line(&format!("let {} = {} {} {};", name, lhs, operator(op), rhs));
+                }
+            }
+            &Step::Check { family, op, ref lhs, ref rhs, ref result } => {
+                let (lhs, rhs, result) = (self.number(lhs, family)?, self.number(rhs, family)?, self.number(result, family)?);
+                let fail = self.fail(fail);
+                if op == ArithOp::Div && family == Family::Int {
+                    code.guard(&format!("{}.checked_div_euclid({}) != Some({})", receiver(&lhs), rhs, result), &fail);
+                } else {
+                    code.guard(&format!("{} {} {} != {}", lhs, operator(op), rhs, result), &fail);
+                }
+            }
+            &Step::Convert { family, ref value, out } => {
+                let value = self.number(value, family)?;
+                let name = self.binding(out, Ty::of(family), live);
+                code.line(&format!("let {} = {};", name, value));
+            }
+            &Step::Compare { family, cmp, ref lhs, ref rhs } => {
+                let (lhs, rhs) = (self.number(lhs, family)?, self.number(rhs, family)?);
+                let fail = self.fail(fail);
+                code.guard(&format!("{} {} {}", lhs, negated(cmp), rhs), &fail);
+            }
+            Step::And(first, second) => {
+                let mut first_live = live.clone();
+                second.reads(&mut first_live);
+                self.emit(first, fail, &first_live, code)?;
+                self.emit(second, fail, live, code)?;
+            }
+            Step::Not(inner) => {
+                let label = self.label("not");
+                let mut nested = code.nested();
+                self.emit(inner, &Fail::breaking(&label, "false"), &HashSet::new(), &mut nested)?;
+                let fail = self.fail(fail);
+                if self.used_labels.contains(&label) {
+                    let held = self.fresh("held");
+                    code.line(&format!("let {} = '{}: {{", held, label));
+                    code.append(nested);
+                    code.line("    true");
+                    code.line("};");
+                    code.guard(&held, &fail);
+                } else {
+                    code.open("{");
+                    code.append(nested);
+                    code.close("}");
+                    code.line(&format!("{};", fail));
+                }
+            }
+            Step::Cond(guard, then, otherwise) => self.cond(step, guard, then, otherwise, fail, live, code)?,
+            Step::Call { rel, args } => {
+                let mut values = Vec::new();
+                let mut types = Vec::new();
+                for arg in args {
+                    let (value, ty) = self.expr(arg)?;
+                    values.push(value);
+                    types.push(ty);
+                }
+                let (name, host_only) = self.generator.call(*rel, types)?;
+                self.uses_host = true;
+                let call = if host_only {
+                    format!("host.{}({})", name, values.join(", "))
+                } else {
+                    self.uses_state = true;
+                    let args: Vec<String> = ["state".to_string(), "host".to_string()].into_iter().chain(values).collect();
+                    format!("{}({})", name, args.join(", "))
+                };
+                let fail = self.fail(fail);
+                code.guard(&format!("!{}", call), &fail);
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cond(
+        &mut self,
+        step: &Step,
+        guard: &Step,
+        then: &Step,
+        otherwise: &Step,
+        fail: &Fail,
+        live: &HashSet<usize>,
+        code: &mut Code,
+    ) -> Result<(), String> {
+        let mut bound = Vec::new();
+        step.binds(&mut bound);
+        let exports: Vec<usize> = sorted(bound.into_iter().filter(|s| live.contains(s)));
+
+        let mut then_live: HashSet<usize> = exports.iter().copied().collect();
+        then.reads(&mut then_live);
+        let mut guard_bound = Vec::new();
+        guard.binds(&mut guard_bound);
+        let guard_exports: Vec<usize> = sorted(guard_bound.into_iter().filter(|s| then_live.contains(s)));
+
+        let label = self.label("when");
+        let guard_fail = Fail::breaking(&label, if guard_exports.is_empty() { "false" } else { "None" });
+        let mut guard_code = code.nested();
+        self.emit(guard, &guard_fail, &guard_exports.iter().copied().collect(), &mut guard_code)?;
+
+        if !self.used_labels.contains(&label) {
+            // The guard always holds, so the other branch never runs.
+            code.append(guard_code.dedented());
+            return self.emit(then, fail, live, code);
+        }
+
+        let guard_names: Vec<String> = guard_exports.iter().map(|&s| self.name(s)).collect();
+        let held = self.fresh("held");
+        code.line(&format!("let {} = '{}: {{", held, label));
+        code.append(guard_code);
+        code.line(&format!("    {}", if guard_names.is_empty() { "true".to_string() } else { format!("Some({})", tuple(&guard_names)) }));
+        code.line("};");
+
+        let then_types = self.types.clone();
+        let mut then_code = code.nested();
+        self.emit(then, fail, live, &mut then_code)?;
+        let mut otherwise_code = code.nested();
+        let branch_types = std::mem::replace(&mut self.types, then_types);
+        self.emit(otherwise, fail, live, &mut otherwise_code)?;
+        for &slot in &exports {
+            if let (Some(&a), Some(&b)) = (branch_types.get(&slot), self.types.get(&slot))
+                && a != b
+            {
+                let name = self.name(slot);
+                return Err(format!("`{}` is {} in one branch and {} in the other", name, a.describe(), b.describe()));
+            }
+        }
+
+        let export_names: Vec<String> = exports.iter().map(|&s| self.name(s)).collect();
+        let condition = if guard_names.is_empty() { held } else { format!("let Some({}) = {}", tuple(&guard_names), held) };
+        let result = tuple(&export_names);
+        if export_names.is_empty() {
+            code.line(&format!("if {} {{", condition));
+        } else {
+            code.line(&format!("let {} = if {} {{", result, condition));
+        }
+        for (i, mut branch) in [then_code, otherwise_code].into_iter().enumerate() {
+            if i > 0 {
+                code.line("} else {");
+            }
+            if !export_names.is_empty() {
+                branch.finish_with(&result);
+            }
+            code.append(branch);
+        }
+        code.line(if export_names.is_empty() { "}" } else { "};" });
+        Ok(())
+    }
+}
+
+fn sorted(slots: impl Iterator<Item = usize>) -> Vec<usize> {
+    let mut slots: Vec<usize> = slots.collect();
+    slots.sort();
+    slots.dedup();
+    slots
+}
+
+/// `a` for one name, `(a, b)` for several.
+fn tuple(names: &[String]) -> String {
+    if names.len() == 1 { names[0].clone() } else { format!("({})", names.join(", ")) }
+}
+
+fn operator(op: ArithOp) -> &'static str {
+    match op {
+        ArithOp::Add => "+",
+        ArithOp::Sub => "-",
+        ArithOp::Mul => "*",
+        ArithOp::Div => "/",
+    }
+}
+
+/// The operator testing that `cmp` does not hold.
+fn negated(cmp: Cmp) -> &'static str {
+    match cmp {
+        Cmp::Eq => "!=",
+        Cmp::Neq => "==",
+        Cmp::Lt => ">=",
+        Cmp::Le => ">",
+        Cmp::Gt => "<=",
+        Cmp::Ge => "<",
+    }
+}
+
+/// `state` and `host`, underscored if the function doesn't use them.
+fn params(uses_state: bool, uses_host: bool) -> String {
+    format!(
+        "{}: &State, {}: &impl Host",
+        if uses_state { "state" } else { "_state" },
+        if uses_host { "host" } else { "_host" }
+    )
+}
+
+impl Generator<'_> {
+    fn state_fields(&mut self) {
+        let Some(known) = forward::fact_values(self.program) else {
+            let message = "generated code only supports facts that are equations or tuples of user relations".to_string();
+            self.error(message, &SourceSpan::default());
+            return;
+        };
+        for name in &self.program.state_vars {
+            let initial = self.program.state_var_term_ids.get(name).and_then(|&term| match self.program.terms.get(term) {
+                Term::Var(v) => known.get(v),
+                _ => None,
+            });
+            let Some(initial) = initial else {
+                self.error(format!("state variable `{}` has no initial value", name), &SourceSpan::default());
+                continue;
+            };
+            match self.value(&initial.clone()) {
+                Ok((initial, ty)) => {
+                    let field = snake_case(name);
+                    self.fields.push(StateField { name: name.clone(), field, ty, initial });
+                }
+                Err(err) => self.error(format!("state variable `{}`: {}", name, err), &SourceSpan::default()),
+            }
+        }
+        let state_vars: HashSet<VarId> = self
+            .program
+            .state_var_term_ids
+            .values()
+            .filter_map(|&t| match self.program.terms.get(t) {
+                Term::Var(v) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        self.constants = known.into_iter().filter(|(v, _)| !state_vars.contains(v)).collect();
+    }
+
+    fn stage_fn(&mut self, stage_index: usize, out: &mut String) {
+        let program = self.program;
+        let stage = &program.stages[stage_index];
+        let context = format!("stage `{}` can't run without the solver", stage.name);
+        let plan = match forward::plan_stage(program, stage) {
+            Ok(plan) => plan,
+            Err(err) => return self.error(format!("{}: {}", context, err.message), &err.span),
+        };
+
+        let mut emitter = FnEmitter::new(self, &plan);
+        let mut body = Code::new(1);
+        let live: HashSet<usize> = plan.outputs.iter().map(|(_, slot)| *slot).collect();
+        let result = emitter.emit(&plan.body, &Fail::returning("return None"), &live, &mut body).and_then(|()| {
+            let mut fields = Vec::new();
+            for (name, slot) in &plan.outputs {
+                let ty = emitter.types.get(slot).copied();
+                let index = emitter.generator.fields.iter().position(|f| f.name == *name);
+                let index = index.ok_or_else(|| format!("state variable `{}` has no initial value", name))?;
+                let field = &emitter.generator.fields[index];
+                if ty != Some(field.ty) {
+                    let found = ty.map_or("unknown", Ty::describe);
+                    return Err(format!("`next({})` is {} but `{}` starts as {}", name, found, name, field.ty.describe()));
+                }
+                let field = field.field.clone();
+                let value = emitter.name(*slot);
+                fields.push((index, if field == value { field } else { format!("{}: {}", field, value) }));
+            }
+            // In the order of the struct's fields.
+            fields.sort();
+            let mut fields: Vec<String> = fields.into_iter().map(|(_, field)| field).collect();
+            if fields.len() < emitter.generator.fields.len() {
+                emitter.uses_state = true;
+                fields.push("..*state".to_string());
+            }
+            body.line(&format!("Some(State {{ {} }})", fields.join(", ")));
+            Ok(())
+        });
+        let (uses_state, uses_host) = (emitter.uses_state, emitter.uses_host);
+        if let Err(err) = result {
+            return self.error(format!("{}: {}", context, err), &stage.span);
+        }
+
+        let name = self.item_name(format!("run_{}", snake_case(&stage.name)));
+        out.push_str(&format!(
+            "/// Runs stage `{}`, or returns `None` where the solver would report\n/// that its state constraints fail.\n",
+            stage.name
+        ));
+        out.push_str(&format!("pub fn {}({}) -> Option<State> {{\n", name, params(uses_state, uses_host)));
+        out.push_str(&body.text);
+        out.push_str("}\n\n");
+    }
+
+    fn draw_fn(&mut self, out: &mut String) {
+        let program = self.program;
+        let mut body = Code::new(1);
+        let (mut uses_state, mut uses_host) = (false, false);
+        let mut labels = 0;
+        for stage in &program.stages {
+            let context = format!("the draw directives of stage `{}` can't run without the solver", stage.name);
+            for directive in &stage.draw_directives {
+                let (plan, draws) = match forward::plan_draw(program, directive, &stage.span) {
+                    Ok(planned) => planned,
+                    Err(err) => {
+                        self.error(format!("{}: {}", context, err.message), &err.span);
+                        continue;
+                    }
+                };
+                let mut emitter = FnEmitter::new(self, &plan);
+                emitter.labels = labels;
+                let label = emitter.label("draw");
+                let mut live = HashSet::new();
+                draws.iter().for_each(|d| d.slots(&mut live));
+                let mut nested = body.nested();
+                let result = emitter.emit(&plan.body, &Fail::breaking(&label, ""), &live, &mut nested).and_then(|()| {
+                    for draw in &draws {
+                        let Expr::App(sym, args) = draw else {
+                            return Err("draw commands must be terms such as `rect(X, Y, W, H)`".to_string());
+                        };
+                        let args = args
+                            .iter()
+                            .map(|a| emitter.number(a, Family::Real))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|_| "draw command arguments must be numbers".to_string())?;
+                        let name = emitter.generator.program.symbols.get(*sym);
+                        nested.line(&format!("draws.push(DrawCommand {{ name: {:?}, args: vec![{}] }});", name, args.join(", ")));
+                    }
+                    Ok(())
+                });
+                uses_state |= emitter.uses_state;
+                uses_host |= emitter.uses_host;
+                labels = emitter.labels;
+                let labeled = emitter.used_labels.contains(&label);
+                if let Err(err) = result {
+                    self.error(format!("{}: {}", context, err), &stage.span);
+                    continue;
+                }
+                if labeled {
+                    body.line(&format!("'{}: {{", label));
+                    body.append(nested);
+                    body.line("}");
+                } else {
+                    body.line("{");
+                    body.append(nested);
+                    body.line("}");
+                }
+            }
+        }
+
+        out.push_str("/// The draw commands of every stage, in order.\n");
+        out.push_str(&format!("pub fn draw({}) -> Vec<DrawCommand> {{\n", params(uses_state, uses_host)));
+        if body.text.is_empty() {
+            out.push_str("    Vec::new()\n");
+        } else {
+            out.push_str("    let mut draws = Vec::new();\n");
+            out.push_str(&body.text);
+            out.push_str("    draws\n");
+        }
+        out.push_str("}\n\n");
+    }
+
+    fn rel_fn(&mut self, index: usize, out: &mut String) {
+        let program = self.program;
+        let (rel, name, types) = {
+            let f = &self.rels[index];
+            (f.rel, f.name.clone(), f.params.clone())
+        };
+        let rel_name = &program.rels.get(rel).name;
+        let clauses: Vec<_> = program.global_rules.iter().filter(|c| c.head_rel == rel).collect();
+
+        // Name parameters after the head variables when every rule agrees.
+        let param_names: Vec<String> = (0..types.len())
+            .map(|i| {
+                let names: HashSet<&str> = clauses
+                    .iter()
+                    .map(|c| match program.terms.get(c.head_args[i]) {
+                        Term::Var(v) => program.vars.get(*v).name.as_str(),
+                        _ => "",
+                    })
+                    .collect();
+                match names.into_iter().collect::<Vec<_>>().as_slice() {
+                    [name] if !name.is_empty() && !name.starts_with('_') => snake_case(name),
+                    _ => format!("arg{}", i),
+                }
+            })
+            .collect();
+        let mut body = Code::new(1);
+        let (mut uses_state, mut uses_host) = (false, false);
+        let mut always = false;
+
+        if program.declared_rels.contains(&rel) {
+            uses_host = true;
+            body.guard(&format!("host.{}({})", snake_case(rel_name), param_names.join(", ")), "return true");
+        }
+        // Generated code runs the rules instead of reading derived facts.
+        for &fact in program.facts.iter().filter(|&&f| !program.datalog.is_derived(f)) {
+            let Prop::App { rel: r, args } = program.props.get(fact) else { continue };
+            if *r != rel || always {
+                continue;
+            }
+            let values: Option<Vec<Value>> = args.iter().map(|&a| Value::from_term(program, a)).collect();
+            let Some(values) = values else {
+                self.error(format!("facts of `{}` with variables are not supported in generated code", rel_name), &program.prop_spans.get(&fact).cloned().unwrap_or_default());
+                continue;
+            };
+            if values.is_empty() {
+                always = true;
+                continue;
+            }
+            let mut tests = Vec::new();
+            for (value, param) in values.iter().zip(&param_names) {
+                match self.value(value) {
+                    Ok((value, _)) => tests.push(format!("{} == {}", param, value)),
+                    Err(err) => self.error(err, &program.prop_spans.get(&fact).cloned().unwrap_or_default()),
+                }
+            }
+            body.guard(&tests.join(" && "), "return true");
+        }
+
+        for clause in clauses {
+            if always {
+                break;
+            }
+            let context = format!("rule `{}` can't run without the solver", clause.name);
+            let plan = match forward::plan_clause(program, clause) {
+                Ok(plan) => plan,
+                Err(err) => {
+                    self.error(format!("{}: {}", context, err.message), &err.span);
+                    continue;
+                }
+            };
+            let mut emitter = FnEmitter::new(self, &plan);
+            for (slot, (param, ty)) in param_names.iter().zip(&types).enumerate() {
+                emitter.taken.insert(param.clone());
+                emitter.names.insert(slot, param.clone());
+                emitter.types.insert(slot, *ty);
+            }
+            let label = unkeyword(snake_case(&clause.name));
+            emitter.taken.insert(label.clone());
+            let mut nested = body.nested();
+            let result = emitter.emit(&plan.body, &Fail::breaking(&label, ""), &HashSet::new(), &mut nested);
+            uses_state |= emitter.uses_state;
+            uses_host |= emitter.uses_host;
+            let labeled = emitter.used_labels.contains(&label);
+            if let Err(err) = result {
+                self.error(format!("{}: {}", context, err), &clause.span);
+                continue;
+            }
+            if labeled {
+                body.line(&format!("'{}: {{", label));
+                body.append(nested);
+                body.line("    return true;");
+                body.line("}");
+            } else {
+                // The rule always holds, so the ones after it never matter.
+                body.append(nested.dedented());
+                always = true;
+            }
+        }
+        body.line(if always { "true" } else { "false" });
+
+        let params: Vec<String> = std::iter::once(params(uses_state, uses_host))
+            .chain(param_names.iter().zip(&types).map(|(p, t)| format!("{}: {}", p, t.rust())))
+            .collect();
+        out.push_str(&format!("fn {}({}) -> bool {{\n", name, params.join(", ")));
+        out.push_str(&body.text);
+        out.push_str("}\n\n");
+    }
+}
+
+/// Generates a standalone Rust module running `program` without the
+/// solver, or the errors explaining which parts need it.
+pub fn generate_rust(program: &Program) -> Result<String, CompileError> {
+    let mut generator = Generator {
+        program,
+        fields: Vec::new(),
+        constants: HashMap::new(),
+        atoms: BTreeMap::new(),
+        rels: Vec::new(),
+        items: ["State", "DrawCommand", "Host", "Atom", "draw"].iter().map(|s| s.to_string()).collect(),
+        diagnostics: Vec::new(),
+    };
+    generator.state_fields();
+
+    let mut functions = String::new();
+    for (i, stage) in program.stages.iter().enumerate() {
+        if !stage.rules.is_empty() {
+            let message = format!("stage `{}` defines its own rules, which generated code does not support", stage.name);
+            generator.error(message, &stage.span);
+            continue;
+        }
+        if !stage.state_constraints.is_empty() {
+            generator.stage_fn(i, &mut functions);
+        }
+    }
+    generator.draw_fn(&mut functions);
+    let mut i = 0;
+    while i < generator.rels.len() {
+        if !generator.rels[i].host_only {
+            generator.rel_fn(i, &mut functions);
+        }
+        i += 1;
+    }
+
+    if !generator.diagnostics.is_empty() {
+        return Err(CompileError { diagnostics: generator.diagnostics });
+    }
+
+    let mut out = String::new();
+    out.push_str("// Generated by langame from a compiled program. Do not edit.\n\n");
+    if !generator.atoms.is_empty() {
+        out.push_str("#[derive(Debug, Clone, Copy, PartialEq, Eq)]\npub enum Atom {\n");
+        for variant in generator.atoms.keys() {
+            out.push_str(&format!("    {},\n", variant));
+        }
+        out.push_str("}\n\n");
+    }
+
+    out.push_str("#[derive(Debug, Clone, Copy, PartialEq)]\npub struct State {\n");
+    for field in &generator.fields {
+        out.push_str(&format!("    pub {}: {},\n", field.field, field.ty.rust()));
+    }
+    out.push_str("}\n\nimpl Default for State {\n    fn default() -> State {\n        State {\n");
+    for field in &generator.fields {
+        out.push_str(&format!("            {}: {},\n", field.field, field.initial));
+    }
+    out.push_str("        }\n    }\n}\n\n");
+
+    out.push_str("#[derive(Debug, Clone, PartialEq)]\npub struct DrawCommand {\n    pub name: &'static str,\n    pub args: Vec<f32>,\n}\n\n");
+
+    out.push_str("/// The relations the game provides, declared with `Relation`.\npub trait Host {\n");
+    let mut host: Vec<&RelFn> = generator.rels.iter().filter(|r| program.declared_rels.contains(&r.rel)).collect();
+    host.sort_by(|a, b| a.name.cmp(&b.name));
+    for rel in host {
+        let name = snake_case(&program.rels.get(rel.rel).name);
+        let params: Vec<String> = rel.params.iter().enumerate().map(|(i, t)| format!("arg{}: {}", i, t.rust())).collect();
+        let params = std::iter::once("&self".to_string()).chain(params).collect::<Vec<_>>().join(", ");
+        out.push_str(&format!("    fn {}({}) -> bool;\n", name, params));
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(functions.trim_end());
+    out.push('\n');
+    Ok(out)
+}
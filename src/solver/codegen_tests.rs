@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use crate::frontend::{Frontend, FrontendOptions};
+    use crate::solver::codegen::generate_rust;
+    use crate::solver::test_support::load_with;
+
+    #[allow(dead_code)]
+    mod runner {
+        include!("../../tests/codegen/runner.rs");
+    }
+
+    const RUNNER_GOLDEN: &str = "tests/codegen/runner.rs";
+
+    /// Keeps the rules the optimizer would inline, so that the golden file
+    /// covers relation functions.
+    const UNOPTIMIZED: FrontendOptions = FrontendOptions { optimize: false, ..FrontendOptions::DEFAULT };
+
+    fn load(source: &str) -> Frontend {
+        load_with(source, UNOPTIMIZED)
+    }
+
+    fn codegen_errors(source: &str) -> Vec<String> {
+        let frontend = load(source);
+        match generate_rust(&frontend.program) {
+            Ok(code) => panic!("expected codegen to fail, got:\n{}", code),
+            Err(err) => err.diagnostics.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    /// Set `UPDATE_GOLDEN=1` to rewrite the golden file after a deliberate
+    /// change to the generated code.
+    #[test]
+    fn test_runner_matches_golden_file() {
+        let source = std::fs::read_to_string("sample/runner.l").unwrap();
+        let code = generate_rust(&load(&source).program).unwrap();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(RUNNER_GOLDEN, &code).unwrap();
+        }
+        assert_eq!(code, std::fs::read_to_string(RUNNER_GOLDEN).unwrap());
+    }
+
+    struct Keys {
+        space: bool,
+    }
+
+    impl runner::Host for Keys {
+        fn key_pressed(&self, key: runner::Atom) -> bool {
+            self.space && key == runner::Atom::Space
+        }
+    }
+
+    #[test]
+    fn test_generated_runner_matches_frontend() {
+        // Start the obstacle closer so that it arrives after one jump.
+        let source = std::fs::read_to_string("sample/runner.l").unwrap();
+        let mut frontend = load(&source.replace("ObstacleX = 100.0", "ObstacleX = 40.0"));
+        let mut state = runner::State { obstacle_x: 40.0, ..Default::default() };
+
+        for frame in 0..30 {
+            let keys = Keys { space: frame == 3 };
+            frontend.clear_facts_by_relation("key_pressed");
+            if keys.space {
+                frontend.add_fact("key_pressed(space)").unwrap();
+            }
+            frontend.run_stage_by_name("Control").unwrap();
+            frontend.run_stage_by_name("Physics").unwrap();
+            state = runner::run_control(&state, &keys).unwrap();
+            state = runner::run_physics(&state, &keys).unwrap();
+
+            let generated = [
+                ("RunnerY", state.runner_y.to_string()),
+                ("RunnerVY", state.runner_vy.to_string()),
+                ("ObstacleX", state.obstacle_x.to_string()),
+                ("ObstacleVX", state.obstacle_vx.to_string()),
+                ("Dead", format!("{:?}", state.dead).to_lowercase()),
+            ];
+            for (name, value) in generated {
+                assert_eq!(frontend.get_state_var(name).unwrap(), value, "{} in frame {}", name, frame);
+            }
+
+            let draws: Vec<(String, Vec<f32>)> = runner::draw(&state, &keys)
+                .into_iter()
+                .map(|d| (d.name.to_string(), d.args))
+                .collect();
+            let expected: Vec<(String, Vec<f32>)> = frontend
+                .collect_draws_by_name("Draw")
+                .unwrap()
+                .into_iter()
+                .map(|d| (d.name, d.args))
+                .collect();
+            assert_eq!(draws, expected, "draws in frame {}", frame);
+        }
+        assert_eq!(state.dead, runner::Atom::Yes, "the runner should have hit an obstacle");
+    }
+
+    #[test]
+    fn test_constructs_needing_search_are_rejected() {
+        let source = r#"Begin Facts:
+    StateVar X
+    X = 1
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Choice:
+Begin State Constraints:
+    or(next(X) = 1, next(X) = 2)
+End State Constraints
+End Stage Choice
+
+Begin Stage Open:
+Begin State Constraints:
+    int_lt(next(X), 5)
+End State Constraints
+End Stage Open
+"#;
+        assert_eq!(
+            codegen_errors(source),
+            vec![
+                "11:5: error: stage `Choice` can't run without the solver: `or` needs the solver to try both branches",
+                "17:5: error: stage `Open` can't run without the solver: `int_lt` needs both arguments known here",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rules_called_with_unknown_arguments_are_rejected() {
+        let source = r#"Begin Facts:
+    StateVar X
+    X = 1
+    step(1, 2)
+    step(2, 3)
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Advance:
+Begin State Constraints:
+    step(X, next(X))
+End State Constraints
+End Stage Advance
+"#;
+        assert_eq!(
+            codegen_errors(source),
+            vec!["13:5: error: stage `Advance` can't run without the solver: `step` is called with arguments that are not known here, which needs the solver"]
+        );
+    }
+}
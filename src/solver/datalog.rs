@@ -0,0 +1,538 @@
+//! Bottom-up evaluation of the global rules that are plain Datalog.
+//!
+//! A relation is Datalog-safe when each of its rules is a conjunction of
+//! calls and equations over variables and constants, every variable in the
+//! rule appears in a call or is equated to one that does, and everything it
+//! calls is Datalog-safe too or is given by ground facts alone. Built-in
+//! arithmetic, negation, disjunction, compound terms and state variables
+//! keep a relation out, as do rules local to a stage and calls of relations
+//! with a cost, which has to be charged as the search makes them.
+//!
+//! [`materialize`] derives every fact these relations imply by semi-naive
+//! iteration: each round joins every rule once for each of its calls, that
+//! call ranging over the tuples the round before found new, the calls ahead
+//! of it over the older tuples and those after it over all but the ones this
+//! round finds, until a round finds none. The derived facts go into
+//! `program.facts` and the solver looks these relations up there instead of
+//! running their rules. The tuples are kept, so [`update`], which the next
+//! [`Solver`](crate::solver::Solver) runs, carries on from facts added since
+//! as from another round's, and only recomputes the relations that read a
+//! relation whose facts were cleared.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::solver::index::ArgKey;
+use crate::solver::ir::{Clause, Program, Prop, PropId, RelId, RelKind, Term, TermId, VarId};
+
+/// What [`materialize`] derived, kept in `program.datalog`.
+#[derive(Debug, Clone, Default)]
+pub struct Materialized {
+    /// The relations whose facts are all in `program.facts`.
+    pub relations: HashSet<RelId>,
+    /// The relations the rules read, whose facts changing makes the result
+    /// stale.
+    inputs: HashSet<RelId>,
+    rules: HashMap<RelId, Vec<Rule>>,
+    /// The tuples of every relation in `inputs`, given and derived.
+    tuples: HashMap<RelId, Relation>,
+    derived: HashSet<PropId>,
+    /// The derived fact of each derived tuple.
+    derived_tuples: HashMap<(RelId, Vec<ArgKey>), PropId>,
+    /// Facts of `inputs` added since the last update.
+    added: Vec<PropId>,
+    /// Relations in `inputs` whose facts were cleared since the last update.
+    cleared: HashSet<RelId>,
+}
+
+impl Materialized {
+    pub fn is_derived(&self, fact: PropId) -> bool {
+        self.derived.contains(&fact)
+    }
+
+    pub fn is_stale(&self) -> bool {
+        !self.added.is_empty() || !self.cleared.is_empty()
+    }
+
+    /// Notes that `fact`, one of `rel`, was added.
+    pub(crate) fn fact_added(&mut self, rel: RelId, fact: PropId) {
+        if self.inputs.contains(&rel) {
+            self.added.push(fact);
+        }
+    }
+
+    /// Notes that the facts of `rel` were cleared.
+    pub(crate) fn facts_cleared(&mut self, rel: RelId) {
+        if self.inputs.contains(&rel) {
+            self.cleared.insert(rel);
+        }
+    }
+
+    /// Adds the facts for the tuples of `rels` past `given`, found by the
+    /// last evaluation.
+    fn add_derived(&mut self, program: &mut Program, given: HashMap<RelId, usize>) {
+        for (rel, given) in given {
+            let Some(relation) = self.tuples.get(&rel) else { continue };
+            for tuple in &relation.tuples[given..] {
+                let args = tuple.iter().map(|key| program.terms.alloc(term(*key))).collect();
+                let fact = program.props.alloc(Prop::App { rel, args });
+                program.add_fact(fact);
+                self.derived.insert(fact);
+                self.derived_tuples.insert((rel, tuple.clone()), fact);
+            }
+        }
+    }
+}
+
+/// Replaces the facts derived by the last call, if any, with those the
+/// Datalog-safe relations imply now.
+pub fn materialize(program: &mut Program) {
+    clear(program);
+    let rules = safe_rules(program);
+    let mut inputs: HashSet<RelId> = rules.keys().copied().collect();
+    for rule in rules.values().flatten() {
+        inputs.extend(rule.body.iter().map(|atom| atom.rel));
+    }
+    let mut tuples: HashMap<RelId, Relation> = HashMap::new();
+    for &fact in &program.facts {
+        if let Prop::App { rel, args } = program.props.get(fact)
+            && inputs.contains(rel)
+        {
+            tuples.entry(*rel).or_default().insert(ground_tuple(program, args).expect("safe relations have ground facts"));
+        }
+    }
+    let given = lengths(&tuples, rules.keys());
+    let heads: HashSet<RelId> = rules.keys().copied().collect();
+
+    evaluate(&rules, &mut tuples, &heads, HashMap::new());
+
+    let mut materialized = Materialized {
+        relations: heads,
+        inputs,
+        rules,
+        tuples,
+        ..Materialized::default()
+    };
+    materialized.add_derived(program, given);
+    program.datalog = materialized;
+}
+
+/// Brings the derived facts up to date with the facts added and cleared
+/// since [`materialize`] or the last update.
+pub fn update(program: &mut Program) {
+    if !program.datalog.is_stale() {
+        return;
+    }
+    let mut materialized = std::mem::take(&mut program.datalog);
+    let added = std::mem::take(&mut materialized.added);
+    let cleared = std::mem::take(&mut materialized.cleared);
+    let added: Option<Vec<(RelId, Vec<ArgKey>)>> = added
+        .iter()
+        .filter_map(|&fact| match program.props.get(fact) {
+            Prop::App { rel, args } => Some(ground_tuple(program, args).map(|tuple| (*rel, tuple))),
+            _ => None,
+        })
+        .collect();
+    let Some(added) = added else {
+        // A fact with variables in it can change which relations are safe.
+        program.datalog = materialized;
+        materialize(program);
+        return;
+    };
+
+    // The relations reading a cleared one start over from their own facts.
+    let mut reset = cleared;
+    loop {
+        let readers: Vec<RelId> = materialized
+            .rules
+            .iter()
+            .filter(|(rel, rules)| {
+                !reset.contains(*rel) && rules.iter().flat_map(|r| &r.body).any(|atom| reset.contains(&atom.rel))
+            })
+            .map(|(&rel, _)| rel)
+            .collect();
+        if readers.is_empty() {
+            break;
+        }
+        reset.extend(readers);
+    }
+    let stale: HashSet<PropId> = materialized
+        .derived_tuples
+        .extract_if(|(rel, _), _| reset.contains(rel))
+        .map(|(_, fact)| fact)
+        .collect();
+    materialized.derived.retain(|fact| !stale.contains(fact));
+    program.remove_facts(&stale);
+    for &rel in &reset {
+        let mut relation = Relation::default();
+        for &fact in &program.facts {
+            if let Prop::App { rel: r, args } = program.props.get(fact)
+                && *r == rel
+            {
+                relation.insert(ground_tuple(program, args).expect("safe relations have ground facts"));
+            }
+        }
+        materialized.tuples.insert(rel, relation);
+    }
+
+    // The other added facts are the first round's new tuples.
+    let marks = lengths(&materialized.tuples, materialized.tuples.keys());
+    for (rel, tuple) in added.into_iter().filter(|(rel, _)| !reset.contains(rel)) {
+        if !materialized.tuples.entry(rel).or_default().insert(tuple.clone())
+            && let Some(fact) = materialized.derived_tuples.remove(&(rel, tuple))
+        {
+            // Given now, so the derived copy would be a second answer.
+            materialized.derived.remove(&fact);
+            program.remove_facts(&HashSet::from([fact]));
+        }
+    }
+    let delta = materialized
+        .tuples
+        .iter()
+        .map(|(&rel, r)| (rel, marks.get(&rel).copied().unwrap_or(0)..r.tuples.len()))
+        .collect();
+    let given = lengths(&materialized.tuples, materialized.rules.keys());
+
+    evaluate(&materialized.rules, &mut materialized.tuples, &reset, delta);
+
+    materialized.add_derived(program, given);
+    program.datalog = materialized;
+}
+
+/// Removes the derived facts, handing the relations back to the solver.
+pub fn clear(program: &mut Program) {
+    let materialized = std::mem::take(&mut program.datalog);
+    if !materialized.derived.is_empty() {
+        program.remove_facts(&materialized.derived);
+    }
+}
+
+/// The names of the relations [`materialize`] would compute.
+pub fn safe_relations(program: &Program) -> HashSet<String> {
+    safe_rules(program).keys().map(|&rel| program.rels.get(rel).name.clone()).collect()
+}
+
+/// A rule argument: one of the rule's variables, or a constant.
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Var(usize),
+    Const(ArgKey),
+}
+
+#[derive(Debug, Clone)]
+struct Atom {
+    rel: RelId,
+    args: Vec<Slot>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    head: Atom,
+    body: Vec<Atom>,
+    eqs: Vec<(Slot, Slot)>,
+    vars: usize,
+}
+
+/// The key of a constant; variables and compound terms have none.
+fn constant(term: &Term) -> Option<ArgKey> {
+    match term {
+        Term::Var(_) | Term::App { .. } => None,
+        _ => ArgKey::of(term),
+    }
+}
+
+fn ground_tuple(program: &Program, args: &[TermId]) -> Option<Vec<ArgKey>> {
+    args.iter().map(|&a| constant(program.terms.get(a))).collect()
+}
+
+/// The number of tuples each of `rels` has.
+fn lengths<'r>(tuples: &HashMap<RelId, Relation>, rels: impl Iterator<Item = &'r RelId>) -> HashMap<RelId, usize> {
+    rels.map(|&rel| (rel, tuples.get(&rel).map_or(0, |r| r.tuples.len()))).collect()
+}
+
+fn term(key: ArgKey) -> Term {
+    match key {
+        ArgKey::Atom(sym) => Term::Atom(sym),
+        ArgKey::Int(i) => Term::Int(i),
+        ArgKey::Float(bits) => Term::Float(f32::from_bits(bits)),
+        ArgKey::Functor(..) => unreachable!("Datalog facts hold only constants"),
+    }
+}
+
+/// The Datalog-safe relations with their rules.
+fn safe_rules(program: &Program) -> HashMap<RelId, Vec<Rule>> {
+    let stage_rels: HashSet<RelId> = program.stages.iter().flat_map(|s| &s.rules).map(|c| c.head_rel).collect();
+    let mut clauses: HashMap<RelId, Vec<&Clause>> = HashMap::new();
+    for clause in &program.global_rules {
+        clauses.entry(clause.head_rel).or_default().push(clause);
+    }
+    let mut ground = HashMap::new();
+    for &fact in &program.facts {
+        if let Prop::App { rel, args } = program.props.get(fact) {
+            let is_ground = args.iter().all(|&a| constant(program.terms.get(a)).is_some());
+            *ground.entry(*rel).or_insert(true) &= is_ground;
+        }
+    }
+    let ground = |rel: &RelId| ground.get(rel).copied().unwrap_or(true);
+    let state_terms: HashSet<TermId> = program.state_var_term_ids.values().copied().collect();
+
+    let mut safe: HashMap<RelId, Vec<Rule>> = clauses
+        .into_iter()
+        .filter(|(rel, _)| !stage_rels.contains(rel) && ground(rel) && !program.costs.contains_key(rel))
+        .filter_map(|(rel, defs)| {
+            let rules = defs.iter().map(|c| lower_clause(program, &state_terms, c)).collect::<Option<Vec<_>>>()?;
+            Some((rel, rules))
+        })
+        .collect();
+
+    // Only facts, held by relations without rules, can stand in for a
+    // relation that isn't safe.
+    let has_rules: HashSet<RelId> = program.global_rules.iter().map(|c| c.head_rel).chain(stage_rels).collect();
+    loop {
+        let unsafe_rels: Vec<RelId> = safe
+            .iter()
+            .filter(|(_, rules)| {
+                rules.iter().flat_map(|r| &r.body).any(|atom| {
+                    let costed = program.costs.contains_key(&atom.rel);
+                    !safe.contains_key(&atom.rel) && (has_rules.contains(&atom.rel) || !ground(&atom.rel) || costed)
+                })
+            })
+            .map(|(&rel, _)| rel)
+            .collect();
+        if unsafe_rels.is_empty() {
+            return safe;
+        }
+        for rel in unsafe_rels {
+            safe.remove(&rel);
+        }
+    }
+}
+
+fn lower_clause(program: &Program, state_terms: &HashSet<TermId>, clause: &Clause) -> Option<Rule> {
+    let mut vars: HashMap<VarId, usize> = HashMap::new();
+    let mut slot = |term: TermId| match program.terms.get(term) {
+        Term::Var(_) if state_terms.contains(&term) => None,
+        Term::Var(v) => {
+            let next = vars.len();
+            Some(Slot::Var(*vars.entry(*v).or_insert(next)))
+        }
+        other => constant(other).map(Slot::Const),
+    };
+    let head = Atom {
+        rel: clause.head_rel,
+        args: clause.head_args.iter().map(|&a| slot(a)).collect::<Option<_>>()?,
+    };
+
+    let mut body = Vec::new();
+    let mut eqs = Vec::new();
+    let mut pending = vec![clause.body];
+    while let Some(prop) = pending.pop() {
+        match program.props.get(prop) {
+            Prop::True => {}
+            Prop::And(a, b) => pending.extend([*b, *a]),
+            Prop::Eq(a, b) => eqs.push((slot(*a)?, slot(*b)?)),
+            Prop::App { rel, args } if program.rels.get(*rel).kind == RelKind::User => {
+                let args = args.iter().map(|&a| slot(a)).collect::<Option<_>>()?;
+                body.push(Atom { rel: *rel, args });
+            }
+            _ => return None,
+        }
+    }
+
+    // Every variable must get its value from a call.
+    let mut bound = vec![false; vars.len()];
+    for atom in &body {
+        for arg in &atom.args {
+            if let Slot::Var(v) = arg {
+                bound[*v] = true;
+            }
+        }
+    }
+    let is_bound = |slot: &Slot, bound: &[bool]| matches!(slot, Slot::Const(_)) || matches!(slot, Slot::Var(v) if bound[*v]);
+    loop {
+        let mut changed = false;
+        for (a, b) in &eqs {
+            for (x, y) in [(a, b), (b, a)] {
+                if let Slot::Var(v) = y
+                    && !bound[*v]
+                    && is_bound(x, &bound)
+                {
+                    bound[*v] = true;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    bound.iter().all(|&b| b).then_some(Rule { head, body, eqs, vars: vars.len() })
+}
+
+/// The tuples of one relation, in the order they were found.
+#[derive(Debug, Clone, Default)]
+struct Relation {
+    tuples: Vec<Vec<ArgKey>>,
+    seen: HashSet<Vec<ArgKey>>,
+    /// The tuples with each constant at each position.
+    index: HashMap<(usize, ArgKey), Vec<usize>>,
+}
+
+impl Relation {
+    fn insert(&mut self, tuple: Vec<ArgKey>) -> bool {
+        if !self.seen.insert(tuple.clone()) {
+            return false;
+        }
+        for (position, key) in tuple.iter().enumerate() {
+            self.index.entry((position, *key)).or_default().push(self.tuples.len());
+        }
+        self.tuples.push(tuple);
+        true
+    }
+}
+
+/// Runs `rules` over `relations` to a fixpoint, starting from the tuples
+/// in `delta`. The first round joins the rules for `naive` relations over
+/// every tuple instead, as their relations start from their given facts.
+fn evaluate(
+    rules: &HashMap<RelId, Vec<Rule>>,
+    relations: &mut HashMap<RelId, Relation>,
+    naive: &HashSet<RelId>,
+    mut delta: HashMap<RelId, Range<usize>>,
+) {
+    let mut naive = naive.clone();
+    loop {
+        let marks: HashMap<RelId, usize> = relations.iter().map(|(&rel, r)| (rel, r.tuples.len())).collect();
+        let known = |rel: &RelId| 0..marks.get(rel).copied().unwrap_or(0);
+        for rule in rules.values().flatten() {
+            let mut found = Vec::new();
+            if naive.contains(&rule.head.rel) {
+                let ranges = rule.body.iter().map(|atom| known(&atom.rel)).collect();
+                Join { rule, relations, first: 0, ranges }.run(0, &mut vec![None; rule.vars], &mut found);
+            } else {
+                for (i, atom) in rule.body.iter().enumerate() {
+                    let Some(range) = delta.get(&atom.rel).filter(|r| !r.is_empty()) else { continue };
+                    // Matches with an earlier call on a new tuple were found
+                    // when that call took its turn.
+                    let ranges = rule
+                        .body
+                        .iter()
+                        .enumerate()
+                        .map(|(j, other)| match j.cmp(&i) {
+                            Ordering::Less => 0..delta.get(&other.rel).map_or(known(&other.rel).end, |r| r.start),
+                            Ordering::Equal => range.clone(),
+                            Ordering::Greater => known(&other.rel),
+                        })
+                        .collect();
+                    Join { rule, relations, first: i, ranges }.run(0, &mut vec![None; rule.vars], &mut found);
+                }
+            }
+            let head = relations.entry(rule.head.rel).or_default();
+            for tuple in found {
+                head.insert(tuple);
+            }
+        }
+        naive.clear();
+        delta = relations
+            .iter()
+            .map(|(&rel, r)| (rel, marks.get(&rel).copied().unwrap_or(0)..r.tuples.len()))
+            .collect();
+        if delta.values().all(|range| range.is_empty()) {
+            return;
+        }
+    }
+}
+
+/// The tuples one rule derives with each call ranging over the tuples of
+/// its relation in `ranges`, starting the join from the `first` call.
+struct Join<'a> {
+    rule: &'a Rule,
+    relations: &'a HashMap<RelId, Relation>,
+    first: usize,
+    ranges: Vec<Range<usize>>,
+}
+
+impl Join<'_> {
+    fn position(&self, depth: usize) -> usize {
+        match depth {
+            0 => self.first,
+            d if d <= self.first => d - 1,
+            d => d,
+        }
+    }
+
+    fn run(&self, depth: usize, bindings: &mut Vec<Option<ArgKey>>, out: &mut Vec<Vec<ArgKey>>) {
+        if depth == self.rule.body.len() {
+            if let Some(tuple) = self.head(bindings.clone()) {
+                out.push(tuple);
+            }
+            return;
+        }
+        let position = self.position(depth);
+        let atom = &self.rule.body[position];
+        let range = &self.ranges[position];
+        let Some(relation) = self.relations.get(&atom.rel) else { return };
+        let value = |slot: &Slot, bindings: &[Option<ArgKey>]| match slot {
+            Slot::Const(key) => Some(*key),
+            Slot::Var(v) => bindings[*v],
+        };
+
+        let bound = atom.args.iter().enumerate().find_map(|(i, slot)| Some((i, value(slot, bindings)?)));
+        let candidates: Vec<usize> = match bound {
+            Some(key) => relation.index.get(&key).into_iter().flatten().copied().filter(|i| range.contains(i)).collect(),
+            None => range.clone().collect(),
+        };
+
+        for index in candidates {
+            let tuple = &relation.tuples[index];
+            let mut newly_bound = Vec::new();
+            let matches = atom.args.iter().zip(tuple).all(|(slot, key)| match value(slot, bindings) {
+                Some(existing) => existing == *key,
+                None => {
+                    if let Slot::Var(v) = slot {
+                        bindings[*v] = Some(*key);
+                        newly_bound.push(*v);
+                    }
+                    true
+                }
+            });
+            if matches {
+                self.run(depth + 1, bindings, out);
+            }
+            for v in newly_bound {
+                bindings[v] = None;
+            }
+        }
+    }
+
+    /// The head tuple for a match of the whole body, once the equations
+    /// hold.
+    fn head(&self, mut bindings: Vec<Option<ArgKey>>) -> Option<Vec<ArgKey>> {
+        let value = |slot: &Slot, bindings: &[Option<ArgKey>]| match slot {
+            Slot::Const(key) => Some(*key),
+            Slot::Var(v) => bindings[*v],
+        };
+        loop {
+            let mut changed = false;
+            for (a, b) in &self.rule.eqs {
+                match (value(a, &bindings), value(b, &bindings)) {
+                    (Some(x), Some(y)) if x != y => return None,
+                    (Some(x), None) | (None, Some(x)) => {
+                        for slot in [a, b] {
+                            if let Slot::Var(v) = slot {
+                                bindings[*v] = Some(x);
+                            }
+                        }
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        self.rule.head.args.iter().map(|slot| value(slot, &bindings)).collect()
+    }
+}
@@ -0,0 +1,205 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::frontend::{Frontend, FrontendOptions};
+    use crate::solver::datalog::safe_relations;
+    use crate::solver::ir::PropId;
+    use crate::solver::test_support::{load_with, sorted};
+
+    const BOTTOM_UP: FrontendOptions = FrontendOptions { use_datalog: true, ..FrontendOptions::DEFAULT };
+    const TOP_DOWN: FrontendOptions = FrontendOptions { use_datalog: false, ..FrontendOptions::DEFAULT };
+
+    /// Left-recursive reachability with no `Table`, which the solver alone
+    /// can't finish.
+    const GRAPH: &str = r#"Relation edge(Node, Node)
+
+Begin Facts:
+    edge(a, b)
+    edge(b, c)
+    edge(c, a)
+    edge(c, d)
+End Facts
+
+Begin Global:
+    Rule ReachStep:
+    reach(X, Y) & edge(Y, Z)
+    ------------------------
+    reach(X, Z)
+
+    Rule ReachEdge:
+    edge(X, Y)
+    -----------
+    reach(X, Y)
+
+    Rule Meet:
+    reach(X, Z) & reach(Y, Z) & W = Z
+    ---------------------------------
+    meet(X, Y, W)
+
+    Rule Far:
+    reach(X, Y) & int_add(1, _, 3)
+    ------------------------------
+    far(X, Y)
+
+    Rule Nested:
+    reach(X, Y)
+    ----------------
+    nested(pair(X, Y))
+
+    Rule Unbound:
+    edge(X, _)
+    ------------
+    unbound(X, Y)
+End Global
+
+Begin Stage Move:
+    Rule Local:
+    reach(X, Y)
+    -----------
+    local(X, Y)
+End Stage Move
+"#;
+
+    #[test]
+    fn test_only_plain_datalog_is_safe() {
+        let frontend = load_with(GRAPH, BOTTOM_UP);
+        let names = safe_relations(&frontend.program);
+        let expected: HashSet<String> = ["reach", "meet"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_left_recursion_is_computed_bottom_up() {
+        let mut frontend = load_with(GRAPH, BOTTOM_UP);
+        let answers = frontend.query_batch("reach(a, Z)", 100).unwrap();
+        assert_eq!(sorted(answers), vec!["Z = a", "Z = b", "Z = c", "Z = d"]);
+        assert_eq!(frontend.query_batch("reach(X, Y)", 100).unwrap().len(), 12);
+        assert_eq!(frontend.query_batch("meet(a, b, d)", 100).unwrap(), vec!["yes"]);
+        assert_eq!(frontend.query_batch("meet(d, X, Y)", 100).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_results_follow_the_facts() {
+        let mut frontend = load_with(GRAPH, BOTTOM_UP);
+        frontend.add_fact("edge(d, e)").unwrap();
+        assert_eq!(frontend.query_batch("reach(b, e)", 10).unwrap(), vec!["yes"]);
+
+        frontend.clear_facts_by_relation("edge");
+        assert_eq!(frontend.query_batch("reach(X, Y)", 10).unwrap(), Vec::<String>::new());
+        frontend.add_fact("edge(x, y)").unwrap();
+        assert_eq!(frontend.query_batch("reach(X, y)", 10).unwrap(), vec!["X = x"]);
+    }
+
+    #[test]
+    fn test_bottom_up_on_request() {
+        let mut frontend = load_with(GRAPH, TOP_DOWN);
+        let facts = frontend.program.facts.len();
+        let answers = frontend.query_batch_bottom_up("reach(c, Z)", 100).unwrap();
+        assert_eq!(sorted(answers), vec!["Z = a", "Z = b", "Z = c", "Z = d"]);
+        assert_eq!(frontend.program.facts.len(), facts);
+
+        assert_eq!(
+            frontend.query_batch_bottom_up("far(a, Z)", 100),
+            Err("`far` is not a Datalog relation, so it can't be computed bottom-up".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bottom_up_is_opt_in() {
+        let mut frontend = Frontend::new();
+        frontend.load(GRAPH).unwrap();
+        assert!(frontend.program.datalog.relations.is_empty());
+        assert_eq!(frontend.query_batch("meet(a, b, d)", 1).unwrap(), vec!["yes"]);
+    }
+
+    fn derived(frontend: &Frontend) -> HashSet<PropId> {
+        let facts = frontend.program.facts.iter().copied();
+        facts.filter(|&f| frontend.program.datalog.is_derived(f)).collect()
+    }
+
+    #[test]
+    fn test_updates_keep_what_still_holds() {
+        let source = GRAPH
+            .replace("    edge(c, d)\n", "    edge(c, d)\n    link(x, y)\n")
+            .replace("End Global", "    Rule Linked:\n    link(X, Y)\n    ------------\n    linked(X, Y)\nEnd Global");
+        let mut frontend = load_with(&source, BOTTOM_UP);
+        let before = derived(&frontend);
+
+        // Only what the new edge leads to is derived.
+        frontend.add_fact("edge(d, e)").unwrap();
+        assert_eq!(frontend.query_batch("reach(a, e)", 10).unwrap(), vec!["yes"]);
+        let after = derived(&frontend);
+        assert!(after.is_superset(&before));
+        // `reach(_, e)` from four nodes, and `meet(_, _, e)` for each pair.
+        assert_eq!(after.len() - before.len(), 4 + 4 * 4);
+
+        // A given fact replaces the derived one.
+        frontend.add_fact("reach(a, c)").unwrap();
+        assert_eq!(frontend.query_batch("reach(a, c)", 10).unwrap(), vec!["yes"]);
+        assert_eq!(derived(&frontend).len(), after.len() - 1);
+
+        // Clearing `link` leaves `reach` and `meet` as they were.
+        let reached = derived(&frontend);
+        frontend.clear_facts_by_relation("link");
+        assert_eq!(frontend.query_batch("linked(X, Y)", 10).unwrap(), Vec::<String>::new());
+        assert_eq!(reached.difference(&derived(&frontend)).count(), 1);
+        assert_eq!(frontend.query_batch("reach(X, Y)", 100).unwrap().len(), 3 * 5 + 1);
+
+        let mut fresh = load_with(&source, BOTTOM_UP);
+        fresh.add_fact("edge(d, e)").unwrap();
+        fresh.clear_facts_by_relation("link");
+        // Answers list their variables in no particular order.
+        let answers = |frontend: &mut Frontend, query| {
+            let answers = frontend.query_batch(query, 1000).unwrap();
+            sorted(answers.iter().map(|a| sorted(a.split(", ").map(String::from).collect()).join(", ")).collect())
+        };
+        for query in ["reach(X, Y)", "meet(X, Y, Z)"] {
+            assert_eq!(answers(&mut frontend, query), answers(&mut fresh, query));
+        }
+    }
+
+    #[test]
+    fn test_calls_on_the_same_relation_see_every_pairing() {
+        let source = r#"Begin Facts:
+    link(1, 2)
+    link(2, 3)
+    link(3, 4)
+    link(4, 5)
+    link(5, 6)
+End Facts
+
+Begin Global:
+    Rule Join:
+    path(X, Y) & path(Y, Z)
+    -----------------------
+    path(X, Z)
+
+    Rule Link:
+    link(X, Y)
+    ----------
+    path(X, Y)
+End Global
+"#;
+        let mut frontend = load_with(source, BOTTOM_UP);
+        assert_eq!(frontend.query_batch("path(X, Y)", 100).unwrap().len(), 15);
+        frontend.add_fact("link(6, 7)").unwrap();
+        assert_eq!(frontend.query_batch("path(X, Y)", 100).unwrap().len(), 21);
+        assert_eq!(frontend.query_batch("path(1, 7)", 10).unwrap(), vec!["yes"]);
+    }
+
+    #[test]
+    fn test_long_chains() {
+        let mut source = String::from("Begin Facts:\n");
+        for i in 0..200 {
+            source.push_str(&format!("    link({}, {})\n", i, i + 1));
+        }
+        source.push_str("End Facts\n\nBegin Global:\n");
+        source.push_str("    Rule Step:\n    path(X, Y) & link(Y, Z)\n    -----------------------\n    path(X, Z)\n\n");
+        source.push_str("    Rule Link:\n    link(X, Y)\n    ----------\n    path(X, Y)\nEnd Global\n");
+
+        let mut frontend = load_with(&source, BOTTOM_UP);
+        assert_eq!(frontend.query_batch("path(0, Z)", 1000).unwrap().len(), 200);
+        assert_eq!(frontend.query_batch("path(150, 200)", 10).unwrap(), vec!["yes"]);
+    }
+}
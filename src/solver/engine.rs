@@ -1,12 +1,19 @@
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
 
 #[cfg(feature = "profile")]
 use std::cell::RefCell;
 
 use im::{HashMap, Vector};
 
+use crate::solver::datalog;
+use crate::solver::index::ArgKey;
 use crate::solver::ir::{Arena, Clause, Program, Prop, PropId, RelId, RelKind, Term, TermId, Var, VarId};
 
+mod tabling;
+
+use tabling::Tables;
+
 #[cfg(feature = "profile")]
 thread_local! {
     static PROFILE_STATS: RefCell<ProfileStats> = RefCell::new(ProfileStats::new());
@@ -70,6 +77,7 @@ impl Subst {
         self.walk_impl(t, terms, 0)
     }
 
+    #[cfg_attr(not(feature = "profile"), allow(clippy::only_used_in_recursion))]
     fn walk_impl(&self, t: TermId, terms: &Arena<Term>, depth: usize) -> TermId {
         match terms.get(t) {
             Term::Var(v) => {
@@ -386,6 +394,14 @@ pub struct State {
     pub subst: Subst,
     pub constraints: ConstraintStore,
     pub goals: Vector<PropId>,
+    /// The goals taken on the way here from the query.
+    pub depth: usize,
+    /// What the calls on the way here cost, by the program's `Cost`
+    /// declarations.
+    pub cost: u64,
+    /// Costs whose terms were still unbound when their calls resolved,
+    /// added to `cost` once a later goal binds them.
+    pub pending_costs: Vector<TermId>,
 }
 
 impl State {
@@ -394,6 +410,9 @@ impl State {
             subst: Subst::new(),
             constraints: ConstraintStore::new(),
             goals: Vector::unit(initial_goal),
+            depth: 0,
+            cost: 0,
+            pending_costs: Vector::new(),
         }
     }
 
@@ -402,6 +421,9 @@ impl State {
             subst: Subst::new(),
             constraints: ConstraintStore::new(),
             goals: Vector::new(),
+            depth: 0,
+            cost: 0,
+            pending_costs: Vector::new(),
         }
     }
 
@@ -410,6 +432,9 @@ impl State {
             subst,
             constraints: self.constraints.clone(),
             goals: self.goals.clone(),
+            depth: self.depth,
+            cost: self.cost,
+            pending_costs: self.pending_costs.clone(),
         }
     }
 
@@ -418,6 +443,9 @@ impl State {
             subst: self.subst.clone(),
             constraints: self.constraints.add(c),
             goals: self.goals.clone(),
+            depth: self.depth,
+            cost: self.cost,
+            pending_costs: self.pending_costs.clone(),
         }
     }
 
@@ -426,6 +454,9 @@ impl State {
             subst: self.subst.clone(),
             constraints: self.constraints.clone(),
             goals: self.goals.clone() + Vector::unit(goal),
+            depth: self.depth,
+            cost: self.cost,
+            pending_costs: self.pending_costs.clone(),
         }
     }
 
@@ -438,6 +469,9 @@ impl State {
             subst: self.subst.clone(),
             constraints: self.constraints.clone(),
             goals,
+            depth: self.depth,
+            cost: self.cost,
+            pending_costs: self.pending_costs.clone(),
         }
     }
 
@@ -453,6 +487,9 @@ impl State {
                     subst: self.subst.clone(),
                     constraints: self.constraints.clone(),
                     goals,
+                    depth: self.depth + 1,
+                    cost: self.cost,
+                    pending_costs: self.pending_costs.clone(),
                 },
             ))
         }
@@ -468,8 +505,28 @@ pub enum SearchStrategy {
     #[default]
     BFS,
     DFS,
+    /// Depth-first, except that a branch goes to the back of the queue every
+    /// [`INTERLEAVE_TURN`] goals, so an infinite branch can't starve the
+    /// others.
+    Interleave,
+    /// Depth-first searches cut off at depths 1, 2, 3 and so on up to
+    /// `max_depth`, each one reporting only the solutions at its own depth,
+    /// so solutions come shallowest first.
+    IterativeDeepening { max_depth: usize },
+    /// The cheapest state first by the program's `Cost` declarations, ties
+    /// going to the state pushed first. Costs are never negative, so the
+    /// first solution is a cheapest one.
+    BestFirst,
 }
 
+/// How many goals a branch takes in a row under
+/// [`SearchStrategy::Interleave`].
+pub const INTERLEAVE_TURN: usize = 8;
+
+/// The depth bound [`SearchStrategy::IterativeDeepening`] gets when none is
+/// given.
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TerminationReason {
     LimitReached,
@@ -492,40 +549,139 @@ impl SolutionSet {
 pub struct SearchQueue {
     pub queue: VecDeque<State>,
     pub strategy: SearchStrategy,
+    /// Whether a state has been popped and none pushed since, so the next
+    /// push is the first way on from it.
+    continuing: bool,
+    deepening: Deepening,
+    /// The states of a best-first search, which don't go in `queue`.
+    ranked: BinaryHeap<Ranked>,
+    pushed: u64,
+}
+
+/// A state in a best-first search, ordered so that the heap's greatest is
+/// the cheapest and, among those, the first pushed.
+struct Ranked {
+    cost: u64,
+    order: u64,
+    state: State,
+}
+
+impl Ranked {
+    fn key(&self) -> (u64, u64) {
+        (self.cost, self.order)
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+/// Where an iterative-deepening search is up to.
+#[derive(Default)]
+struct Deepening {
+    /// The states pushed before the first pop, to start each search from.
+    roots: Vec<State>,
+    started: bool,
+    limit: usize,
+    /// The limit of the search before, whose solutions were reported then.
+    reported: Option<usize>,
+    /// Whether this search cut off a state, so a deeper one may find more.
+    cut: bool,
 }
 
 impl SearchQueue {
     pub fn new() -> Self {
-        Self {
-            queue: VecDeque::new(),
-            strategy: SearchStrategy::default(),
-        }
+        Self::with_strategy(SearchStrategy::default())
     }
 
     pub fn with_strategy(strategy: SearchStrategy) -> Self {
         Self {
             queue: VecDeque::new(),
             strategy,
+            continuing: false,
+            deepening: Deepening { limit: 1, ..Deepening::default() },
+            ranked: BinaryHeap::new(),
+            pushed: 0,
         }
     }
 
     pub fn push(&mut self, state: State) {
-        self.queue.push_back(state);
+        let continuing = std::mem::take(&mut self.continuing);
+        match self.strategy {
+            SearchStrategy::Interleave if continuing && !state.depth.is_multiple_of(INTERLEAVE_TURN) => {
+                self.queue.push_front(state);
+            }
+            SearchStrategy::IterativeDeepening { .. } if !self.deepening.started => {
+                self.deepening.roots.push(state.clone());
+                self.queue.push_back(state);
+            }
+            SearchStrategy::BestFirst => {
+                self.pushed += 1;
+                self.ranked.push(Ranked { cost: state.cost, order: self.pushed, state });
+            }
+            _ => self.queue.push_back(state),
+        }
     }
 
     pub fn pop(&mut self) -> Option<State> {
+        self.continuing = true;
         match self.strategy {
-            SearchStrategy::BFS => self.queue.pop_front(),
+            SearchStrategy::BFS | SearchStrategy::Interleave => self.queue.pop_front(),
             SearchStrategy::DFS => self.queue.pop_back(),
+            SearchStrategy::IterativeDeepening { max_depth } => self.pop_deepening(max_depth),
+            SearchStrategy::BestFirst => self.ranked.pop().map(|ranked| ranked.state),
+        }
+    }
+
+    fn pop_deepening(&mut self, max_depth: usize) -> Option<State> {
+        let deepening = &mut self.deepening;
+        deepening.started = true;
+        loop {
+            match self.queue.pop_back() {
+                Some(state) if state.depth > deepening.limit => deepening.cut = true,
+                Some(state) if state.is_solved() && deepening.reported.is_some_and(|r| state.depth <= r) => {}
+                Some(state) => return Some(state),
+                None if deepening.cut && deepening.limit < max_depth => {
+                    deepening.reported = Some(deepening.limit);
+                    deepening.limit += 1;
+                    deepening.cut = false;
+                    self.queue.extend(deepening.roots.iter().cloned());
+                }
+                None => return None,
+            }
         }
     }
 
+    /// Whether the search is over. An iterative-deepening search that cut
+    /// off states below its bound still has a deeper search to go.
     pub fn is_empty(&self) -> bool {
-        self.queue.is_empty()
+        let deeper = match self.strategy {
+            SearchStrategy::IterativeDeepening { max_depth } => {
+                self.deepening.cut && self.deepening.limit < max_depth
+            }
+            _ => false,
+        };
+        self.queue.is_empty() && self.ranked.is_empty() && !deeper
     }
 
     pub fn len(&self) -> usize {
-        self.queue.len()
+        self.queue.len() + self.ranked.len()
     }
 
     pub fn strategy(&self) -> SearchStrategy {
@@ -543,14 +699,17 @@ pub struct Solver<'p> {
     pub program: &'p mut Program,
     fresh_counter: u32,
     z3_solver: z3::Solver,
+    tables: Tables,
 }
 
 impl<'p> Solver<'p> {
     pub fn new(program: &'p mut Program) -> Self {
+        datalog::update(program);
         Self {
             program,
             fresh_counter: 0,
             z3_solver: z3::Solver::new(),
+            tables: Tables::default(),
         }
     }
 
@@ -682,9 +841,7 @@ impl<'p> Solver<'p> {
             Prop::True => {
                 queue.push(state);
             }
-            Prop::False => {
-                queue.pop();
-            }
+            Prop::False => {}
             Prop::Eq(t1, t2) => {
                 if let Some(new_subst) = state.subst.unify(t1, t2, &self.program.terms) {
                     queue.push(state.with_subst(new_subst));
@@ -751,6 +908,9 @@ impl<'p> Solver<'p> {
                                     subst: solved_subst,
                                     constraints: remaining,
                                     goals: new_state.goals,
+                                    depth: new_state.depth,
+                                    cost: new_state.cost,
+                                    pending_costs: new_state.pending_costs,
                                 });
                             }
                         }
@@ -767,45 +927,104 @@ impl<'p> Solver<'p> {
         args: &[TermId],
         queue: &mut SearchQueue,
     ) {
-        let matching_facts: Vec<Vec<TermId>> = self
-            .program
-            .facts
-            .iter()
-            .filter_map(|&prop_id| {
-                match self.program.props.get(prop_id) {
-                    Prop::App { rel: fact_rel, args: fact_args } if *fact_rel == rel => {
-                        Some(fact_args.clone())
-                    }
-                    _ => None,
-                }
-            })
-            .collect();
+        let materialized = self.program.datalog.relations.contains(&rel);
+        if self.program.tabled_rels.contains(&rel) && !materialized {
+            self.step_tabled(state, rel, args, queue);
+        } else {
+            self.resolve_user_rel(state, rel, args, queue);
+        }
+    }
 
-        for fact_args in matching_facts {
-            if let Some(new_subst) = state.subst.unify_args(args, &fact_args, &self.program.terms) {
-                queue.push(state.with_subst(new_subst));
+    /// Pushes a state for each fact and clause of `rel` that `args` unify
+    /// with.
+    fn resolve_user_rel(
+        &mut self,
+        state: &State,
+        rel: RelId,
+        args: &[TermId],
+        queue: &mut SearchQueue,
+    ) {
+        let terms = &self.program.terms;
+        let keys: Vec<Option<ArgKey>> =
+            args.iter().map(|&arg| ArgKey::of(terms.get(state.subst.walk(arg, terms)))).collect();
+
+        for fact in self.program.candidate_facts(rel, &keys) {
+            let Prop::App { args: fact_args, .. } = self.program.props.get(fact) else {
+                continue;
+            };
+            if let Some(new_subst) = state.subst.unify_args(args, fact_args, &self.program.terms) {
+                queue.push(self.charge(state.with_subst(new_subst), rel, args, queue.strategy()));
             }
         }
 
-        let clauses: Vec<Clause> = self
-            .program
-            .global_rules
-            .iter()
-            .filter(|c| c.head_rel == rel)
-            .cloned()
-            .collect();
-
-        for clause in clauses {
+        // The facts of a materialized relation already hold everything its
+        // rules derive.
+        if self.program.datalog.relations.contains(&rel) {
+            return;
+        }
+        for position in self.program.candidate_clauses(rel, &keys) {
+            let clause = self.program.global_rules[position].clone();
             let (new_head_args, new_body) = self.instantiate_clause(&clause);
 
-            if let Some(new_subst) =
-                state.subst.unify_args(args, &new_head_args, &self.program.terms)
-            {
-                queue.push(state.with_subst(new_subst).with_goal(new_body));
+            if let Some(new_subst) = state.subst.unify_args(args, &new_head_args, &self.program.terms) {
+                let charged = self.charge(state.with_subst(new_subst), rel, args, queue.strategy());
+                queue.push(charged.with_goal(new_body));
             }
         }
     }
 
+    /// `state` plus what resolving `rel` on `args` costs: the cost of the
+    /// first `Cost` declaration whose pattern the resolved arguments match.
+    /// Costs only order a best-first search, so other searches charge
+    /// nothing.
+    fn charge(&mut self, state: State, rel: RelId, args: &[TermId], strategy: SearchStrategy) -> State {
+        if strategy != SearchStrategy::BestFirst {
+            return state;
+        }
+        let Some(rules) = self.program.costs.get(&rel).cloned() else {
+            return state;
+        };
+        let call: Vec<TermId> = args.iter().map(|&arg| self.resolve(arg, &state.subst)).collect();
+        for rule in rules {
+            let mut vars = HashMap::new();
+            let pattern: Vec<TermId> = rule.args.iter().map(|&arg| self.rename_term(arg, &mut vars)).collect();
+            let cost = self.rename_term(rule.cost, &mut vars);
+            // The pattern goes first, so that a pattern variable binds to a
+            // variable of the call rather than the other way round.
+            let Some(subst) = state.subst.unify_args(&pattern, &call, &self.program.terms) else {
+                continue;
+            };
+            // A pattern matches when it binds only its own variables.
+            if call.iter().any(|&arg| self.resolve(arg, &subst) != arg) {
+                continue;
+            }
+            // The cost as the call has it, which the pattern's bindings
+            // don't outlive.
+            let cost = subst.walk(cost, &self.program.terms);
+            let mut pending_costs = state.pending_costs.clone();
+            pending_costs.push_back(cost);
+            return self.settle_costs(State { pending_costs, ..state });
+        }
+        state
+    }
+
+    /// `state` with the pending costs whose terms are bound by now added to
+    /// its cost. A rule's body may bind its cost only after the call
+    /// resolves, and a cost that comes out as anything but a non-negative
+    /// integer charges nothing.
+    fn settle_costs(&self, state: State) -> State {
+        let mut cost = state.cost;
+        let mut pending_costs = Vector::new();
+        for &term in &state.pending_costs {
+            match self.program.terms.get(state.subst.walk(term, &self.program.terms)) {
+                Term::Var(_) => pending_costs.push_back(term),
+                Term::Int(n) => cost += u64::try_from(*n).unwrap_or(0),
+                _ => {}
+            }
+        }
+        State { cost, pending_costs, ..state }
+    }
+
     fn make_int_constraint(&self, name: &str, args: &[TermId]) -> Option<ArithConstraint> {
         match (name, args) {
             ("int_eq", [a, b]) => Some(ArithConstraint::IntEq(*a, *b)),
@@ -853,6 +1072,14 @@ impl<'p> Solver<'p> {
                 queue.push(state);
                 return (None, queue);
             }
+            // A state whose pending costs have come due goes back to wait
+            // its turn at its new cost.
+            let cost = state.cost;
+            let state = self.settle_costs(state);
+            if state.cost > cost {
+                queue.push(state);
+                continue;
+            }
 
             if let Some((goal, remaining)) = state.pop_goal() {
                 self.step_prop(remaining, goal, &mut queue);
@@ -868,6 +1095,9 @@ impl<'p> Solver<'p> {
                         subst: solved_subst,
                         constraints: ConstraintStore::new(),
                         goals: Vector::new(),
+                        depth: state.depth,
+                        cost: state.cost,
+                        pending_costs: state.pending_costs,
                     }),
                     queue,
                 );
@@ -876,12 +1106,16 @@ impl<'p> Solver<'p> {
         (None, queue)
     }
 
-    pub fn init_query(&mut self, goal: PropId, strategy: SearchStrategy) -> SearchQueue {
-        let mut state = State::new(goal);
+    /// The facts a search has to prove. Relation facts are looked up when a
+    /// goal calls them; only the equations fixing the state variables need
+    /// proving.
+    fn state_equations(&self) -> Vec<PropId> {
+        let facts = self.program.facts.iter().copied();
+        facts.filter(|&f| !matches!(self.program.props.get(f), Prop::App { .. })).collect()
+    }
 
-        for &fact_prop in &self.program.facts {
-            state = state.with_goal(fact_prop);
-        }
+    pub fn init_query(&mut self, goal: PropId, strategy: SearchStrategy) -> SearchQueue {
+        let state = State::new(goal).with_goals(self.state_equations());
 
         let mut queue = SearchQueue::with_strategy(strategy);
         queue.push(state);
@@ -942,6 +1176,9 @@ pub fn reify_term(term_id: TermId, subst: &Subst, program: &Program) -> String {
         Term::Atom(s) => program.symbols.get(*s).clone(),
         Term::Int(i) => i.to_string(),
         Term::Float(f) => f.to_string(),
+        Term::App { sym, args } if args.len() == 2 && program.symbols.get(*sym) == "cons" => {
+            reify_list(args[0], args[1], subst, program)
+        }
         Term::App { sym, args } => {
             let name = program.symbols.get(*sym).clone();
             let arg_strs: Vec<String> = args
@@ -953,6 +1190,28 @@ pub fn reify_term(term_id: TermId, subst: &Subst, program: &Program) -> String {
     }
 }
 
+/// Prints a `cons` chain as a list literal, `[a, b]` if it ends in `nil`
+/// and `[a, b | T]` otherwise.
+fn reify_list(head: TermId, tail: TermId, subst: &Subst, program: &Program) -> String {
+    let mut elements = vec![reify_term(head, subst, program)];
+    let mut tail = subst.walk(tail, &program.terms);
+    loop {
+        match program.terms.get(tail) {
+            Term::App { sym, args } if args.len() == 2 && program.symbols.get(*sym) == "cons" => {
+                elements.push(reify_term(args[0], subst, program));
+                tail = subst.walk(args[1], &program.terms);
+            }
+            Term::Atom(s) if program.symbols.get(*s) == "nil" => {
+                return format!("[{}]", elements.join(", "));
+            }
+            _ => {
+                let rest = reify_term(tail, subst, program);
+                return format!("[{} | {}]", elements.join(", "), rest);
+            }
+        }
+    }
+}
+
 pub fn format_solution(
     query_vars: &[(String, TermId)],
     state: &State,
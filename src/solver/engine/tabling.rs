@@ -0,0 +1,190 @@
+//! Tabled resolution for relations declared with `Table`.
+//!
+//! The first call of a tabled relation with a given pattern of arguments, a
+//! *variant*, fills an answer table by searching the relation's facts and
+//! clauses on their own, round after round, until a round adds no answers.
+//! A call of a variant whose table is still being filled reads the answers
+//! found so far and the next round follows up on what they lead to, so left
+//! recursion such as `reach(X, Z) :- reach(X, Y), edge(Y, Z)` stops once no
+//! new nodes turn up. Later calls of the variant only read its table.
+//!
+//! A table filled while reading one further out, as mutually recursive
+//! calls do, is only complete once that outer table is, and is refilled
+//! whenever it is called before then. An answer holds the call's resolved
+//! arguments and not the constraints left on them, so relations whose rules
+//! reach arithmetic constraints are not tabled. Tables last as long as the
+//! [`Solver`], which is one query or stage run. Under best-first search a
+//! call of a tabled relation costs what its own `Cost` declarations say,
+//! not what the calls made to fill its table would.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use super::{SearchQueue, SearchStrategy, Solver, State, Subst};
+use crate::solver::ir::{RelId, Term, TermId, VarId};
+
+pub(super) struct Tables {
+    tables: HashMap<String, Table>,
+    /// The variants being filled, innermost last.
+    stack: Vec<String>,
+    /// The lowest place in `stack` read from since the innermost fill began.
+    floor: usize,
+    /// Variants filled by reading a table further down `stack`, to be
+    /// completed along with it.
+    incomplete: Vec<String>,
+    /// Answers across all tables, to tell when a round found nothing new.
+    answer_count: usize,
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Self {
+            tables: HashMap::new(),
+            stack: Vec::new(),
+            floor: usize::MAX,
+            incomplete: Vec::new(),
+            answer_count: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    /// The arguments of each answer, fully resolved.
+    answers: Vec<Vec<TermId>>,
+    seen: HashSet<String>,
+    complete: bool,
+}
+
+impl Solver<'_> {
+    pub(super) fn step_tabled(&mut self, state: &State, rel: RelId, args: &[TermId], queue: &mut SearchQueue) {
+        let call: Vec<TermId> = args.iter().map(|&arg| self.resolve(arg, &state.subst)).collect();
+        let key = self.variant(rel, &call);
+
+        if let Some(position) = self.tables.stack.iter().position(|k| *k == key) {
+            self.tables.floor = self.tables.floor.min(position);
+        } else if !self.tables.tables.get(&key).is_some_and(|t| t.complete) {
+            self.fill_table(rel, &call, &key);
+        }
+
+        for answer in self.tables.tables[&key].answers.clone() {
+            let mut vars = im::HashMap::new();
+            let answer: Vec<TermId> = answer.iter().map(|&t| self.rename_term(t, &mut vars)).collect();
+            if let Some(subst) = state.subst.unify_args(args, &answer, &self.program.terms) {
+                queue.push(self.charge(state.with_subst(subst), rel, args, queue.strategy()));
+            }
+        }
+    }
+
+    fn fill_table(&mut self, rel: RelId, call: &[TermId], key: &str) {
+        self.tables.tables.entry(key.to_string()).or_default();
+        let position = self.tables.stack.len();
+        self.tables.stack.push(key.to_string());
+        let outer_floor = std::mem::replace(&mut self.tables.floor, usize::MAX);
+        let incomplete = self.tables.incomplete.len();
+
+        loop {
+            let before = self.tables.answer_count;
+            for answer in self.solve_call(rel, call) {
+                self.add_answer(key, answer);
+            }
+            if self.tables.answer_count == before {
+                break;
+            }
+        }
+
+        self.tables.stack.pop();
+        let floor = std::mem::replace(&mut self.tables.floor, outer_floor);
+        if floor >= position {
+            // Nothing further out was read, so this table and those filled
+            // on the way are final.
+            let done: Vec<String> = self.tables.incomplete.drain(incomplete..).collect();
+            for key in done.iter().map(String::as_str).chain([key]) {
+                if let Some(table) = self.tables.tables.get_mut(key) {
+                    table.complete = true;
+                }
+            }
+        } else {
+            self.tables.floor = outer_floor.min(floor);
+            self.tables.incomplete.push(key.to_string());
+        }
+    }
+
+    /// One round of answers for `call`, searched apart from the caller.
+    fn solve_call(&mut self, rel: RelId, call: &[TermId]) -> Vec<Vec<TermId>> {
+        let start = State::empty().with_goals(self.state_equations());
+        let mut queue = SearchQueue::with_strategy(SearchStrategy::DFS);
+        self.resolve_user_rel(&start, rel, call, &mut queue);
+
+        let mut answers = Vec::new();
+        loop {
+            let (solution, rest) = self.step_until_solution(queue, usize::MAX);
+            let Some(solution) = solution else {
+                return answers;
+            };
+            answers.push(call.iter().map(|&arg| self.resolve(arg, &solution.subst)).collect());
+            queue = rest;
+        }
+    }
+
+    fn add_answer(&mut self, key: &str, answer: Vec<TermId>) {
+        let mut text = String::new();
+        let mut vars = HashMap::new();
+        for &arg in &answer {
+            self.write_variant(arg, &mut vars, &mut text);
+        }
+        let table = self.tables.tables.get_mut(key).unwrap();
+        if table.seen.insert(text) {
+            table.answers.push(answer);
+            self.tables.answer_count += 1;
+        }
+    }
+
+    /// `term` with every bound variable replaced by its value.
+    pub(super) fn resolve(&mut self, term: TermId, subst: &Subst) -> TermId {
+        let term = subst.walk(term, &self.program.terms);
+        match self.program.terms.get(term).clone() {
+            Term::App { sym, args } => {
+                let resolved: Vec<TermId> = args.iter().map(|&a| self.resolve(a, subst)).collect();
+                if resolved == args {
+                    term
+                } else {
+                    self.program.terms.alloc(Term::App { sym, args: resolved })
+                }
+            }
+            _ => term,
+        }
+    }
+
+    /// A key shared by the calls of `rel` that are the same up to the
+    /// names of their variables.
+    fn variant(&self, rel: RelId, call: &[TermId]) -> String {
+        let mut text = format!("{}", rel.index());
+        let mut vars = HashMap::new();
+        for &arg in call {
+            text.push(' ');
+            self.write_variant(arg, &mut vars, &mut text);
+        }
+        text
+    }
+
+    fn write_variant(&self, term: TermId, vars: &mut HashMap<VarId, usize>, out: &mut String) {
+        let _ = match self.program.terms.get(term) {
+            Term::Var(v) => {
+                let next = vars.len();
+                write!(out, "_{}", vars.entry(*v).or_insert(next))
+            }
+            Term::Atom(sym) => write!(out, "a{}", sym.index()),
+            Term::Int(i) => write!(out, "i{}", i),
+            Term::Float(f) => write!(out, "f{}", if *f == 0.0 { 0.0 } else { *f }),
+            Term::App { sym, args } => {
+                let _ = write!(out, "s{}(", sym.index());
+                for &arg in args {
+                    self.write_variant(arg, vars, out);
+                    out.push(',');
+                }
+                write!(out, ")")
+            }
+        };
+    }
+}
@@ -5,7 +5,13 @@ use nom::Finish;
 
 use crate::solver::ir;
 
-const ALL_STRATEGIES: [SearchStrategy; 2] = [SearchStrategy::BFS, SearchStrategy::DFS];
+const ALL_STRATEGIES: [SearchStrategy; 5] = [
+    SearchStrategy::BFS,
+    SearchStrategy::DFS,
+    SearchStrategy::Interleave,
+    SearchStrategy::IterativeDeepening { max_depth: 64 },
+    SearchStrategy::BestFirst,
+];
 
 fn parse_and_compile(input: &str) -> Program {
     let result = parser::parse_module(input.into()).finish();
@@ -718,3 +724,42 @@ fn test_cartcost_unbound_maxsize() {
 
     assert!(!solution_set.solutions().is_empty(), "Should find carts costing 25 with unbound MaxSize");
 }
+
+#[test]
+fn test_failing_branch_keeps_the_other_states() {
+    for_each_strategy(|strategy| {
+        let input = r#"Begin Facts:
+End Facts
+
+Begin Global:
+End Global
+"#;
+        let mut program = parse_and_compile(input);
+
+        let var = program.vars.alloc(ir::Var {
+            name: "X".to_string(),
+        });
+        let var_term = program.terms.alloc(Term::Var(var));
+        let one_term = program.terms.alloc(Term::Int(1));
+
+        let fail = program.props.alloc(Prop::False);
+        let eq = program.props.alloc(Prop::Eq(var_term, one_term));
+        // Whichever branch the strategy takes first, `false` must only end
+        // its own branch and leave the other one queued.
+        let queries = [
+            program.props.alloc(Prop::Or(fail, eq)),
+            program.props.alloc(Prop::Or(eq, fail)),
+        ];
+
+        let mut solver = Solver::new(&mut program);
+        for query_prop in queries {
+            let solution_set = solver.collect_solutions(query_prop, strategy, usize::MAX, 100_000);
+            assert_eq!(
+                solution_set.solutions().len(),
+                1,
+                "or(false, eq(X, 1)) should still find X = 1 (strategy: {:?})",
+                strategy
+            );
+        }
+    });
+}
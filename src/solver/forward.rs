@@ -0,0 +1,876 @@
+//! Forward compilation of stage state constraints into native step functions.
+//!
+//! Most stages compute each `next(X)` from the current state with a chain of
+//! arithmetic and conditionals, and running them through the solver means a
+//! proof search and a Z3 call per frame. When every constraint of a stage can
+//! be run left to right, with each equation or arithmetic relation either
+//! checking known values or determining exactly one unknown, this pass turns
+//! the constraints into closures that compute the next state directly.
+//!
+//! A stage that uses user relations, disjunction, or leaves some `next(X)`
+//! open does not compile, and `Frontend::run_stage` keeps using the solver for
+//! it. A compiled step gives up at runtime instead of guessing whenever the
+//! solver might disagree with it, for instance on overflow or when a
+//! constraint fails, so that the solver can report the error.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::SourceSpan;
+use crate::solver::ir::{
+    float_to_rational, Clause, DrawDirective, Program, Prop, PropId, RelId, RelKind, Stage, SymbolId, Term, TermId, VarId,
+};
+
+/// A ground value of a state variable or an intermediate variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Real(f32),
+    Atom(SymbolId),
+    App(SymbolId, Vec<Value>),
+}
+
+impl Value {
+    /// The value of `term`, if it is ground.
+    pub fn from_term(program: &Program, term: TermId) -> Option<Value> {
+        match program.terms.get(term) {
+            Term::Var(_) => None,
+            Term::Int(i) => Some(Value::Int(*i)),
+            Term::Float(f) => Some(Value::Real(*f)),
+            Term::Atom(sym) => Some(Value::Atom(*sym)),
+            Term::App { sym, args } => {
+                let args = args.iter().map(|&a| Value::from_term(program, a)).collect::<Option<_>>()?;
+                Some(Value::App(*sym, args))
+            }
+        }
+    }
+
+    pub fn to_term(&self, program: &mut Program) -> TermId {
+        let term = match self {
+            Value::Int(i) => Term::Int(*i),
+            Value::Real(f) => Term::Float(*f),
+            Value::Atom(sym) => Term::Atom(*sym),
+            Value::App(sym, args) => Term::App {
+                sym: *sym,
+                args: args.iter().map(|a| a.to_term(program)).collect(),
+            },
+        };
+        program.terms.alloc(term)
+    }
+}
+
+/// An exact rational, which is how Z3 computes with reals.
+#[derive(Debug, Clone, Copy)]
+struct Ratio {
+    num: i128,
+    den: i128,
+}
+
+impl Ratio {
+    fn new(num: i128, den: i128) -> Option<Ratio> {
+        if den == 0 {
+            return None;
+        }
+        fn gcd(a: i128, b: i128) -> i128 {
+            if b == 0 { a.abs() } else { gcd(b, a % b) }
+        }
+        let g = gcd(num, den).max(1) * den.signum();
+        Some(Ratio { num: num / g, den: den / g })
+    }
+
+    fn add(self, other: Ratio) -> Option<Ratio> {
+        let num = self.num.checked_mul(other.den)?.checked_add(other.num.checked_mul(self.den)?)?;
+        Ratio::new(num, self.den.checked_mul(other.den)?)
+    }
+
+    fn sub(self, other: Ratio) -> Option<Ratio> {
+        self.add(Ratio { num: -other.num, den: other.den })
+    }
+
+    fn mul(self, other: Ratio) -> Option<Ratio> {
+        Ratio::new(self.num.checked_mul(other.num)?, self.den.checked_mul(other.den)?)
+    }
+
+    fn div(self, other: Ratio) -> Option<Ratio> {
+        Ratio::new(self.num.checked_mul(other.den)?, self.den.checked_mul(other.num)?)
+    }
+
+    fn cmp(self, other: Ratio) -> Option<std::cmp::Ordering> {
+        Some(self.num.checked_mul(other.den)?.cmp(&other.num.checked_mul(self.den)?))
+    }
+
+    /// Converts the way the solver reads Z3's rationals back into floats.
+    fn to_value(self) -> Option<Value> {
+        let num = i64::try_from(self.num).ok()?;
+        let den = i64::try_from(self.den).ok()?;
+        Some(Value::Real(num as f32 / den as f32))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Family {
+    Int,
+    Real,
+}
+
+impl Family {
+    fn of(kind: &RelKind) -> Option<Family> {
+        match kind {
+            RelKind::User => None,
+            RelKind::SMTInt => Some(Family::Int),
+            RelKind::SMTReal => Some(Family::Real),
+        }
+    }
+
+    /// Reads `value` as a number of this family. Ints may stand in for
+    /// reals, as they can in the solver, but not the other way around.
+    fn ratio(self, value: &Value) -> Option<Ratio> {
+        match (self, value) {
+            (_, Value::Int(i)) => Ratio::new(*i as i128, 1),
+            (Family::Real, Value::Real(f)) => {
+                let (num, den) = float_to_rational(*f);
+                Ratio::new(num as i128, den as i128)
+            }
+            _ => None,
+        }
+    }
+
+    fn value(self, ratio: Ratio) -> Option<Value> {
+        match self {
+            Family::Int if ratio.den == 1 => Some(Value::Int(i32::try_from(ratio.num).ok()?)),
+            Family::Int => None,
+            Family::Real => ratio.to_value(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl ArithOp {
+    fn from_name(name: &str) -> Option<ArithOp> {
+        match name {
+            "add" => Some(ArithOp::Add),
+            "sub" => Some(ArithOp::Sub),
+            "mul" => Some(ArithOp::Mul),
+            "div" => Some(ArithOp::Div),
+            _ => None,
+        }
+    }
+
+    fn apply(self, family: Family, a: Ratio, b: Ratio) -> Option<Ratio> {
+        match (self, family) {
+            (ArithOp::Add, _) => a.add(b),
+            (ArithOp::Sub, _) => a.sub(b),
+            (ArithOp::Mul, _) => a.mul(b),
+            (ArithOp::Div, Family::Real) => a.div(b),
+            (ArithOp::Div, Family::Int) => {
+                // Z3's integer division rounds so the remainder is non-negative.
+                let quotient = a.num.checked_div_euclid(b.num)?;
+                Ratio::new(quotient, 1)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Cmp {
+    Eq,
+    Neq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn from_name(name: &str) -> Option<Cmp> {
+        match name {
+            "eq" => Some(Cmp::Eq),
+            "neq" => Some(Cmp::Neq),
+            "lt" => Some(Cmp::Lt),
+            "le" => Some(Cmp::Le),
+            "gt" => Some(Cmp::Gt),
+            "ge" => Some(Cmp::Ge),
+            _ => None,
+        }
+    }
+
+    fn test(self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            Cmp::Eq => ordering.is_eq(),
+            Cmp::Neq => ordering.is_ne(),
+            Cmp::Lt => ordering.is_lt(),
+            Cmp::Le => ordering.is_le(),
+            Cmp::Gt => ordering.is_gt(),
+            Cmp::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+/// What a built-in relation says about literal arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Evaluated {
+    /// Every argument was given, and the relation holds or it doesn't.
+    Holds(bool),
+    /// The inputs of an arithmetic relation were given and its result
+    /// wasn't; this is the result.
+    Result(Value),
+}
+
+/// Evaluates the built-in relation `name` of family `kind` on `args`, the
+/// values of whichever arguments are ground. Gives up unless enough
+/// arguments are numbers of the relation's family, and wherever the solver
+/// might compute differently, such as on overflow or division by zero.
+pub(crate) fn evaluate_builtin(kind: &RelKind, name: &str, args: &[Option<Value>]) -> Option<Evaluated> {
+    let family = Family::of(kind)?;
+    let op = name.split_once('_').map_or("", |(_, op)| op);
+    let ratio = |value: &Option<Value>| value.as_ref().and_then(|v| family.ratio(v));
+    if let (Some(op), [a, b, c]) = (ArithOp::from_name(op), args) {
+        let result = op.apply(family, ratio(a)?, ratio(b)?)?;
+        return match c {
+            Some(_) => Some(Evaluated::Holds(result.cmp(ratio(c)?)?.is_eq())),
+            None => family.value(result).map(Evaluated::Result),
+        };
+    }
+    let (Some(cmp), [a, b]) = (Cmp::from_name(op), args) else {
+        return None;
+    };
+    Some(Evaluated::Holds(cmp.test(ratio(a)?.cmp(ratio(b)?)?)))
+}
+
+/// A term whose variables are all bound, read from the slots of a plan.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Slot(usize),
+    Int(i32),
+    Real(f32),
+    Atom(SymbolId),
+    App(SymbolId, Vec<Expr>),
+}
+
+impl Expr {
+    pub(crate) fn slots(&self, out: &mut HashSet<usize>) {
+        match self {
+            Expr::Slot(slot) => {
+                out.insert(*slot);
+            }
+            Expr::App(_, args) => args.iter().for_each(|a| a.slots(out)),
+            _ => {}
+        }
+    }
+}
+
+/// A term matched against a value, binding its unbound variables.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Pattern {
+    Check(Expr),
+    Bind(usize),
+    App(SymbolId, Vec<Pattern>),
+}
+
+impl Pattern {
+    fn reads(&self, out: &mut HashSet<usize>) {
+        match self {
+            Pattern::Check(expr) => expr.slots(out),
+            Pattern::Bind(_) => {}
+            Pattern::App(_, args) => args.iter().for_each(|a| a.reads(out)),
+        }
+    }
+}
+
+/// One constraint, ordered so that everything it reads is bound before it
+/// runs. Each step either holds or fails; none of them search.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Step {
+    Const(bool),
+    Match(Expr, Pattern),
+    /// `out = lhs op rhs`.
+    Compute { family: Family, op: ArithOp, lhs: Expr, rhs: Expr, out: usize },
+    /// `lhs op rhs = result`, with everything known.
+    Check { family: Family, op: ArithOp, lhs: Expr, rhs: Expr, result: Expr },
+    /// `out = value`, read as a number of `family`.
+    Convert { family: Family, value: Expr, out: usize },
+    Compare { family: Family, cmp: Cmp, lhs: Expr, rhs: Expr },
+    And(Box<Step>, Box<Step>),
+    Not(Box<Step>),
+    Cond(Box<Step>, Box<Step>, Box<Step>),
+    /// A user relation called with every argument known, which holds if one
+    /// of its facts or rules does.
+    Call { rel: RelId, args: Vec<Expr> },
+}
+
+impl Step {
+    /// The slots this step binds on the way to succeeding.
+    pub(crate) fn binds(&self, out: &mut Vec<usize>) {
+        fn pattern_binds(pattern: &Pattern, out: &mut Vec<usize>) {
+            match pattern {
+                Pattern::Check(_) => {}
+                Pattern::Bind(slot) => out.push(*slot),
+                Pattern::App(_, args) => args.iter().for_each(|a| pattern_binds(a, out)),
+            }
+        }
+        match self {
+            Step::Match(_, pattern) => pattern_binds(pattern, out),
+            Step::Compute { out: slot, .. } | Step::Convert { out: slot, .. } => out.push(*slot),
+            Step::And(lhs, rhs) => {
+                lhs.binds(out);
+                rhs.binds(out);
+            }
+            Step::Cond(guard, then, otherwise) => {
+                let mut then_binds = Vec::new();
+                guard.binds(&mut then_binds);
+                then.binds(&mut then_binds);
+                let mut otherwise_binds = Vec::new();
+                otherwise.binds(&mut otherwise_binds);
+                out.extend(then_binds.into_iter().filter(|s| otherwise_binds.contains(s)));
+            }
+            Step::Const(_) | Step::Check { .. } | Step::Compare { .. } | Step::Not(_) | Step::Call { .. } => {}
+        }
+    }
+
+    /// The slots this step reads, including those of nested steps.
+    pub(crate) fn reads(&self, out: &mut HashSet<usize>) {
+        match self {
+            Step::Const(_) => {}
+            Step::Match(value, pattern) => {
+                value.slots(out);
+                pattern.reads(out);
+            }
+            Step::Compute { lhs, rhs, .. } | Step::Compare { lhs, rhs, .. } => {
+                lhs.slots(out);
+                rhs.slots(out);
+            }
+            Step::Check { lhs, rhs, result, .. } => {
+                for expr in [lhs, rhs, result] {
+                    expr.slots(out);
+                }
+            }
+            Step::Convert { value, .. } => value.slots(out),
+            Step::And(lhs, rhs) => {
+                lhs.reads(out);
+                rhs.reads(out);
+            }
+            Step::Not(inner) => inner.reads(out),
+            Step::Cond(guard, then, otherwise) => {
+                for step in [guard, then, otherwise] {
+                    step.reads(out);
+                }
+            }
+            Step::Call { args, .. } => args.iter().for_each(|a| a.slots(out)),
+        }
+    }
+}
+
+/// A variable bound before the plan runs.
+#[derive(Debug, Clone)]
+pub(crate) struct Input {
+    pub var: VarId,
+    /// The state variable `var` holds the current value of, if any.
+    pub state_var: Option<String>,
+    pub slot: usize,
+}
+
+/// Constraints compiled into steps over numbered slots, one per variable.
+#[derive(Debug, Clone)]
+pub(crate) struct Plan {
+    pub inputs: Vec<Input>,
+    /// Slots bound to the arguments of a rule before its head is matched.
+    pub params: usize,
+    /// The slot holding each `next(X)`, sorted by name.
+    pub outputs: Vec<(String, usize)>,
+    /// The variable each slot stands for.
+    pub vars: Vec<VarId>,
+    pub body: Step,
+}
+
+/// Why a constraint can't be run forward, and where.
+#[derive(Debug, Clone)]
+pub(crate) struct Unsupported {
+    pub message: String,
+    pub span: SourceSpan,
+}
+
+struct Planner<'p> {
+    program: &'p Program,
+    slots: HashMap<VarId, usize>,
+    vars: Vec<VarId>,
+    /// Variables bound before the constraints run, with the state variable
+    /// each holds, if any.
+    globals: HashMap<VarId, Option<String>>,
+    inputs: Vec<Input>,
+    /// The innermost constraint with a source location, for errors.
+    span: SourceSpan,
+}
+
+impl<'p> Planner<'p> {
+    fn new(program: &'p Program, fact_vars: bool, span: &SourceSpan) -> Planner<'p> {
+        let mut globals = HashMap::new();
+        // Rules are instantiated with fresh copies of everything but the
+        // state variables, so only stages and draws see the fact variables.
+        if fact_vars {
+            for &fact in &program.facts {
+                if let Prop::Eq(lhs, rhs) = program.props.get(fact) {
+                    for term in [lhs, rhs] {
+                        if let Term::Var(v) = program.terms.get(*term) {
+                            globals.insert(*v, None);
+                        }
+                    }
+                }
+            }
+        }
+        for (name, &term) in &program.state_var_term_ids {
+            if let Term::Var(v) = program.terms.get(term) {
+                globals.insert(*v, Some(name.clone()));
+            }
+        }
+        Planner {
+            program,
+            slots: HashMap::new(),
+            vars: Vec::new(),
+            globals,
+            inputs: Vec::new(),
+            span: span.clone(),
+        }
+    }
+
+    fn unsupported<T>(&self, message: String) -> Result<T, Unsupported> {
+        Err(Unsupported { message, span: self.span.clone() })
+    }
+
+    fn bound(&self) -> HashSet<VarId> {
+        self.globals.keys().copied().collect()
+    }
+
+    fn slot(&mut self, var: VarId) -> usize {
+        if let Some(&slot) = self.slots.get(&var) {
+            return slot;
+        }
+        let slot = self.vars.len();
+        self.slots.insert(var, slot);
+        self.vars.push(var);
+        if let Some(state_var) = self.globals.get(&var) {
+            self.inputs.push(Input { var, state_var: state_var.clone(), slot });
+        }
+        slot
+    }
+
+    fn is_known(&self, term: TermId, bound: &HashSet<VarId>) -> bool {
+        match self.program.terms.get(term) {
+            Term::Var(v) => bound.contains(v),
+            Term::App { args, .. } => args.iter().all(|&a| self.is_known(a, bound)),
+            _ => true,
+        }
+    }
+
+    fn expr(&mut self, term: TermId) -> Expr {
+        match self.program.terms.get(term).clone() {
+            Term::Var(v) => Expr::Slot(self.slot(v)),
+            Term::Int(i) => Expr::Int(i),
+            Term::Float(f) => Expr::Real(f),
+            Term::Atom(sym) => Expr::Atom(sym),
+            Term::App { sym, args } => Expr::App(sym, args.iter().map(|&a| self.expr(a)).collect()),
+        }
+    }
+
+    /// Matches `term` against a value, binding the variables of `term` that
+    /// are not yet bound from left to right.
+    fn pattern(&mut self, term: TermId, bound: &mut HashSet<VarId>) -> Pattern {
+        if self.is_known(term, bound) {
+            return Pattern::Check(self.expr(term));
+        }
+        match self.program.terms.get(term).clone() {
+            Term::Var(v) => {
+                bound.insert(v);
+                Pattern::Bind(self.slot(v))
+            }
+            Term::App { sym, args } => Pattern::App(sym, args.iter().map(|&a| self.pattern(a, bound)).collect()),
+            _ => unreachable!("literals are always known"),
+        }
+    }
+
+    /// The slot of `target`, which the step being planned binds.
+    fn target(&mut self, target: TermId, name: &str, bound: &mut HashSet<VarId>) -> Result<usize, Unsupported> {
+        match self.program.terms.get(target) {
+            Term::Var(v) => {
+                bound.insert(*v);
+                Ok(self.slot(*v))
+            }
+            _ => self.unsupported(format!("`{}` can only determine a variable here", name)),
+        }
+    }
+
+    fn prop(&mut self, prop: PropId, bound: &mut HashSet<VarId>) -> Result<Step, Unsupported> {
+        let outer = self.span.clone();
+        if let Some(span) = self.program.prop_spans.get(&prop) {
+            self.span = span.clone();
+        }
+        let step = self.prop_inner(prop, bound);
+        self.span = outer;
+        step
+    }
+
+    fn prop_inner(&mut self, prop: PropId, bound: &mut HashSet<VarId>) -> Result<Step, Unsupported> {
+        match self.program.props.get(prop).clone() {
+            Prop::True => Ok(Step::Const(true)),
+            Prop::False => Ok(Step::Const(false)),
+            Prop::Eq(lhs, rhs) => {
+                let (known, other) = if self.is_known(lhs, bound) {
+                    (lhs, rhs)
+                } else if self.is_known(rhs, bound) {
+                    (rhs, lhs)
+                } else {
+                    return self.unsupported("neither side of this equation is known here".to_string());
+                };
+                let value = self.expr(known);
+                Ok(Step::Match(value, self.pattern(other, bound)))
+            }
+            Prop::And(lhs, rhs) => {
+                let first = self.prop(lhs, bound)?;
+                let second = self.prop(rhs, bound)?;
+                Ok(Step::And(Box::new(first), Box::new(second)))
+            }
+            Prop::Not(inner) => Ok(Step::Not(Box::new(self.prop(inner, &mut bound.clone())?))),
+            Prop::Cond(guard, then, otherwise) => {
+                let mut then_bound = bound.clone();
+                let guard = self.prop(guard, &mut then_bound)?;
+                let then = self.prop(then, &mut then_bound)?;
+                let mut otherwise_bound = bound.clone();
+                let otherwise = self.prop(otherwise, &mut otherwise_bound)?;
+                *bound = then_bound.intersection(&otherwise_bound).copied().collect();
+                Ok(Step::Cond(Box::new(guard), Box::new(then), Box::new(otherwise)))
+            }
+            Prop::Or(..) => self.unsupported("`or` needs the solver to try both branches".to_string()),
+            Prop::App { rel, args } => {
+                let info = self.program.rels.get(rel);
+                let Some(family) = Family::of(&info.kind) else {
+                    if !args.iter().all(|&a| self.is_known(a, bound)) {
+                        return self.unsupported(format!(
+                            "`{}` is called with arguments that are not known here, which needs the solver",
+                            info.name
+                        ));
+                    }
+                    let args = args.iter().map(|&a| self.expr(a)).collect();
+                    return Ok(Step::Call { rel, args });
+                };
+                let name = info.name.clone();
+                self.builtin(family, &name, &args, bound)
+            }
+        }
+    }
+
+    fn builtin(&mut self, family: Family, name: &str, args: &[TermId], bound: &mut HashSet<VarId>) -> Result<Step, Unsupported> {
+        let op = ArithOp::from_name(name.split_once('_').map_or("", |(_, op)| op));
+
+        if let (Some(op), &[a, b, c]) = (op, args) {
+            let known = [a, b, c].map(|t| self.is_known(t, bound));
+            let invertible = matches!(op, ArithOp::Add | ArithOp::Sub);
+            return match known {
+                [true, true, true] => {
+                    let (lhs, rhs, result) = (self.expr(a), self.expr(b), self.expr(c));
+                    Ok(Step::Check { family, op, lhs, rhs, result })
+                }
+                [true, true, false] => {
+                    let (lhs, rhs) = (self.expr(a), self.expr(b));
+                    let out = self.target(c, name, bound)?;
+                    Ok(Step::Compute { family, op, lhs, rhs, out })
+                }
+                // Only sums and differences can be run backwards without
+                // worrying about rounding and division by zero.
+                [false, true, true] if invertible => {
+                    let (lhs, rhs) = (self.expr(c), self.expr(b));
+                    let op = if op == ArithOp::Add { ArithOp::Sub } else { ArithOp::Add };
+                    let out = self.target(a, name, bound)?;
+                    Ok(Step::Compute { family, op, lhs, rhs, out })
+                }
+                [true, false, true] if invertible => {
+                    let (lhs, rhs) = if op == ArithOp::Add { (self.expr(c), self.expr(a)) } else { (self.expr(a), self.expr(c)) };
+                    let out = self.target(b, name, bound)?;
+                    Ok(Step::Compute { family, op: ArithOp::Sub, lhs, rhs, out })
+                }
+                _ if invertible => self.unsupported(format!("`{}` needs two of its three arguments known here", name)),
+                _ => self.unsupported(format!("`{}` needs its first two arguments known here", name)),
+            };
+        }
+
+        let (Some(cmp), &[lhs, rhs]) = (Cmp::from_name(name.split_once('_').map_or("", |(_, op)| op)), args) else {
+            return self.unsupported(format!("`{}` is not supported here", name));
+        };
+        let (lhs_known, rhs_known) = (self.is_known(lhs, bound), self.is_known(rhs, bound));
+        if cmp == Cmp::Eq && lhs_known != rhs_known {
+            let (known, target) = if lhs_known { (lhs, rhs) } else { (rhs, lhs) };
+            let value = self.expr(known);
+            let out = self.target(target, name, bound)?;
+            return Ok(Step::Convert { family, value, out });
+        }
+        if !(lhs_known && rhs_known) {
+            return self.unsupported(format!("`{}` needs both arguments known here", name));
+        }
+        let (lhs, rhs) = (self.expr(lhs), self.expr(rhs));
+        Ok(Step::Compare { family, cmp, lhs, rhs })
+    }
+
+    fn finish(self, params: usize, outputs: Vec<(String, usize)>, body: Step) -> Plan {
+        Plan {
+            inputs: self.inputs,
+            params,
+            outputs,
+            vars: self.vars,
+            body,
+        }
+    }
+}
+
+/// Plans the state constraints of `stage`, in order.
+pub(crate) fn plan_stage(program: &Program, stage: &Stage) -> Result<Plan, Unsupported> {
+    let mut planner = Planner::new(program, true, &stage.span);
+    let mut bound = planner.bound();
+    let mut body = Step::Const(true);
+    for &constraint in &stage.state_constraints {
+        let step = planner.prop(constraint, &mut bound)?;
+        body = Step::And(Box::new(body), Box::new(step));
+    }
+
+    let mut outputs = Vec::new();
+    for (name, &next) in &stage.next_var_map {
+        match program.terms.get(next) {
+            Term::Var(v) if bound.contains(v) && !planner.globals.contains_key(v) => {
+                outputs.push((name.clone(), planner.slot(*v)));
+            }
+            _ => {
+                return planner.unsupported(format!(
+                    "`next({})` is not determined by the state constraints of stage `{}`",
+                    name, stage.name
+                ));
+            }
+        }
+    }
+    outputs.sort();
+    Ok(planner.finish(0, outputs, body))
+}
+
+/// Plans `clause` as a test: its arguments are the first slots, matched
+/// against the head before the body runs.
+pub(crate) fn plan_clause(program: &Program, clause: &Clause) -> Result<Plan, Unsupported> {
+    let mut planner = Planner::new(program, false, &clause.span);
+    let mut bound = planner.bound();
+    // Reserve the parameter slots for variables no term mentions.
+    let params: Vec<usize> = (0..clause.head_args.len())
+        .map(|i| {
+            let slot = planner.vars.len();
+            planner.vars.push(VarId::new_raw(u32::MAX - i as u32));
+            slot
+        })
+        .collect();
+
+    let mut body = Step::Const(true);
+    for (&arg, slot) in clause.head_args.iter().zip(params) {
+        let pattern = planner.pattern(arg, &mut bound);
+        body = Step::And(Box::new(body), Box::new(Step::Match(Expr::Slot(slot), pattern)));
+    }
+    let step = planner.prop(clause.body, &mut bound)?;
+    body = Step::And(Box::new(body), Box::new(step));
+    Ok(planner.finish(clause.head_args.len(), Vec::new(), body))
+}
+
+/// Plans the condition of a draw directive, and the draw terms it leaves
+/// ground.
+pub(crate) fn plan_draw(program: &Program, directive: &DrawDirective, span: &SourceSpan) -> Result<(Plan, Vec<Expr>), Unsupported> {
+    let mut planner = Planner::new(program, true, span);
+    let mut bound = planner.bound();
+    let body = planner.prop(directive.condition, &mut bound)?;
+    let mut draws = Vec::new();
+    for &draw in &directive.draws {
+        if !planner.is_known(draw, &bound) {
+            return planner.unsupported("this draw command uses variables its condition doesn't determine".to_string());
+        }
+        draws.push(planner.expr(draw));
+    }
+    Ok((planner.finish(0, Vec::new(), body), draws))
+}
+
+type Env = Vec<Option<Value>>;
+type Eval = Box<dyn Fn(&Env) -> Option<Value>>;
+/// Binds the unknowns of a pattern against a value, or checks it matches.
+type Match = Box<dyn Fn(&Value, &mut Env) -> Option<bool>>;
+/// Runs one step: `Some(true)` if it holds, `Some(false)` if it fails, and
+/// `None` if the stage should be left to the solver after all.
+type Exec = Box<dyn Fn(&mut Env) -> Option<bool>>;
+
+fn eval(expr: &Expr) -> Eval {
+    match expr.clone() {
+        Expr::Slot(slot) => Box::new(move |env| env[slot].clone()),
+        Expr::Int(i) => Box::new(move |_| Some(Value::Int(i))),
+        Expr::Real(f) => Box::new(move |_| Some(Value::Real(f))),
+        Expr::Atom(sym) => Box::new(move |_| Some(Value::Atom(sym))),
+        Expr::App(sym, args) => {
+            let args: Vec<Eval> = args.iter().map(eval).collect();
+            Box::new(move |env| {
+                let values = args.iter().map(|a| a(env)).collect::<Option<_>>()?;
+                Some(Value::App(sym, values))
+            })
+        }
+    }
+}
+
+fn number(expr: &Expr, family: Family) -> impl Fn(&Env) -> Option<Ratio> + 'static {
+    let eval = eval(expr);
+    move |env| family.ratio(&eval(env)?)
+}
+
+fn matcher(pattern: &Pattern) -> Match {
+    match pattern {
+        Pattern::Check(expr) => {
+            let eval = eval(expr);
+            Box::new(move |value, env| Some(eval(env)? == *value))
+        }
+        &Pattern::Bind(slot) => Box::new(move |value, env| {
+            env[slot] = Some(value.clone());
+            Some(true)
+        }),
+        Pattern::App(sym, args) => {
+            let (sym, arity) = (*sym, args.len());
+            let args: Vec<Match> = args.iter().map(matcher).collect();
+            Box::new(move |value, env| match value {
+                Value::App(s, values) if *s == sym && values.len() == arity => {
+                    for (arg, value) in args.iter().zip(values) {
+                        if !arg(value, env)? {
+                            return Some(false);
+                        }
+                    }
+                    Some(true)
+                }
+                _ => Some(false),
+            })
+        }
+    }
+}
+
+/// Turns a plan's steps into closures. Calls of user relations would need
+/// the solver's facts and rules, so plans with them are not compiled.
+fn exec(step: &Step) -> Option<Exec> {
+    Some(match step {
+        &Step::Const(holds) => Box::new(move |_| Some(holds)),
+        Step::Match(value, pattern) => {
+            let (value, pattern) = (eval(value), matcher(pattern));
+            Box::new(move |env| pattern(&value(env)?, env))
+        }
+        &Step::Compute { family, op, ref lhs, ref rhs, out } => {
+            let (lhs, rhs) = (number(lhs, family), number(rhs, family));
+            Box::new(move |env| {
+                env[out] = Some(family.value(op.apply(family, lhs(env)?, rhs(env)?)?)?);
+                Some(true)
+            })
+        }
+        &Step::Check { family, op, ref lhs, ref rhs, ref result } => {
+            let (lhs, rhs, result) = (number(lhs, family), number(rhs, family), number(result, family));
+            Box::new(move |env| Some(op.apply(family, lhs(env)?, rhs(env)?)?.cmp(result(env)?)?.is_eq()))
+        }
+        &Step::Convert { family, ref value, out } => {
+            let value = number(value, family);
+            Box::new(move |env| {
+                env[out] = Some(family.value(value(env)?)?);
+                Some(true)
+            })
+        }
+        &Step::Compare { family, cmp, ref lhs, ref rhs } => {
+            let (lhs, rhs) = (number(lhs, family), number(rhs, family));
+            Box::new(move |env| Some(cmp.test(lhs(env)?.cmp(rhs(env)?)?)))
+        }
+        Step::And(lhs, rhs) => {
+            let (first, second) = (exec(lhs)?, exec(rhs)?);
+            Box::new(move |env| if first(env)? { second(env) } else { Some(false) })
+        }
+        Step::Not(inner) => {
+            let inner = exec(inner)?;
+            Box::new(move |env| Some(!inner(&mut env.clone())?))
+        }
+        Step::Cond(guard, then, otherwise) => {
+            let (guard, then, otherwise) = (exec(guard)?, exec(then)?, exec(otherwise)?);
+            Box::new(move |env| {
+                let before = env.clone();
+                if guard(env)? {
+                    then(env)
+                } else {
+                    *env = before;
+                    otherwise(env)
+                }
+            })
+        }
+        Step::Call { .. } => return None,
+    })
+}
+
+/// The compiled state constraints of one stage.
+pub struct StepFn {
+    inputs: Vec<Input>,
+    outputs: Vec<(String, usize)>,
+    slots: usize,
+    body: Exec,
+}
+
+impl StepFn {
+    /// Computes the next value of each state variable the stage constrains,
+    /// reading the current state from `var_map` and the facts of `program`.
+    /// Returns `None` whenever the solver should run the stage instead.
+    pub fn run(&self, program: &Program, var_map: &HashMap<String, TermId>) -> Option<Vec<(String, Value)>> {
+        let known = fact_values(program)?;
+        let mut env: Env = vec![None; self.slots];
+        for input in &self.inputs {
+            let current = input.state_var.as_ref().and_then(|name| var_map.get(name));
+            let value = current
+                .and_then(|&term| Value::from_term(program, term))
+                .or_else(|| known.get(&input.var).cloned())?;
+            env[input.slot] = Some(value);
+        }
+
+        if !(self.body)(&mut env)? {
+            return None;
+        }
+        self.outputs
+            .iter()
+            .map(|(name, slot)| Some((name.clone(), env[*slot].clone()?)))
+            .collect()
+    }
+}
+
+/// The values the facts give variables, as long as the facts are nothing
+/// but such equations and tuples of user relations. Anything else could make
+/// the solver reject the state, so the caller should not step forward.
+pub(crate) fn fact_values(program: &Program) -> Option<HashMap<VarId, Value>> {
+    let mut known = HashMap::new();
+    for &fact in &program.facts {
+        match program.props.get(fact) {
+            Prop::True => {}
+            Prop::App { rel, .. } if program.rels.get(*rel).kind == RelKind::User => {}
+            Prop::Eq(lhs, rhs) => {
+                let (var, value) = match (program.terms.get(*lhs), program.terms.get(*rhs)) {
+                    (Term::Var(v), _) => (*v, Value::from_term(program, *rhs)?),
+                    (_, Term::Var(v)) => (*v, Value::from_term(program, *lhs)?),
+                    _ => return None,
+                };
+                if known.insert(var, value.clone()).is_some_and(|old| old != value) {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(known)
+}
+
+/// Compiles the state constraints of `stage`, or returns `None` if they
+/// can't be run forward and the stage has to go through the solver.
+pub fn compile_stage(program: &Program, stage: &Stage) -> Option<StepFn> {
+    let plan = plan_stage(program, stage).ok()?;
+    Some(StepFn {
+        body: exec(&plan.body)?,
+        slots: plan.vars.len(),
+        inputs: plan.inputs,
+        outputs: plan.outputs,
+    })
+}
@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod tests {
+    use crate::frontend::{Frontend, FrontendOptions};
+    use crate::solver::test_support::{load, load_with};
+
+    const SOLVER_ONLY: FrontendOptions = FrontendOptions { use_forward: false, ..FrontendOptions::DEFAULT };
+
+    fn stage_index(frontend: &Frontend, name: &str) -> usize {
+        frontend.program.stages.iter().position(|s| s.name == name).unwrap()
+    }
+
+    /// Runs `stages` for `frames` frames with and without the forward path,
+    /// checking both see the same state after every stage.
+    fn assert_same_as_solver(source: &str, stages: &[&str], frames: usize) {
+        let mut forward = load(source);
+        let mut solver = load_with(source, SOLVER_ONLY);
+        for frame in 0..frames {
+            for stage in stages {
+                let forward_result = forward.run_stage_by_name(stage);
+                let solver_result = solver.run_stage_by_name(stage);
+                assert_eq!(forward_result, solver_result, "stage {} in frame {}", stage, frame);
+                assert_eq!(forward.state_vars(), solver.state_vars(), "stage {} in frame {}", stage, frame);
+            }
+        }
+    }
+
+    const COUNTER: &str = r#"Begin Facts:
+    StateVar Count
+    StateVar Step
+    Count = 7
+    Step = -3
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Tick:
+Begin State Constraints:
+    int_div(Count, 2, Half)
+    int_add(Half, Step, next(Count))
+    when int_lt(Count, 0): next(Step) = Step * -1
+    otherwise: preserve(Step)
+End State Constraints
+End Stage Tick
+"#;
+
+    #[test]
+    fn test_runner_physics_compiles_forward() {
+        let source = std::fs::read_to_string("sample/runner.l").unwrap();
+        let frontend = load(&source);
+
+        // Control consults user rules, which only the solver can run.
+        assert!(frontend.is_stage_compiled(stage_index(&frontend, "Physics")));
+        assert!(!frontend.is_stage_compiled(stage_index(&frontend, "Control")));
+    }
+
+    #[test]
+    fn test_forward_steps_match_the_solver() {
+        assert_same_as_solver(COUNTER, &["Tick"], 8);
+
+        let runner = std::fs::read_to_string("sample/runner.l").unwrap();
+        let mut forward = load(&runner);
+        let mut solver = load_with(&runner, SOLVER_ONLY);
+        for frame in 0..12 {
+            for frontend in [&mut forward, &mut solver] {
+                frontend.clear_facts_by_relation("key_pressed");
+                if frame % 5 == 0 {
+                    frontend.add_fact("key_pressed(space)").unwrap();
+                }
+                frontend.run_stage_by_name("Control").unwrap();
+                frontend.run_stage_by_name("Physics").unwrap();
+            }
+            assert_eq!(forward.state_vars(), solver.state_vars(), "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn test_stages_that_cannot_run_forward() {
+        let source = r#"Begin Facts:
+    StateVar X
+    X = 1
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Open:
+Begin State Constraints:
+    int_lt(next(X), 5)
+End State Constraints
+End Stage Open
+
+Begin Stage Choice:
+Begin State Constraints:
+    or(next(X) = 1, next(X) = 2)
+End State Constraints
+End Stage Choice
+
+Begin Stage Scale:
+Begin State Constraints:
+    int_mul(next(X), 2, X)
+End State Constraints
+End Stage Scale
+"#;
+        let frontend = load(source);
+        for stage in ["Open", "Choice", "Scale"] {
+            assert!(!frontend.is_stage_compiled(stage_index(&frontend, stage)), "{}", stage);
+        }
+    }
+
+    #[test]
+    fn test_failing_forward_step_reports_the_solver_error() {
+        let source = r#"Begin Facts:
+    StateVar X
+    X = 1
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Grow:
+Begin State Constraints:
+    next(X) = X + 1 & int_lt(next(X), 3)
+End State Constraints
+End Stage Grow
+"#;
+        let mut frontend = load(source);
+        assert!(frontend.is_stage_compiled(0));
+        frontend.run_stage(0).unwrap();
+        assert_eq!(frontend.get_state_var("X").unwrap(), "2");
+
+        let err = frontend.run_stage(0).unwrap_err();
+        assert!(err.contains("no solutions found"), "{}", err);
+        assert_eq!(frontend.get_state_var("X").unwrap(), "2");
+    }
+}
@@ -0,0 +1,212 @@
+//! Indexes of the facts and clauses a call could match, so that the solver
+//! looks at those rather than at every fact and rule in the program.
+//!
+//! Each fact and clause is filed under its relation and, for every argument
+//! position, under the constant or functor it has there. One whose argument
+//! is a variable matches any call, so it is kept aside for that position. A
+//! call looks up each of its bound arguments and keeps the smallest set of
+//! candidates, which come back in program order.
+//!
+//! The index lives in [`Program`] and stays current as long as facts and
+//! rules are added and removed through the methods here rather than by
+//! editing `facts` and `global_rules` directly. Those methods also tell the
+//! Datalog results when a fact they were derived from changes.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::solver::ir::{Clause, Program, Prop, PropId, RelId, SymbolId, Term, TermId};
+
+/// What a term must be to unify with a non-variable argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArgKey {
+    Atom(SymbolId),
+    Int(i32),
+    Float(u32),
+    Functor(SymbolId, usize),
+}
+
+impl ArgKey {
+    /// The key of `term`, or `None` for a variable.
+    pub fn of(term: &Term) -> Option<Self> {
+        match term {
+            Term::Var(_) => None,
+            Term::Atom(sym) => Some(ArgKey::Atom(*sym)),
+            Term::Int(i) => Some(ArgKey::Int(*i)),
+            // `0.0` and `-0.0` unify, so they share a key.
+            Term::Float(f) => Some(ArgKey::Float(if *f == 0.0 { 0 } else { f.to_bits() })),
+            Term::App { sym, args } => Some(ArgKey::Functor(*sym, args.len())),
+        }
+    }
+}
+
+/// The facts or clauses of one relation, each paired with its place in
+/// program order.
+#[derive(Debug, Clone)]
+struct Entries<T> {
+    all: Vec<(usize, T)>,
+    keyed: HashMap<(usize, ArgKey), Vec<(usize, T)>>,
+    /// For each argument position, the entries with a variable there.
+    open: Vec<Vec<(usize, T)>>,
+}
+
+impl<T> Default for Entries<T> {
+    fn default() -> Self {
+        Self { all: Vec::new(), keyed: HashMap::new(), open: Vec::new() }
+    }
+}
+
+impl<T: Copy> Entries<T> {
+    fn insert(&mut self, order: usize, item: T, keys: &[Option<ArgKey>]) {
+        self.all.push((order, item));
+        for (position, key) in keys.iter().enumerate() {
+            match key {
+                Some(key) => self.keyed.entry((position, *key)).or_default().push((order, item)),
+                None => {
+                    if self.open.len() <= position {
+                        self.open.resize_with(position + 1, Vec::new);
+                    }
+                    self.open[position].push((order, item));
+                }
+            }
+        }
+    }
+
+    /// Drops the entries from `order` on, which are always the last ones.
+    fn truncate(&mut self, order: usize) {
+        let lists = std::iter::once(&mut self.all).chain(self.keyed.values_mut()).chain(&mut self.open);
+        for list in lists {
+            while list.last().is_some_and(|&(o, _)| o >= order) {
+                list.pop();
+            }
+        }
+        self.keyed.retain(|_, list| !list.is_empty());
+    }
+
+    fn candidates(&self, keys: &[Option<ArgKey>]) -> Vec<T> {
+        let no_entries = Vec::new();
+        let best = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(position, key)| {
+                let keyed = self.keyed.get(&(position, (*key)?)).unwrap_or(&no_entries);
+                let open = self.open.get(position).unwrap_or(&no_entries);
+                Some((keyed, open))
+            })
+            .min_by_key(|(keyed, open)| keyed.len() + open.len());
+
+        let Some((keyed, open)) = best else {
+            return self.all.iter().map(|&(_, item)| item).collect();
+        };
+        let mut merged = Vec::with_capacity(keyed.len() + open.len());
+        let (mut i, mut j) = (0, 0);
+        while i < keyed.len() || j < open.len() {
+            if j == open.len() || (i < keyed.len() && keyed[i].0 < open[j].0) {
+                merged.push(keyed[i].1);
+                i += 1;
+            } else {
+                merged.push(open[j].1);
+                j += 1;
+            }
+        }
+        merged
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    facts: HashMap<RelId, Entries<PropId>>,
+    /// Clauses are identified by their position in `global_rules`.
+    clauses: HashMap<RelId, Entries<usize>>,
+    /// The place in program order of the next fact added.
+    next_fact: usize,
+}
+
+impl Program {
+    fn arg_keys(&self, args: &[TermId]) -> Vec<Option<ArgKey>> {
+        args.iter().map(|&arg| ArgKey::of(self.terms.get(arg))).collect()
+    }
+
+    fn index_fact(&mut self, fact: PropId) {
+        if let Prop::App { rel, args } = self.props.get(fact) {
+            let keys = self.arg_keys(args);
+            let order = self.index.next_fact;
+            self.index.facts.entry(*rel).or_default().insert(order, fact, &keys);
+            self.index.next_fact += 1;
+        }
+    }
+
+    fn index_clause(&mut self, position: usize) {
+        let clause = &self.global_rules[position];
+        let keys = self.arg_keys(&clause.head_args);
+        self.index.clauses.entry(clause.head_rel).or_default().insert(position, position, &keys);
+    }
+
+    pub fn add_fact(&mut self, fact: PropId) {
+        self.facts.push(fact);
+        self.index_fact(fact);
+        if let Prop::App { rel, .. } = self.props.get(fact) {
+            self.datalog.fact_added(*rel, fact);
+        }
+    }
+
+    /// Removes every fact of `rel`.
+    pub fn clear_facts(&mut self, rel: RelId) {
+        let props = &self.props;
+        self.facts.retain(|&fact| !matches!(props.get(fact), Prop::App { rel: r, .. } if *r == rel));
+        self.index.facts.remove(&rel);
+        self.datalog.facts_cleared(rel);
+    }
+
+    /// Removes the given facts.
+    pub fn remove_facts(&mut self, facts: &HashSet<PropId>) {
+        self.facts.retain(|fact| !facts.contains(fact));
+        self.reindex_facts();
+    }
+
+    pub fn add_rule(&mut self, clause: Clause) {
+        self.global_rules.push(clause);
+        self.index_clause(self.global_rules.len() - 1);
+    }
+
+    /// Keeps only the first `len` global rules.
+    pub fn truncate_rules(&mut self, len: usize) {
+        if len >= self.global_rules.len() {
+            return;
+        }
+        for (rel, entries) in self.index.clauses.iter_mut() {
+            if self.global_rules[len..].iter().any(|c| c.head_rel == *rel) {
+                entries.truncate(len);
+            }
+        }
+        self.global_rules.truncate(len);
+    }
+
+    /// Rebuilds the index after `facts` or `global_rules` were replaced.
+    pub fn reindex(&mut self) {
+        self.reindex_facts();
+        self.index.clauses.clear();
+        for position in 0..self.global_rules.len() {
+            self.index_clause(position);
+        }
+    }
+
+    fn reindex_facts(&mut self) {
+        self.index.facts.clear();
+        self.index.next_fact = 0;
+        for fact in self.facts.clone() {
+            self.index_fact(fact);
+        }
+    }
+
+    /// The facts of `rel` that may unify with a call whose arguments have
+    /// `keys`, in program order.
+    pub fn candidate_facts(&self, rel: RelId, keys: &[Option<ArgKey>]) -> Vec<PropId> {
+        self.index.facts.get(&rel).map(|entries| entries.candidates(keys)).unwrap_or_default()
+    }
+
+    /// The positions in `global_rules` of the clauses of `rel` whose heads
+    /// may unify with a call whose arguments have `keys`, in program order.
+    pub fn candidate_clauses(&self, rel: RelId, keys: &[Option<ArgKey>]) -> Vec<usize> {
+        self.index.clauses.get(&rel).map(|entries| entries.candidates(keys)).unwrap_or_default()
+    }
+}
@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests {
+    use crate::frontend::Frontend;
+    use crate::solver::index::ArgKey;
+    use crate::solver::ir::{RelId, Term};
+    use crate::solver::test_support::load;
+
+    fn rel(frontend: &Frontend, name: &str) -> RelId {
+        frontend.program.rels.iter().find(|(_, r)| r.name == name).map(|(id, _)| id).unwrap()
+    }
+
+    fn atom(frontend: &mut Frontend, name: &str) -> Option<ArgKey> {
+        let sym = frontend.program.symbols.intern(name.to_string());
+        ArgKey::of(&Term::Atom(sym))
+    }
+
+    /// A `size` by `size` grid of `tile(X, Y, Kind)` facts.
+    fn level(size: i32) -> String {
+        let mut source = String::from("Begin Facts:\n");
+        for x in 0..size {
+            for y in 0..size {
+                let kind = if (x + y) % 7 == 0 { "wall" } else { "floor" };
+                source.push_str(&format!("    tile({}, {}, {})\n", x, y, kind));
+            }
+        }
+        source.push_str("End Facts\n\nBegin Global:\n");
+        source.push_str("    Rule Blocked:\n    tile(X, Y, wall)\n    ----------------\n    blocked(X, Y)\n");
+        source.push_str("End Global\n");
+        source
+    }
+
+    #[test]
+    fn test_bound_arguments_narrow_the_candidate_facts() {
+        let mut frontend = load(&level(40));
+        let tile = rel(&frontend, "tile");
+        let wall = atom(&mut frontend, "wall");
+        let program = &frontend.program;
+
+        assert_eq!(program.candidate_facts(tile, &[None, None, None]).len(), 1600);
+        assert_eq!(program.candidate_facts(tile, &[Some(ArgKey::Int(3)), None, None]).len(), 40);
+        assert_eq!(program.candidate_facts(tile, &[None, None, wall]).len(), 228);
+        // The most selective bound argument is the one looked up.
+        let keys = [Some(ArgKey::Int(3)), Some(ArgKey::Int(4)), wall];
+        assert_eq!(program.candidate_facts(tile, &keys).len(), 40);
+        assert!(program.candidate_facts(tile, &[Some(ArgKey::Int(99)), None, None]).is_empty());
+    }
+
+    #[test]
+    fn test_queries_over_large_levels() {
+        let mut frontend = load(&level(60));
+        assert_eq!(frontend.query_batch("tile(12, 31, K)", 5).unwrap(), vec!["K = floor"]);
+        assert_eq!(frontend.query_batch("blocked(3, 4)", 5).unwrap(), vec!["yes"]);
+        assert_eq!(frontend.query_batch("blocked(3, 5)", 5).unwrap(), Vec::<String>::new());
+        assert_eq!(frontend.query_batch("tile(X, 0, wall)", 20).unwrap().len(), 9);
+    }
+
+    #[test]
+    fn test_clauses_with_variable_heads_match_any_call() {
+        let source = r#"Begin Facts:
+End Facts
+
+Begin Global:
+    Rule First:
+    true
+    --------
+    kind(a, first)
+
+    Rule Any:
+    true
+    --------
+    kind(X, any)
+
+    Rule Second:
+    true
+    --------
+    kind(b, second)
+End Global
+"#;
+        let mut frontend = load(source);
+        let kind = rel(&frontend, "kind");
+        let a = atom(&mut frontend, "a");
+        let names = |keys: &[Option<ArgKey>]| -> Vec<String> {
+            let positions = frontend.program.candidate_clauses(kind, keys);
+            positions.iter().map(|&p| frontend.program.global_rules[p].name.clone()).collect()
+        };
+        assert_eq!(names(&[a, None]), vec!["First", "Any"]);
+        assert_eq!(names(&[None, None]), vec!["First", "Any", "Second"]);
+        assert_eq!(frontend.query_batch("kind(b, K)", 5).unwrap(), vec!["K = any", "K = second"]);
+    }
+
+    #[test]
+    fn test_host_facts_are_indexed() {
+        let mut frontend = load(&level(10));
+        frontend.add_fact("tile(99, 99, lava)").unwrap();
+        assert_eq!(frontend.query_batch("tile(99, 99, K)", 5).unwrap(), vec!["K = lava"]);
+
+        frontend.clear_facts_by_relation("tile");
+        let tile = rel(&frontend, "tile");
+        assert!(frontend.program.candidate_facts(tile, &[None, None, None]).is_empty());
+        assert_eq!(frontend.query_batch("tile(X, Y, K)", 5).unwrap(), Vec::<String>::new());
+
+        frontend.add_fact("tile(1, 2, floor)").unwrap();
+        assert_eq!(frontend.query_batch("tile(1, Y, floor)", 5).unwrap(), vec!["Y = 2"]);
+    }
+}
@@ -2,11 +2,29 @@ use std::collections::HashMap;
 use std::hash::Hash;
 use std::marker::PhantomData;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+use crate::ast::{Mode, SourceSpan};
+use crate::solver::datalog::Materialized;
+use crate::solver::index::Index;
+
+#[derive(Debug)]
 pub struct Id<T>(u32, PhantomData<T>);
 
 impl<T> Copy for Id<T> {}
 
+impl<T> PartialEq for Id<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T> Eq for Id<T> {}
+
+impl<T> Hash for Id<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
 impl<T> Clone for Id<T> {
     fn clone(&self) -> Self {
         *self
@@ -166,7 +184,7 @@ impl Term {
     }
 }
 
-fn float_to_rational(f: f32) -> (i64, i64) {
+pub(crate) fn float_to_rational(f: f32) -> (i64, i64) {
     const PRECISION: i64 = 1_000_000;
     let num = (f * PRECISION as f32).round() as i64;
     fn gcd(a: i64, b: i64) -> i64 {
@@ -202,12 +220,29 @@ pub struct RelInfo {
     pub kind: RelKind,
 }
 
+/// A `Cost` declaration: resolving a call whose arguments match `args`
+/// adds `cost`, an integer or one of the pattern's variables, to the state.
+#[derive(Debug, Clone)]
+pub struct CostRule {
+    pub args: Vec<TermId>,
+    pub cost: TermId,
+}
+
+/// A `Mode` declaration, resolved to the relation it describes.
+#[derive(Debug, Clone)]
+pub struct RelMode {
+    pub rel: RelId,
+    pub modes: Vec<Mode>,
+    pub span: SourceSpan,
+}
+
 #[derive(Debug, Clone)]
 pub struct Clause {
     pub name: String,
     pub head_rel: RelId,
     pub head_args: Vec<TermId>,
     pub body: PropId,
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone)]
@@ -223,6 +258,7 @@ pub struct Stage {
     pub state_constraints: Vec<PropId>,
     pub next_var_map: std::collections::HashMap<String, TermId>,
     pub draw_directives: Vec<DrawDirective>,
+    pub span: SourceSpan,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -237,4 +273,20 @@ pub struct Program {
     pub facts: Vec<PropId>,
     pub global_rules: Vec<Clause>,
     pub stages: Vec<Stage>,
+    /// Source locations of props lowered from the AST, for diagnostics.
+    pub prop_spans: HashMap<PropId, SourceSpan>,
+    /// Relations given a `Relation` signature. The type checker enforces
+    /// their arity, and they may be filled in at runtime by the host.
+    pub declared_rels: std::collections::HashSet<RelId>,
+    pub rel_modes: Vec<RelMode>,
+    /// Relations declared with `Table`, whose answers the solver memoizes.
+    pub tabled_rels: std::collections::HashSet<RelId>,
+    /// The `Cost` declarations of each relation, in source order.
+    pub costs: HashMap<RelId, Vec<CostRule>>,
+    /// The relations computed bottom-up and the facts derived for them; see
+    /// [`crate::solver::datalog`].
+    pub datalog: Materialized,
+    /// Which facts and global rules each call could match; see
+    /// [`crate::solver::index`].
+    pub index: Index,
 }
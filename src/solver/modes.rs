@@ -0,0 +1,297 @@
+//! Mode checking: which arguments are ground when each relation is called.
+//!
+//! Relations run in any direction, but not every direction is cheap. A
+//! `Mode length(+, -)` declaration says `length` expects its first argument
+//! ground and determines the second. This pass follows each rule body and
+//! each stage's state constraints left to right, tracking the variables that
+//! are ground so far, and warns about calls that match none of a relation's
+//! declared modes, rules that don't determine their declared outputs, and
+//! `next(X)` values the state constraints leave open.
+//!
+//! Relations without declarations are assumed to ground all of their
+//! arguments, as tables of facts do, so undeclared code is never flagged.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{format_modes, Mode, SourceSpan};
+use crate::diagnostic::Diagnostic;
+use crate::solver::ir::{Clause, Program, Prop, PropId, RelId, RelKind, RelMode, Stage, Term, TermId, VarId};
+
+type Ground = HashSet<VarId>;
+
+struct ModeChecker<'a> {
+    program: &'a Program,
+    modes: HashMap<RelId, Vec<&'a RelMode>>,
+    /// Variables bound by the facts, ground everywhere.
+    global: Ground,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> ModeChecker<'a> {
+    fn warn(&mut self, message: String, span: &SourceSpan) {
+        // A rule checked in several modes may make the same complaint.
+        if !self.diagnostics.iter().any(|d| d.message == message && d.span == *span) {
+            self.diagnostics.push(Diagnostic::warning(message, span.clone()));
+        }
+    }
+
+    /// The declared modes of `rel` that fit a use with `arity` arguments at
+    /// `span`, reporting the declarations that don't.
+    fn modes_for(&mut self, rel: RelId, arity: usize, span: &SourceSpan) -> Vec<&'a RelMode> {
+        let decls = self.modes.get(&rel).cloned().unwrap_or_default();
+        let name = &self.program.rels.get(rel).name;
+        for decl in decls.iter().filter(|d| d.modes.len() != arity) {
+            // Only the first misfit use of each declaration is worth a warning.
+            if self.diagnostics.iter().any(|d| d.span == decl.span) {
+                continue;
+            }
+            self.warn(
+                format!(
+                    "mode declaration for `{}` has {} positions but `{}` is used with {} arguments at {}",
+                    name,
+                    decl.modes.len(),
+                    name,
+                    arity,
+                    span
+                ),
+                &decl.span,
+            );
+        }
+        decls.into_iter().filter(|d| d.modes.len() == arity).collect()
+    }
+
+    fn term_vars(&self, term: TermId, out: &mut Vec<VarId>) {
+        match self.program.terms.get(term) {
+            Term::Var(v) => out.push(*v),
+            Term::App { args, .. } => args.iter().for_each(|&a| self.term_vars(a, out)),
+            _ => {}
+        }
+    }
+
+    fn is_ground(&self, term: TermId, ground: &Ground) -> bool {
+        let mut vars = Vec::new();
+        self.term_vars(term, &mut vars);
+        vars.iter().all(|v| ground.contains(v))
+    }
+
+    fn bind(&self, term: TermId, ground: &mut Ground) {
+        let mut vars = Vec::new();
+        self.term_vars(term, &mut vars);
+        ground.extend(vars);
+    }
+
+    fn visit(&mut self, prop: PropId, ground: &mut Ground, span: &SourceSpan) {
+        let span = self.program.prop_spans.get(&prop).unwrap_or(span);
+        match self.program.props.get(prop) {
+            Prop::True | Prop::False => {}
+            Prop::Eq(lhs, rhs) => {
+                if self.is_ground(*lhs, ground) {
+                    self.bind(*rhs, ground);
+                } else if self.is_ground(*rhs, ground) {
+                    self.bind(*lhs, ground);
+                }
+            }
+            Prop::And(lhs, rhs) => {
+                self.visit(*lhs, ground, span);
+                self.visit(*rhs, ground, span);
+            }
+            Prop::Or(lhs, rhs) => self.branches(*lhs, *rhs, ground, span),
+            Prop::Cond(guard, then, otherwise) => {
+                self.visit(*guard, ground, span);
+                self.branches(*then, *otherwise, ground, span);
+            }
+            Prop::Not(inner) => self.visit(*inner, &mut ground.clone(), span),
+            Prop::App { rel, args } => self.call(*rel, args, ground, span),
+        }
+    }
+
+    /// Only what both branches ground is ground afterwards.
+    fn branches(&mut self, lhs: PropId, rhs: PropId, ground: &mut Ground, span: &SourceSpan) {
+        let mut left = ground.clone();
+        self.visit(lhs, &mut left, span);
+        let mut right = ground.clone();
+        self.visit(rhs, &mut right, span);
+        *ground = left.intersection(&right).copied().collect();
+    }
+
+    fn call(&mut self, rel: RelId, args: &[TermId], ground: &mut Ground, span: &SourceSpan) {
+        let info = self.program.rels.get(rel);
+        if info.kind != RelKind::User {
+            // Arithmetic determines any one argument from the other two, and
+            // an equation either side from the other. Comparisons bind nothing.
+            let known = args.iter().filter(|&&a| self.is_ground(a, ground)).count();
+            let is_eq = info.name.ends_with("_eq") && args.len() == 2;
+            if (args.len() == 3 && known >= 2) || (is_eq && known >= 1) {
+                args.iter().for_each(|&a| self.bind(a, ground));
+            }
+            return;
+        }
+
+        let decls = self.modes_for(rel, args.len(), span);
+        if decls.is_empty() {
+            args.iter().for_each(|&a| self.bind(a, ground));
+            return;
+        }
+        let pattern: Vec<Mode> = args
+            .iter()
+            .map(|&a| if self.is_ground(a, ground) { Mode::In } else { Mode::Out })
+            .collect();
+        let accepts = |decl: &&RelMode| {
+            decl.modes
+                .iter()
+                .zip(&pattern)
+                .all(|(declared, actual)| *declared != Mode::In || *actual == Mode::In)
+        };
+        if !decls.iter().any(accepts) {
+            let declared: Vec<String> = decls.iter().map(|d| format_modes(&info.name, &d.modes)).collect();
+            self.warn(
+                format!(
+                    "`{}` is called as {} here, which is not a declared mode ({})",
+                    info.name,
+                    format_modes(&info.name, &pattern),
+                    declared.join(", ")
+                ),
+                span,
+            );
+        }
+        // Assume the call grounds everything either way, so that one bad
+        // call doesn't cascade into warnings about the calls after it.
+        args.iter().for_each(|&a| self.bind(a, ground));
+    }
+
+    fn clause(&mut self, clause: &Clause) {
+        let decls = self.modes_for(clause.head_rel, clause.head_args.len(), &clause.span);
+        if decls.is_empty() {
+            // Assume callers supply every argument.
+            let mut ground = self.global.clone();
+            clause.head_args.iter().for_each(|&a| self.bind(a, &mut ground));
+            self.visit(clause.body, &mut ground, &clause.span);
+            return;
+        }
+
+        let name = &self.program.rels.get(clause.head_rel).name;
+        for decl in decls {
+            let mut ground = self.global.clone();
+            for (&arg, mode) in clause.head_args.iter().zip(&decl.modes) {
+                if *mode == Mode::In {
+                    self.bind(arg, &mut ground);
+                }
+            }
+            self.visit(clause.body, &mut ground, &clause.span);
+
+            for (i, (&arg, mode)) in clause.head_args.iter().zip(&decl.modes).enumerate() {
+                if *mode == Mode::Out && !self.is_ground(arg, &ground) {
+                    self.warn(
+                        format!(
+                            "rule `{}` does not determine argument {} of {}",
+                            clause.name,
+                            i + 1,
+                            format_modes(name, &decl.modes)
+                        ),
+                        &clause.span,
+                    );
+                }
+            }
+        }
+    }
+
+    fn mentions(&self, prop: PropId, var: TermId) -> bool {
+        let in_terms = |terms: &[TermId]| terms.iter().any(|&t| t == var || self.term_mentions(t, var));
+        match self.program.props.get(prop) {
+            Prop::True | Prop::False => false,
+            Prop::Eq(lhs, rhs) => in_terms(&[*lhs, *rhs]),
+            Prop::And(lhs, rhs) | Prop::Or(lhs, rhs) => self.mentions(*lhs, var) || self.mentions(*rhs, var),
+            Prop::Not(inner) => self.mentions(*inner, var),
+            Prop::Cond(guard, then, otherwise) => {
+                self.mentions(*guard, var) || self.mentions(*then, var) || self.mentions(*otherwise, var)
+            }
+            Prop::App { args, .. } => in_terms(args),
+        }
+    }
+
+    fn term_mentions(&self, term: TermId, var: TermId) -> bool {
+        match self.program.terms.get(term) {
+            Term::App { args, .. } => args.iter().any(|&a| a == var || self.term_mentions(a, var)),
+            _ => false,
+        }
+    }
+
+    fn stage(&mut self, stage: &Stage) {
+        stage.rules.iter().for_each(|clause| self.clause(clause));
+
+        let mut ground = self.global.clone();
+        for &constraint in &stage.state_constraints {
+            self.visit(constraint, &mut ground, &stage.span);
+        }
+
+        let mut next_vars: Vec<(&String, &TermId)> = stage.next_var_map.iter().collect();
+        next_vars.sort_by_key(|(name, _)| *name);
+        for (name, &next) in next_vars {
+            if self.is_ground(next, &ground) {
+                continue;
+            }
+            let span = stage
+                .state_constraints
+                .iter()
+                .find(|&&c| self.mentions(c, next))
+                .and_then(|c| self.program.prop_spans.get(c))
+                .unwrap_or(&stage.span)
+                .clone();
+            self.warn(
+                format!(
+                    "`next({})` is not determined by the state constraints of stage `{}`",
+                    name, stage.name
+                ),
+                &span,
+            );
+        }
+    }
+}
+
+/// Checks rule bodies and stage constraints against the declared modes.
+pub fn check_modes(program: &Program) -> Vec<Diagnostic> {
+    let mut checker = ModeChecker {
+        program,
+        modes: HashMap::new(),
+        global: HashSet::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for decl in &program.rel_modes {
+        checker.modes.entry(decl.rel).or_default().push(decl);
+    }
+
+    let mut fact_terms: Vec<TermId> = program.state_var_term_ids.values().copied().collect();
+    for &fact in &program.facts {
+        collect_prop_terms(program, fact, &mut fact_terms);
+        if let Prop::App { rel, args } = program.props.get(fact) {
+            let span = program.prop_spans.get(&fact).cloned().unwrap_or_default();
+            checker.modes_for(*rel, args.len(), &span);
+        }
+    }
+    let mut global = Ground::new();
+    fact_terms.into_iter().for_each(|t| checker.bind(t, &mut global));
+    checker.global = global;
+
+    program.global_rules.iter().for_each(|clause| checker.clause(clause));
+    program.stages.iter().for_each(|stage| checker.stage(stage));
+    checker.diagnostics
+}
+
+fn collect_prop_terms(program: &Program, prop: PropId, out: &mut Vec<TermId>) {
+    match program.props.get(prop) {
+        Prop::True | Prop::False => {}
+        Prop::Eq(lhs, rhs) => out.extend([*lhs, *rhs]),
+        Prop::And(lhs, rhs) | Prop::Or(lhs, rhs) => {
+            collect_prop_terms(program, *lhs, out);
+            collect_prop_terms(program, *rhs, out);
+        }
+        Prop::Not(inner) => collect_prop_terms(program, *inner, out),
+        Prop::Cond(guard, then, otherwise) => {
+            for p in [*guard, *then, *otherwise] {
+                collect_prop_terms(program, p, out);
+            }
+        }
+        Prop::App { args, .. } => out.extend(args),
+    }
+}
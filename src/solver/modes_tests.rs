@@ -0,0 +1,88 @@
+#[cfg(test)]
+mod tests {
+    use crate::frontend::Frontend;
+
+    const LENGTH: &str = r#"Mode length(+, -)
+
+Begin Facts:
+    list(cons(a, cons(b, nil)))
+End Facts
+
+Begin Global:
+    Rule LengthNil:
+    list(_)
+    -------
+    length(nil, 0)
+
+    Rule LengthCons:
+    length(Xs, N) & int_add(N, 1, N1)
+    ---------------------------------
+    length(cons(_, Xs), N1)
+"#;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let mut frontend = Frontend::new();
+        frontend.load(source).unwrap();
+        frontend.diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_declared_modes_accept_well_moded_rules() {
+        let source = format!("{}\n    Rule Sized:\n    list(L) & length(L, N)\n    ----------------------\n    sized(N)\nEnd Global\n", LENGTH);
+        assert_eq!(warnings(&source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_call_in_undeclared_mode() {
+        let source = format!("{}\n    Rule TwoLong:\n    length(L, 2) & list(L)\n    ----------------------\n    twoLong(yes)\nEnd Global\n", LENGTH);
+        assert_eq!(
+            warnings(&source),
+            vec!["19:5: warning: `length` is called as length(-, +) here, which is not a declared mode (length(+, -))"]
+        );
+    }
+
+    #[test]
+    fn test_rule_that_leaves_an_output_open() {
+        let source = "Mode double(+, -)\n\nBegin Facts:\n    n(2)\nEnd Facts\n\nBegin Global:\n    Rule Double:\n    n(X) & int_gt(Y, X)\n    -------------------\n    double(X, Y)\nEnd Global\n";
+        assert_eq!(
+            warnings(source),
+            vec!["8:5: warning: rule `Double` does not determine argument 2 of double(+, -)"]
+        );
+    }
+
+    #[test]
+    fn test_next_value_left_open_by_a_stage() {
+        let source = r#"Begin Facts:
+End Facts
+
+Begin Initial State:
+    StateVars Score, Bonus
+    Score = 0
+    Bonus = 1
+End Initial State
+
+Begin Global:
+End Global
+
+Begin Stage Tick:
+    Begin State Constraints:
+        preserve(Bonus)
+        when int_lt(Score, 10): next(Score) = Score + Bonus
+    End State Constraints
+End Stage Tick
+"#;
+        assert_eq!(
+            warnings(source),
+            vec!["16:9: warning: `next(Score)` is not determined by the state constraints of stage `Tick`"]
+        );
+    }
+
+    #[test]
+    fn test_mode_declaration_with_wrong_arity() {
+        let source = "Mode pos(+, -)\n\nBegin Facts:\n    pos(player, 1, 2)\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+        assert_eq!(
+            warnings(source),
+            vec!["1:1: warning: mode declaration for `pos` has 2 positions but `pos` is used with 3 arguments at 4:5"]
+        );
+    }
+}
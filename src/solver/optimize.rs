@@ -0,0 +1,327 @@
+//! Load-time simplification of a compiled program.
+//!
+//! Rule bodies, stage constraints and draw conditions are rewritten in three
+//! ways. Built-in relations over literals are evaluated, so that
+//! `int_add(1, 2, X)` becomes `X = 3` and `real_lt(0.5, 1.0)` becomes `true`.
+//! Calls to a relation defined by a single non-recursive rule are replaced by
+//! the rule's body. Connectives are then simplified around the `true`s and
+//! `false`s this leaves, dropping the branches of `cond` and `or` that can
+//! never hold, and rules whose bodies can never hold are removed. Every
+//! constraint folded away is one the solver doesn't hand to Z3 each frame.
+//!
+//! Relations declared with `Relation` are never inlined, since the host may
+//! add facts for them at runtime, and neither are tabled relations, those
+//! with a cost or those with facts or with rules local to a stage. The host
+//! can still add facts for an undeclared relation, so `Frontend::add_fact`
+//! runs the pass again over the rules as compiled when one of the relations
+//! it returns gains a fact. Queries still see every relation as written.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::solver::forward::{evaluate_builtin, Evaluated, Value};
+use crate::solver::ir::{Clause, Program, Prop, PropId, RelId, RelKind, Term, TermId, VarId};
+
+/// Simplifies `program` in place, returning the relations whose calls it
+/// inlined.
+pub fn optimize(program: &mut Program) -> HashSet<RelId> {
+    let inline = inlinable(program);
+    let inlined = inline.keys().copied().collect();
+    let state_terms = program.state_var_term_ids.values().copied().collect();
+    let mut optimizer = Optimizer {
+        program,
+        inline,
+        state_terms,
+    };
+
+    let rules = std::mem::take(&mut optimizer.program.global_rules);
+    optimizer.program.global_rules = optimizer.clauses(rules);
+    for i in 0..optimizer.program.stages.len() {
+        let rules = std::mem::take(&mut optimizer.program.stages[i].rules);
+        optimizer.program.stages[i].rules = optimizer.clauses(rules);
+
+        let constraints = optimizer.program.stages[i].state_constraints.clone();
+        let constraints = constraints.into_iter().map(|c| optimizer.prop(c)).collect();
+        optimizer.program.stages[i].state_constraints = constraints;
+
+        for j in 0..optimizer.program.stages[i].draw_directives.len() {
+            let condition = optimizer.program.stages[i].draw_directives[j].condition;
+            optimizer.program.stages[i].draw_directives[j].condition = optimizer.prop(condition);
+        }
+    }
+    optimizer.program.reindex();
+    inlined
+}
+
+/// The relations whose calls can be replaced by the body of their only
+/// clause.
+fn inlinable(program: &Program) -> HashMap<RelId, Clause> {
+    let stage_rules = program.stages.iter().flat_map(|s| &s.rules);
+    let mut clauses: HashMap<RelId, Vec<&Clause>> = HashMap::new();
+    for clause in program.global_rules.iter().chain(stage_rules.clone()) {
+        clauses.entry(clause.head_rel).or_default().push(clause);
+    }
+    let stage_rels: HashSet<RelId> = stage_rules.map(|c| c.head_rel).collect();
+    let fact_rels: HashSet<RelId> = program
+        .facts
+        .iter()
+        .filter_map(|&f| match program.props.get(f) {
+            Prop::App { rel, .. } => Some(*rel),
+            _ => None,
+        })
+        .collect();
+
+    let calls: HashMap<RelId, HashSet<RelId>> = clauses
+        .iter()
+        .map(|(&rel, defs)| {
+            let mut called = HashSet::new();
+            for clause in defs {
+                calls_in(program, clause.body, &mut called);
+            }
+            (rel, called)
+        })
+        .collect();
+
+    clauses
+        .into_iter()
+        .filter(|(rel, defs)| {
+            defs.len() == 1
+                && !stage_rels.contains(rel)
+                && !fact_rels.contains(rel)
+                && !program.declared_rels.contains(rel)
+                && !program.tabled_rels.contains(rel)
+                && !program.costs.contains_key(rel)
+                && !reaches(&calls, *rel, *rel)
+        })
+        .map(|(rel, defs)| (rel, defs[0].clone()))
+        .collect()
+}
+
+fn calls_in(program: &Program, prop: PropId, out: &mut HashSet<RelId>) {
+    match program.props.get(prop) {
+        Prop::True | Prop::False | Prop::Eq(..) => {}
+        Prop::And(a, b) | Prop::Or(a, b) => {
+            calls_in(program, *a, out);
+            calls_in(program, *b, out);
+        }
+        Prop::Not(p) => calls_in(program, *p, out),
+        Prop::Cond(c, a, b) => {
+            calls_in(program, *c, out);
+            calls_in(program, *a, out);
+            calls_in(program, *b, out);
+        }
+        Prop::App { rel, .. } => {
+            if program.rels.get(*rel).kind == RelKind::User {
+                out.insert(*rel);
+            }
+        }
+    }
+}
+
+/// Whether some chain of calls leads from `from` to `to`.
+fn reaches(calls: &HashMap<RelId, HashSet<RelId>>, from: RelId, to: RelId) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![from];
+    while let Some(rel) = stack.pop() {
+        for &next in calls.get(&rel).into_iter().flatten() {
+            if next == to {
+                return true;
+            }
+            if seen.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+    false
+}
+
+struct Optimizer<'a> {
+    program: &'a mut Program,
+    inline: HashMap<RelId, Clause>,
+    /// The terms standing for state variables, which rules share with the
+    /// facts rather than getting fresh copies of.
+    state_terms: HashSet<TermId>,
+}
+
+impl Optimizer<'_> {
+    fn clauses(&mut self, clauses: Vec<Clause>) -> Vec<Clause> {
+        clauses
+            .into_iter()
+            .filter_map(|mut clause| {
+                clause.body = self.prop(clause.body);
+                (*self.program.props.get(clause.body) != Prop::False).then_some(clause)
+            })
+            .collect()
+    }
+
+    /// Allocates `prop` as the simplified form of `original`, keeping its
+    /// source location.
+    fn alloc(&mut self, prop: Prop, original: PropId) -> PropId {
+        let id = self.program.props.alloc(prop);
+        if let Some(span) = self.program.prop_spans.get(&original).cloned() {
+            self.program.prop_spans.insert(id, span);
+        }
+        id
+    }
+
+    /// `prop` as the simplified form of `original`, reusing `original` when
+    /// nothing changed.
+    fn rebuild(&mut self, prop: Prop, original: PropId) -> PropId {
+        if *self.program.props.get(original) == prop {
+            return original;
+        }
+        self.alloc(prop, original)
+    }
+
+    fn is(&self, prop: PropId, expected: Prop) -> bool {
+        *self.program.props.get(prop) == expected
+    }
+
+    /// The simplified form of `id`. A `false` is only ever returned for a
+    /// whole prop, never left inside a connective.
+    fn prop(&mut self, id: PropId) -> PropId {
+        match self.program.props.get(id).clone() {
+            Prop::True | Prop::False | Prop::Eq(..) => id,
+            Prop::And(a, b) => {
+                let (a, b) = (self.prop(a), self.prop(b));
+                if self.is(a, Prop::False) || self.is(b, Prop::False) {
+                    self.alloc(Prop::False, id)
+                } else if self.is(a, Prop::True) {
+                    b
+                } else if self.is(b, Prop::True) {
+                    a
+                } else {
+                    self.rebuild(Prop::And(a, b), id)
+                }
+            }
+            Prop::Or(a, b) => {
+                let (a, b) = (self.prop(a), self.prop(b));
+                if self.is(a, Prop::False) {
+                    b
+                } else if self.is(b, Prop::False) {
+                    a
+                } else {
+                    self.rebuild(Prop::Or(a, b), id)
+                }
+            }
+            Prop::Not(p) => {
+                let p = self.prop(p);
+                if self.is(p, Prop::True) {
+                    self.alloc(Prop::False, id)
+                } else if self.is(p, Prop::False) {
+                    self.alloc(Prop::True, id)
+                } else {
+                    self.rebuild(Prop::Not(p), id)
+                }
+            }
+            Prop::Cond(c, a, b) => {
+                let (c, a, b) = (self.prop(c), self.prop(a), self.prop(b));
+                if self.is(c, Prop::True) {
+                    return a;
+                }
+                if self.is(c, Prop::False) {
+                    return b;
+                }
+                match (self.is(a, Prop::False), self.is(b, Prop::False)) {
+                    (true, true) => self.alloc(Prop::False, id),
+                    (true, false) => {
+                        let not = self.alloc(Prop::Not(c), id);
+                        self.alloc(Prop::And(not, b), id)
+                    }
+                    (false, true) => self.alloc(Prop::And(c, a), id),
+                    (false, false) => self.rebuild(Prop::Cond(c, a, b), id),
+                }
+            }
+            Prop::App { rel, args } => {
+                let info = self.program.rels.get(rel).clone();
+                if info.kind != RelKind::User {
+                    return self.fold(id, &info.kind, &info.name, &args);
+                }
+                match self.inline.get(&rel).cloned() {
+                    Some(clause) => {
+                        let body = self.instantiate(&clause, &args, id);
+                        self.prop(body)
+                    }
+                    None => id,
+                }
+            }
+        }
+    }
+
+    fn fold(&mut self, id: PropId, kind: &RelKind, name: &str, args: &[TermId]) -> PropId {
+        let values: Vec<Option<Value>> = args.iter().map(|&a| Value::from_term(self.program, a)).collect();
+        match evaluate_builtin(kind, name, &values) {
+            Some(Evaluated::Holds(true)) => self.alloc(Prop::True, id),
+            Some(Evaluated::Holds(false)) => self.alloc(Prop::False, id),
+            // Binding anything but a variable is left to the solver.
+            Some(Evaluated::Result(value)) if matches!(self.program.terms.get(args[2]), Term::Var(_)) => {
+                let result = value.to_term(self.program);
+                self.alloc(Prop::Eq(args[2], result), id)
+            }
+            _ => id,
+        }
+    }
+
+    /// The body of `clause` for a call with `args`, with fresh variables as
+    /// the solver would give it. A head argument that is a variable of its
+    /// own simply becomes the caller's argument; others are unified with it.
+    fn instantiate(&mut self, clause: &Clause, args: &[TermId], call: PropId) -> PropId {
+        let mut vars = HashMap::new();
+        let mut unify = Vec::new();
+        for (&head, &arg) in clause.head_args.iter().zip(args) {
+            match *self.program.terms.get(head) {
+                Term::Var(v) if !self.state_terms.contains(&head) && !vars.contains_key(&v) => {
+                    vars.insert(v, arg);
+                }
+                _ => {
+                    let head = self.rename_term(head, &mut vars);
+                    unify.push(self.alloc(Prop::Eq(arg, head), call));
+                }
+            }
+        }
+        let body = self.rename_prop(clause.body, &mut vars);
+        unify.into_iter().rev().fold(body, |body, eq| self.alloc(Prop::And(eq, body), call))
+    }
+
+    fn rename_term(&mut self, term: TermId, vars: &mut HashMap<VarId, TermId>) -> TermId {
+        match self.program.terms.get(term).clone() {
+            Term::Var(_) if self.state_terms.contains(&term) => term,
+            Term::Var(v) => {
+                if let Some(&renamed) = vars.get(&v) {
+                    return renamed;
+                }
+                let var = self.program.vars.get(v).clone();
+                let fresh = self.program.vars.alloc(var);
+                let renamed = self.program.terms.alloc(Term::Var(fresh));
+                vars.insert(v, renamed);
+                renamed
+            }
+            Term::App { sym, args } => {
+                let renamed: Vec<TermId> = args.iter().map(|&a| self.rename_term(a, vars)).collect();
+                if renamed == args {
+                    term
+                } else {
+                    self.program.terms.alloc(Term::App { sym, args: renamed })
+                }
+            }
+            Term::Atom(_) | Term::Int(_) | Term::Float(_) => term,
+        }
+    }
+
+    fn rename_prop(&mut self, prop: PropId, vars: &mut HashMap<VarId, TermId>) -> PropId {
+        let renamed = match self.program.props.get(prop).clone() {
+            Prop::True | Prop::False => return prop,
+            Prop::Eq(a, b) => Prop::Eq(self.rename_term(a, vars), self.rename_term(b, vars)),
+            Prop::And(a, b) => Prop::And(self.rename_prop(a, vars), self.rename_prop(b, vars)),
+            Prop::Or(a, b) => Prop::Or(self.rename_prop(a, vars), self.rename_prop(b, vars)),
+            Prop::Not(p) => Prop::Not(self.rename_prop(p, vars)),
+            Prop::Cond(c, a, b) => {
+                Prop::Cond(self.rename_prop(c, vars), self.rename_prop(a, vars), self.rename_prop(b, vars))
+            }
+            Prop::App { rel, args } => Prop::App {
+                rel,
+                args: args.iter().map(|&a| self.rename_term(a, vars)).collect(),
+            },
+        };
+        self.alloc(renamed, prop)
+    }
+}
@@ -0,0 +1,181 @@
+#[cfg(test)]
+mod tests {
+    use crate::frontend::{Frontend, FrontendOptions};
+    use crate::solver::ir::{Program, Prop, PropId};
+    use crate::solver::test_support::{load, load_with};
+
+    const UNOPTIMIZED: FrontendOptions = FrontendOptions { optimize: false, ..FrontendOptions::DEFAULT };
+
+    /// The names of the relations called anywhere in `prop`.
+    fn calls(program: &Program, prop: PropId) -> Vec<String> {
+        match program.props.get(prop) {
+            Prop::True | Prop::False | Prop::Eq(..) => vec![],
+            Prop::And(a, b) | Prop::Or(a, b) => [calls(program, *a), calls(program, *b)].concat(),
+            Prop::Not(p) => calls(program, *p),
+            Prop::Cond(c, a, b) => [calls(program, *c), calls(program, *a), calls(program, *b)].concat(),
+            Prop::App { rel, .. } => vec![program.rels.get(*rel).name.clone()],
+        }
+    }
+
+    fn stage_calls(frontend: &Frontend, stage: &str) -> Vec<String> {
+        let program = &frontend.program;
+        let stage = program.stages.iter().find(|s| s.name == stage).unwrap();
+        stage.state_constraints.iter().flat_map(|&c| calls(program, c)).collect()
+    }
+
+    fn rule_names(frontend: &Frontend) -> Vec<&str> {
+        frontend.program.global_rules.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    /// Runs `stages` for `frames` frames with and without the optimizer,
+    /// checking both see the same state after every stage.
+    fn assert_same_as_unoptimized(source: &str, stages: &[&str], frames: usize) {
+        let mut optimized = load(source);
+        let mut plain = load_with(source, UNOPTIMIZED);
+        for frame in 0..frames {
+            for stage in stages {
+                let optimized_result = optimized.run_stage_by_name(stage);
+                let plain_result = plain.run_stage_by_name(stage);
+                assert_eq!(optimized_result, plain_result, "stage {} in frame {}", stage, frame);
+                assert_eq!(optimized.state_vars(), plain.state_vars(), "stage {} in frame {}", stage, frame);
+            }
+        }
+    }
+
+    const STEPS: &str = r#"Relation boost(Int)
+
+Begin Facts:
+    StateVar X
+    StateVar Mode
+    X = 0
+    Mode = up
+    boost(0)
+End Facts
+
+Begin Global:
+    Rule Step:
+    int_mul(3, 2, Six) & int_add(X, Six, Y)
+    ---------------------------------------
+    step(X, Y)
+
+    Rule Never:
+    int_lt(2, 1)
+    ------------
+    never()
+
+    Rule CountDown:
+    int_gt(N, 0) & int_sub(N, 1, M) & countdown(M)
+    ----------------------------------------------
+    countdown(N)
+
+    Rule CountDownDone:
+    N = 0
+    -----------
+    countdown(N)
+
+    Rule Limit:
+    int_le(X, 12)
+    -------------
+    below_limit(X)
+End Global
+
+Begin Stage Advance:
+Begin State Constraints:
+    when below_limit(X): step(X, next(X))
+    otherwise: next(X) = 0
+
+    when int_lt(1, 2): preserve(Mode)
+    otherwise: next(Mode) = down
+End State Constraints
+End Stage Advance
+
+Begin Stage Boost:
+Begin State Constraints:
+    boost(B) & int_add(X, B, next(X))
+    preserve(Mode)
+End State Constraints
+End Stage Boost
+"#;
+
+    #[test]
+    fn test_ground_builtins_are_folded() {
+        let frontend = load(STEPS);
+        let calls = stage_calls(&frontend, "Advance");
+        // `int_mul(3, 2, Six)` is evaluated, as is the constant guard on
+        // `Mode`; only the calls on `X` remain.
+        assert_eq!(calls, vec!["int_le", "int_add"]);
+    }
+
+    #[test]
+    fn test_statically_false_rules_are_dropped() {
+        let optimized = load(STEPS);
+        let plain = load_with(STEPS, UNOPTIMIZED);
+        assert!(rule_names(&plain).contains(&"Never"));
+        assert!(!rule_names(&optimized).contains(&"Never"));
+    }
+
+    #[test]
+    fn test_only_single_non_recursive_rules_are_inlined() {
+        let frontend = load(STEPS);
+        assert!(stage_calls(&frontend, "Advance").iter().all(|c| c != "step" && c != "below_limit"));
+        // Declared relations may gain facts from the host.
+        assert!(stage_calls(&frontend, "Boost").contains(&"boost".to_string()));
+
+        let names = rule_names(&frontend);
+        assert!(names.contains(&"CountDown") && names.contains(&"CountDownDone"));
+        let countdown = frontend.program.global_rules.iter().find(|c| c.name == "CountDown").unwrap();
+        assert!(calls(&frontend.program, countdown.body).contains(&"countdown".to_string()));
+    }
+
+    #[test]
+    fn test_optimized_program_runs_the_same() {
+        assert_same_as_unoptimized(STEPS, &["Advance", "Boost"], 6);
+
+        let mut optimized = load(STEPS);
+        let mut plain = load_with(STEPS, UNOPTIMIZED);
+        for query in ["step(4, Y)", "never()", "below_limit(20)", "below_limit(3)"] {
+            assert_eq!(
+                optimized.query_batch(query, 5),
+                plain.query_batch(query, 5),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_facts_added_after_load_reach_inlined_relations() {
+        let mut optimized = load(STEPS);
+        let mut plain = load_with(STEPS, UNOPTIMIZED);
+        for frontend in [&mut optimized, &mut plain] {
+            frontend.add_fact("below_limit(18)").unwrap();
+        }
+        assert!(stage_calls(&optimized, "Advance").contains(&"below_limit".to_string()));
+
+        // X goes 0, 6, 12, 18 and then past the limit only because of the fact.
+        for frame in 0..4 {
+            assert_eq!(optimized.run_stage_by_name("Advance"), plain.run_stage_by_name("Advance"), "frame {}", frame);
+        }
+        assert_eq!(optimized.get_state_var("X").as_deref(), Some("24"));
+        assert_eq!(plain.get_state_var("X").as_deref(), Some("24"));
+    }
+
+    #[test]
+    fn test_runner_runs_the_same_when_optimized() {
+        let runner = std::fs::read_to_string("sample/runner.l").unwrap();
+        let mut optimized = load(&runner);
+        let mut plain = load_with(&runner, UNOPTIMIZED);
+        for frame in 0..12 {
+            for frontend in [&mut optimized, &mut plain] {
+                frontend.clear_facts_by_relation("key_pressed");
+                if frame % 5 == 0 {
+                    frontend.add_fact("key_pressed(space)").unwrap();
+                }
+                frontend.run_stage_by_name("Control").unwrap();
+                frontend.run_stage_by_name("Physics").unwrap();
+            }
+            assert_eq!(optimized.state_vars(), plain.state_vars(), "frame {}", frame);
+        }
+        assert!(stage_calls(&optimized, "Control").iter().all(|c| c != "shouldJump" && c != "collided"));
+    }
+}
@@ -0,0 +1,282 @@
+#[cfg(test)]
+mod tests {
+    use crate::frontend::Frontend;
+    use crate::solver::test_support::{self, sorted};
+    use crate::solver::{SearchStrategy, TerminationReason};
+
+    fn load(source: &str, strategy: SearchStrategy) -> Frontend {
+        let mut frontend = test_support::load(source);
+        frontend.strategy = strategy;
+        frontend
+    }
+
+    const DEEPENING: SearchStrategy = SearchStrategy::IterativeDeepening { max_depth: 64 };
+
+    /// Two infinite branches under `pick`, each with answers all the way down.
+    const NATS: &str = r#"Begin Facts:
+    nat(z)
+    tally(nil)
+End Facts
+
+Begin Global:
+    Rule Succ:
+    nat(X)
+    ---------
+    nat(s(X))
+
+    Rule More:
+    tally(X)
+    ---------------
+    tally(mark(X))
+
+    Rule PickNat:
+    nat(X)
+    -------
+    pick(X)
+
+    Rule PickTally:
+    tally(X)
+    --------
+    pick(X)
+End Global
+"#;
+
+    /// A finite tree whose answers sit at different depths.
+    const TREE: &str = r#"Begin Facts:
+    edge(a, b)
+    edge(a, c)
+    edge(b, d)
+    edge(c, e)
+    edge(d, f)
+End Facts
+
+Begin Global:
+    Rule Step:
+    edge(X, Y) & below(Y, Z)
+    ------------------------
+    below(X, Z)
+
+    Rule Edge:
+    edge(X, Y)
+    -----------
+    below(X, Y)
+End Global
+"#;
+
+    #[test]
+    fn test_finite_searches_agree() {
+        let expected = ["Z = b", "Z = c", "Z = d", "Z = e", "Z = f"];
+        for strategy in [SearchStrategy::BFS, SearchStrategy::DFS, SearchStrategy::Interleave, DEEPENING] {
+            let mut frontend = load(TREE, strategy);
+            let answers = frontend.query_batch("below(a, Z)", 100).unwrap();
+            assert_eq!(sorted(answers), expected, "strategy: {:?}", strategy);
+            assert_eq!(frontend.last_query_reason, Some(TerminationReason::SearchExhausted));
+        }
+    }
+
+    #[test]
+    fn test_deepening_finds_shallow_answers_first() {
+        let mut frontend = load(TREE, SearchStrategy::BFS);
+        let breadth_first = frontend.query_batch("below(a, Z)", 100).unwrap();
+        let mut frontend = load(TREE, DEEPENING);
+        let deepening = frontend.query_batch("below(a, Z)", 100).unwrap();
+        // The same layers as breadth-first, each in depth-first order.
+        for layer in [0..2, 2..4, 4..5] {
+            let expected = sorted(breadth_first[layer.clone()].to_vec());
+            assert_eq!(sorted(deepening[layer].to_vec()), expected);
+        }
+        assert_eq!(breadth_first, ["Z = b", "Z = c", "Z = d", "Z = e", "Z = f"]);
+
+        let mut frontend = load(TREE, SearchStrategy::DFS);
+        let depth_first = frontend.query_batch("below(a, Z)", 100).unwrap();
+        assert_ne!(depth_first, breadth_first);
+    }
+
+    #[test]
+    fn test_infinite_branches() {
+        let mut frontend = load(NATS, SearchStrategy::DFS);
+        assert_eq!(frontend.query_batch("nat(X)", 3).unwrap(), Vec::<String>::new());
+        assert_eq!(frontend.last_query_reason, Some(TerminationReason::MaxStepsReached));
+
+        for strategy in [SearchStrategy::BFS, SearchStrategy::Interleave, DEEPENING] {
+            let mut frontend = load(NATS, strategy);
+            let answers = frontend.query_batch("nat(X)", 3).unwrap();
+            assert_eq!(answers, ["X = z", "X = s(z)", "X = s(s(z))"], "strategy: {:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_interleaving_is_fair() {
+        let mut frontend = load(NATS, SearchStrategy::Interleave);
+        let answers = frontend.query_batch("pick(X)", 6).unwrap();
+        assert_eq!(answers.len(), 6);
+        assert!(answers.iter().any(|a| a.contains("s(")), "{:?}", answers);
+        assert!(answers.iter().any(|a| a.contains("mark(")), "{:?}", answers);
+    }
+
+    #[test]
+    fn test_deepening_stops_at_its_bound() {
+        let strategy = SearchStrategy::IterativeDeepening { max_depth: 12 };
+        let mut frontend = load(NATS, strategy);
+        let answers = frontend.query_batch("nat(X)", 100).unwrap();
+        assert!(!answers.is_empty(), "{:?}", answers);
+        assert_eq!(frontend.last_query_reason, Some(TerminationReason::SearchExhausted));
+
+        let mut frontend = load(NATS, SearchStrategy::IterativeDeepening { max_depth: 24 });
+        assert!(frontend.query_batch("nat(X)", 100).unwrap().len() > answers.len());
+    }
+
+    /// Roads with their lengths, and a way back round to `home`.
+    const ROADS: &str = r#"Cost road(_, _, D) = D
+
+Begin Facts:
+    road(home, park, 7)
+    road(home, shop, 2)
+    road(shop, park, 3)
+    road(park, lake, 1)
+    road(shop, lake, 9)
+    road(park, home, 4)
+End Facts
+
+Begin Global:
+    Rule Arrive:
+    road(X, Y, _)
+    ------------------
+    route(X, Y, [X, Y])
+
+    Rule Via:
+    road(X, Y, _) & route(Y, Z, P)
+    ------------------------------
+    route(X, Z, [X | P])
+End Global
+"#;
+
+    #[test]
+    fn test_best_first_finds_the_cheapest_first() {
+        let mut frontend = load(ROADS, SearchStrategy::BestFirst);
+        let answers = frontend.query_batch("route(home, lake, P)", 3).unwrap();
+        assert_eq!(
+            answers,
+            ["P = [home, shop, park, lake]", "P = [home, park, lake]", "P = [home, shop, lake]"]
+        );
+
+        let mut frontend = load(ROADS, SearchStrategy::BFS);
+        let answers = frontend.query_batch("route(home, lake, P)", 1).unwrap();
+        assert_ne!(answers, ["P = [home, shop, park, lake]"]);
+    }
+
+    #[test]
+    fn test_first_matching_cost_applies() {
+        let source = r#"Cost step(_, water) = 5
+Cost step(_, _) = 1
+
+Begin Facts:
+    step(a, water)
+    step(a, b)
+    step(b, c)
+End Facts
+
+Begin Global:
+    Rule Dry:
+    step(X, b) & step(b, Y)
+    -----------------------
+    two(X, Y)
+
+    Rule Wet:
+    step(X, water)
+    --------------
+    two(X, water)
+End Global
+"#;
+        let mut frontend = load(source, SearchStrategy::BestFirst);
+        assert_eq!(frontend.query_batch("two(a, Y)", 2).unwrap(), ["Y = c", "Y = water"]);
+        let mut frontend = load(source, SearchStrategy::BFS);
+        assert_eq!(frontend.query_batch("two(a, Y)", 2).unwrap(), ["Y = water", "Y = c"]);
+    }
+
+    #[test]
+    fn test_cost_declarations_are_checked() {
+        let source = r#"Cost int_add(_, _, _) = 1
+Cost missing(X) = X
+Cost road(X, Y) = 1
+Cost edge(X, Y) = Z
+Cost far(_) = 0.5
+
+Begin Facts:
+    road(a, b, 1)
+    edge(a, b)
+    far(a)
+End Facts
+
+Begin Global:
+End Global
+"#;
+        let mut frontend = Frontend::new();
+        let err = frontend.load(source).unwrap_err();
+        assert!(err.contains("`road` takes 3 arguments, but its cost pattern has 2"), "{}", err);
+        assert!(err.contains("the cost of `edge` must be a non-negative integer or a variable of its pattern, found `Z`"), "{}", err);
+        assert!(err.contains("the cost of `far` must be a non-negative integer or a variable of its pattern, found `0.5`"), "{}", err);
+        let messages: Vec<&str> = frontend.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert!(messages.contains(&"`int_add` is a built-in relation and can't have a cost"), "{:?}", messages);
+        assert!(messages.contains(&"`missing` has a cost but is never defined"), "{:?}", messages);
+    }
+
+    #[test]
+    fn test_only_best_first_charges_costs() {
+        let source = r#"Cost hop(_, _, C) = C
+
+Begin Facts:
+    hop(a, b, 2)
+    hop(a, c, _)
+    edge(a, d, 3)
+    edge(a, e, 1)
+End Facts
+
+Begin Global:
+    Rule Edge:
+    edge(X, Y, C)
+    -------------
+    hop(X, Y, C)
+End Global
+"#;
+        for strategy in [SearchStrategy::BFS, SearchStrategy::DFS, SearchStrategy::BestFirst] {
+            let mut frontend = load(source, strategy);
+            let answers = frontend.query_batch("hop(a, X, _)", 10).unwrap();
+            assert_eq!(sorted(answers), ["X = b", "X = c", "X = d", "X = e"], "strategy: {:?}", strategy);
+            assert_eq!(frontend.query_batch("hop(a, c, 3)", 10).unwrap(), ["yes"]);
+        }
+
+        // The rule binds the cost of `hop(a, d, C)` and `hop(a, e, C)` only
+        // after the call resolves; `hop(a, c, _)` never costs anything.
+        let mut frontend = load(source, SearchStrategy::BestFirst);
+        let answers = frontend.query_batch("hop(a, X, C)", 10).unwrap();
+        let hops: Vec<&str> = answers.iter().map(|a| a.split(", ").find(|b| b.starts_with("X")).unwrap()).collect();
+        assert_eq!(hops, ["X = c", "X = e", "X = b", "X = d"]);
+    }
+
+    #[test]
+    fn test_literal_costs_must_be_integers() {
+        let source = r#"Cost hop(_, _, C) = C
+
+Begin Facts:
+    hop(a, b, 2)
+    hop(a, c, 2.5)
+    hop(a, d, far)
+    edge(a, e)
+End Facts
+
+Begin Global:
+    Rule Edge:
+    edge(X, Y)
+    -----------------
+    hop(X, Y, s(far))
+End Global
+"#;
+        let mut frontend = Frontend::new();
+        let err = frontend.load(source).unwrap_err();
+        assert!(err.contains("the cost of `hop(a, c, 2.5)` must be a non-negative integer, found `2.5`"), "{}", err);
+        assert!(err.contains("the cost of `hop(a, d, far)` must be a non-negative integer, found `far`"), "{}", err);
+        assert!(err.contains("the cost of `hop(X, Y, s(far))` must be a non-negative integer, found `s(far)`"), "{}", err);
+        assert!(!err.contains("hop(a, b, 2)"), "{}", err);
+    }
+}
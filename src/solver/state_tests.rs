@@ -573,4 +573,126 @@ End Global
             },
         }
     }
+
+    #[test]
+    fn test_infix_arithmetic_in_state_constraints() {
+        let mut frontend = Frontend::new();
+        frontend.load(r#"Begin Facts:
+    StateVar Y
+    StateVar VY
+    StateVar Ticks
+    Y = 10.0
+    VY = 2.0
+    Ticks = 0
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Physics:
+Begin State Constraints:
+    next(VY) = VY - 0.5
+    next(Y) = Y + VY * 2
+    next(Ticks) = Ticks + 1
+End State Constraints
+End Stage Physics
+"#).unwrap();
+
+        frontend.run_stage_by_name("Physics").expect("Stage should succeed");
+
+        assert_eq!(frontend.get_state_var("Y").unwrap(), "14");
+        assert_eq!(frontend.get_state_var("VY").unwrap(), "1.5");
+        assert_eq!(frontend.get_state_var("Ticks").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_when_otherwise_and_preserve() {
+        let mut frontend = Frontend::new();
+        frontend.load(r#"Begin Facts:
+    StateVar Mode
+    StateVar Score
+    StateVar Lives
+    Mode = fast
+    Score = 0
+    Lives = 3
+End Facts
+
+Begin Global:
+End Global
+
+Begin Stage Tick:
+Begin State Constraints:
+    when Mode = slow: next(Score) = Score + 1
+    otherwise when Mode = fast:
+        next(Score) = Score + 10
+    otherwise: preserve(Score)
+    preserve(Mode, Lives)
+End State Constraints
+End Stage Tick
+"#).unwrap();
+
+        frontend.run_stage_by_name("Tick").expect("Stage should succeed");
+
+        assert_eq!(frontend.get_state_var("Score").unwrap(), "10");
+        assert_eq!(frontend.get_state_var("Mode").unwrap(), "fast");
+        assert_eq!(frontend.get_state_var("Lives").unwrap(), "3");
+    }
+
+    #[test]
+    fn test_initial_state_block() {
+        let mut frontend = Frontend::new();
+        frontend.load(r#"Begin Facts:
+End Facts
+
+Begin Initial State:
+    StateVars Health, Armor
+    Health = 10
+    Armor = Health - 4
+End Initial State
+
+Begin Global:
+End Global
+
+Begin Stage Hit:
+Begin State Constraints:
+    next(Health) = Health - Armor
+    preserve(Armor)
+End State Constraints
+End Stage Hit
+"#).unwrap();
+
+        assert_eq!(frontend.get_state_var("Health").unwrap(), "10");
+        assert_eq!(frontend.get_state_var("Armor").unwrap(), "6");
+        frontend.run_stage_by_name("Hit").expect("Stage should succeed");
+        assert_eq!(frontend.get_state_var("Health").unwrap(), "4");
+    }
+
+    #[test]
+    fn test_state_vars_need_exactly_one_initial_value() {
+        let mut frontend = Frontend::new();
+        let result = frontend.load(r#"Begin Facts:
+    StateVar Score
+    Score = 0
+    Score = 1
+End Facts
+
+Begin Initial State:
+    StateVars X, Y
+    Y = Z
+End Initial State
+
+Begin Global:
+End Global
+"#);
+
+        assert!(result.is_err());
+        let messages: Vec<&str> = frontend.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "state variable `Score` has more than one initial value; the first is at 3:5",
+            "state variable `X` has no initial value",
+            "state variable `Y` has no initial value",
+        ]);
+        assert_eq!(frontend.diagnostics[0].span.start.line, 4);
+        assert_eq!(frontend.diagnostics[1].span.start.line, 8);
+    }
 }
@@ -0,0 +1,130 @@
+#[cfg(test)]
+mod tests {
+    use crate::frontend::Frontend;
+    use crate::solver::codegen::generate_rust;
+    use crate::solver::test_support::{load, sorted};
+
+    /// A graph with a cycle through `a`, `b` and `c`, and a way out to `d`.
+    const GRAPH: &str = r#"Table reach
+Table path
+
+Begin Facts:
+    edge(a, b)
+    edge(b, c)
+    edge(c, a)
+    edge(c, d)
+End Facts
+
+Begin Global:
+    Rule ReachStep:
+    reach(X, Y) & edge(Y, Z)
+    ------------------------
+    reach(X, Z)
+
+    Rule ReachEdge:
+    edge(X, Y)
+    -----------
+    reach(X, Y)
+
+    Rule PathStep:
+    edge(X, Y) & path(Y, Z)
+    -----------------------
+    path(X, Z)
+
+    Rule PathEdge:
+    edge(X, Y)
+    ----------
+    path(X, Y)
+End Global
+"#;
+
+    #[test]
+    fn test_left_recursion_terminates() {
+        let mut frontend = load(GRAPH);
+        let answers = frontend.query_batch("reach(a, Z)", 100).unwrap();
+        assert_eq!(sorted(answers), vec!["Z = a", "Z = b", "Z = c", "Z = d"]);
+        assert_eq!(frontend.query_batch("reach(d, Z)", 100).unwrap(), Vec::<String>::new());
+        assert_eq!(frontend.query_batch("reach(b, d)", 100).unwrap(), vec!["yes"]);
+    }
+
+    #[test]
+    fn test_recursion_through_other_calls_terminates() {
+        // `path(a, Z)` needs `path(b, Z)`, which needs `path(c, Z)`, which
+        // needs `path(a, Z)` again while it is still being filled.
+        let mut frontend = load(GRAPH);
+        let answers = frontend.query_batch("path(a, Z)", 100).unwrap();
+        assert_eq!(sorted(answers), vec!["Z = a", "Z = b", "Z = c", "Z = d"]);
+        let answers = frontend.query_batch("path(X, d)", 100).unwrap();
+        assert_eq!(sorted(answers), vec!["X = a", "X = b", "X = c"]);
+    }
+
+    #[test]
+    fn test_each_answer_is_found_once() {
+        // Without the table, every way round the cycle is another answer.
+        let mut frontend = load(GRAPH);
+        let answers = frontend.query_batch("reach(X, Y)", 100).unwrap();
+        assert_eq!(answers.len(), 12);
+    }
+
+    #[test]
+    fn test_tabled_relations_in_stages() {
+        let source = GRAPH.replace(
+            "End Facts",
+            "    StateVar At\n    At = a\nEnd Facts",
+        ) + r#"
+Begin Stage Move:
+Begin State Constraints:
+    when reach(At, d): edge(At, next(At)) & reach(next(At), d)
+    otherwise: preserve(At)
+End State Constraints
+End Stage Move
+"#;
+        let mut frontend = load(&source);
+        for expected in ["b", "c", "a", "b"] {
+            frontend.run_stage_by_name("Move").unwrap();
+            assert_eq!(frontend.get_state_var("At").unwrap(), expected);
+        }
+        assert!(generate_rust(&frontend.program).is_err());
+    }
+
+    #[test]
+    fn test_table_declarations_are_checked() {
+        let source = "Table int_add\nTable missing\n\nBegin Facts:\nEnd Facts\n\nBegin Global:\nEnd Global\n";
+        let frontend = load(source);
+        let messages: Vec<&str> = frontend.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec!["`int_add` is a built-in relation and can't be tabled", "`missing` is tabled but never defined"]
+        );
+    }
+
+    #[test]
+    fn test_relations_reaching_constraints_are_not_tabled() {
+        let source = r#"Table positive
+Table above
+
+Begin Facts:
+End Facts
+
+Begin Global:
+    Rule Positive:
+    int_gt(X, 0)
+    ------------
+    positive(X)
+
+    Rule Above:
+    positive(X)
+    -----------
+    above(X)
+End Global
+"#;
+        // A table would keep one value of `X` rather than `X > 0`.
+        let mut frontend = Frontend::new();
+        let err = frontend.load(source).unwrap_err();
+        assert!(err.contains("`positive` can't be tabled, since its rules reach the constraint `int_gt`"), "{}", err);
+        assert!(err.contains("`above` can't be tabled, since its rules reach the constraint `int_gt`"), "{}", err);
+
+        let mut frontend = load(&source.replace("Table positive\nTable above\n", ""));
+        assert_eq!(frontend.query_batch("above(X) & int_gt(X, 5)", 1).unwrap().len(), 1);
+    }
+}
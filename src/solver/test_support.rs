@@ -0,0 +1,21 @@
+//! Helpers shared by the solver's test modules.
+
+use crate::frontend::{Frontend, FrontendOptions};
+
+/// A frontend with `source` loaded with the default options.
+pub(super) fn load(source: &str) -> Frontend {
+    load_with(source, FrontendOptions::DEFAULT)
+}
+
+/// A frontend with `source` loaded with `options`.
+pub(super) fn load_with(source: &str, options: FrontendOptions) -> Frontend {
+    let mut frontend = Frontend::with_options(options);
+    frontend.load(source).unwrap();
+    frontend
+}
+
+/// The answers in sorted order, for queries whose order isn't fixed.
+pub(super) fn sorted(mut answers: Vec<String>) -> Vec<String> {
+    answers.sort();
+    answers
+}
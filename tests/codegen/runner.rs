@@ -0,0 +1,165 @@
+// Generated by langame from a compiled program. Do not edit.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Atom {
+    No,
+    Space,
+    Yes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct State {
+    pub runner_y: f32,
+    pub runner_vy: f32,
+    pub obstacle_x: f32,
+    pub obstacle_vx: f32,
+    pub dead: Atom,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            runner_y: 0.0,
+            runner_vy: 0.0,
+            obstacle_x: 100.0,
+            obstacle_vx: 1.5,
+            dead: Atom::No,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawCommand {
+    pub name: &'static str,
+    pub args: Vec<f32>,
+}
+
+/// The relations the game provides, declared with `Relation`.
+pub trait Host {
+    fn key_pressed(&self, arg0: Atom) -> bool;
+}
+
+/// Runs stage `Control`, or returns `None` where the solver would report
+/// that its state constraints fail.
+pub fn run_control(state: &State, host: &impl Host) -> Option<State> {
+    let held = 'when1: {
+        if !should_jump(state, host) {
+            break 'when1 false;
+        }
+        true
+    };
+    let next_runner_vy = if held {
+        3.5
+    } else {
+        state.runner_vy
+    };
+    let held2 = 'when2: {
+        if !should_respawn_obstacle(state, host) {
+            break 'when2 false;
+        }
+        true
+    };
+    let (next_obstacle_x, next_obstacle_vx) = if held2 {
+        let next_obstacle_x = 100.0;
+        let next_obstacle_vx = state.obstacle_vx + 0.25;
+        (next_obstacle_x, next_obstacle_vx)
+    } else {
+        let next_obstacle_x = state.obstacle_x - state.obstacle_vx;
+        let next_obstacle_vx = state.obstacle_vx;
+        (next_obstacle_x, next_obstacle_vx)
+    };
+    let held3 = 'when3: {
+        if !collided(state, host) {
+            break 'when3 false;
+        }
+        true
+    };
+    let next_dead = if held3 {
+        Atom::Yes
+    } else {
+        state.dead
+    };
+    Some(State { runner_vy: next_runner_vy, obstacle_x: next_obstacle_x, obstacle_vx: next_obstacle_vx, dead: next_dead, ..*state })
+}
+
+/// Runs stage `Physics`, or returns `None` where the solver would report
+/// that its state constraints fail.
+pub fn run_physics(state: &State, _host: &impl Host) -> Option<State> {
+    let _ = state.runner_vy - 0.5;
+    let new_y = state.runner_y + state.runner_vy;
+    let held = 'when1: {
+        if new_y <= 0.0 {
+            break 'when1 false;
+        }
+        true
+    };
+    let (next_runner_vy, next_runner_y) = if held {
+        let next_runner_vy = state.runner_vy - 0.5;
+        let next_runner_y = state.runner_y + state.runner_vy;
+        (next_runner_vy, next_runner_y)
+    } else {
+        let next_runner_y = 0.0;
+        let next_runner_vy = 0.0;
+        (next_runner_vy, next_runner_y)
+    };
+    Some(State { runner_y: next_runner_y, runner_vy: next_runner_vy, ..*state })
+}
+
+/// The draw commands of every stage, in order.
+pub fn draw(state: &State, _host: &impl Host) -> Vec<DrawCommand> {
+    let mut draws = Vec::new();
+    'draw1: {
+        if state.dead != Atom::No {
+            break 'draw1;
+        }
+        draws.push(DrawCommand { name: "rect", args: vec![1.0, state.runner_y, 1.0, 1.0] });
+        draws.push(DrawCommand { name: "rect", args: vec![state.obstacle_x, 0.0, 1.0, 1.0] });
+    }
+    'draw2: {
+        if state.dead != Atom::Yes {
+            break 'draw2;
+        }
+        draws.push(DrawCommand { name: "rect", args: vec![40.0, 40.0, 1.0, 1.0] });
+        draws.push(DrawCommand { name: "rect", args: vec![60.0, 40.0, 1.0, 1.0] });
+        draws.push(DrawCommand { name: "rect", args: vec![46.0, 25.0, 9.0, 1.0] });
+        draws.push(DrawCommand { name: "rect", args: vec![45.0, 23.0, 1.0, 2.0] });
+        draws.push(DrawCommand { name: "rect", args: vec![55.0, 23.0, 1.0, 2.0] });
+    }
+    draws
+}
+
+fn should_jump(state: &State, host: &impl Host) -> bool {
+    'should_jump: {
+        if state.runner_y != 0.0 {
+            break 'should_jump;
+        }
+        if !host.key_pressed(Atom::Space) {
+            break 'should_jump;
+        }
+        return true;
+    }
+    false
+}
+
+fn should_respawn_obstacle(state: &State, _host: &impl Host) -> bool {
+    'respawn_obstacle: {
+        if state.obstacle_x >= -3.0 {
+            break 'respawn_obstacle;
+        }
+        return true;
+    }
+    false
+}
+
+fn collided(state: &State, _host: &impl Host) -> bool {
+    'game_over: {
+        if state.runner_y >= 1.0 {
+            break 'game_over;
+        }
+        if state.obstacle_x >= 2.0 {
+            break 'game_over;
+        }
+        return true;
+    }
+    false
+}